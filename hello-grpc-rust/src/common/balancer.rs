@@ -0,0 +1,356 @@
+//! Client-side load balancing across several backend addresses.
+//!
+//! `GRPC_HELLO_BACKEND` may list several targets (`host[:port],host[:port]`);
+//! [`BalancedChannel`] keeps one tonic `Channel` per target and picks one per
+//! call according to `GRPC_HELLO_LB_POLICY`:
+//!
+//! - `pick_first` (default): the first target in list order that is up.
+//! - `round_robin`: rotate over the targets that are up.
+//! - `p2c`: power of two choices, the less loaded of two random targets.
+//!
+//! A target whose connection fails is ejected and reconnected in the
+//! background with exponential backoff. It rejoins the rotation once a probe
+//! connects. While every target is ejected, calls fail fast with
+//! `UNAVAILABLE`, unless they carry [`WaitForReady`], in which case they wait
+//! for a target to come back.
+
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use log::{info, warn};
+use rand::Rng;
+use tokio::sync::Notify;
+use tonic::codegen::StdError;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Status};
+use tower::{Service, ServiceExt};
+
+use crate::common::framing::{Finish, ObservedBody};
+use crate::common::metrics::REGISTRY;
+use crate::common::tls::TlsConnector;
+
+const PROBE_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const PROBE_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+const ENDPOINT_UP: &str = "grpc_client_lb_endpoint_up";
+const EJECTIONS_TOTAL: &str = "grpc_client_lb_ejections_total";
+
+/// Load-balancing policy for [`BalancedChannel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LbPolicy {
+    PickFirst,
+    RoundRobin,
+    PowerOfTwoChoices,
+}
+
+impl LbPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pick_first" | "pick-first" => Some(LbPolicy::PickFirst),
+            "round_robin" | "round-robin" => Some(LbPolicy::RoundRobin),
+            "p2c" | "power_of_two_choices" => Some(LbPolicy::PowerOfTwoChoices),
+            _ => None,
+        }
+    }
+
+    /// Reads `GRPC_HELLO_LB_POLICY`, defaulting to pick_first like the
+    /// other gRPC implementations.
+    pub fn from_env() -> Self {
+        match env::var("GRPC_HELLO_LB_POLICY") {
+            Ok(value) => LbPolicy::parse(&value).unwrap_or_else(|| {
+                warn!("Unknown GRPC_HELLO_LB_POLICY={:?}, using pick_first", value);
+                LbPolicy::PickFirst
+            }),
+            Err(_) => LbPolicy::PickFirst,
+        }
+    }
+}
+
+/// Splits a comma-separated target list into `(host, port)` pairs; entries
+/// without a port use `default_port`. IPv6 literals must be bracketed.
+pub fn parse_targets(list: &str, default_port: &str) -> Vec<(String, String)> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.rsplit_once(':') {
+            Some((host, port))
+                if !port.is_empty()
+                    && port.bytes().all(|b| b.is_ascii_digit())
+                    && (!host.contains(':') || host.ends_with(']')) =>
+            {
+                (host.to_string(), port.to_string())
+            }
+            _ => (entry.to_string(), default_port.to_string()),
+        })
+        .collect()
+}
+
+/// Request extension asking [`BalancedChannel`] to wait for a reachable
+/// target instead of failing fast.
+#[derive(Debug, Clone, Copy)]
+pub struct WaitForReady;
+
+struct Target {
+    uri: String,
+    endpoint: Endpoint,
+    /// Makes the TLS connections when the target is secure.
+    tls: Option<TlsConnector>,
+    /// `None` while the target is ejected.
+    channel: Mutex<Option<Channel>>,
+    in_flight: AtomicUsize,
+    probing: AtomicBool,
+    /// Shared by every target of a channel; woken when one comes up.
+    ready: Arc<Notify>,
+}
+
+impl Target {
+    fn new(endpoint: Endpoint, tls: Option<TlsConnector>, ready: Arc<Notify>) -> Arc<Self> {
+        Arc::new(Target {
+            uri: endpoint.uri().to_string(),
+            endpoint,
+            tls,
+            channel: Mutex::new(None),
+            in_flight: AtomicUsize::new(0),
+            probing: AtomicBool::new(false),
+            ready,
+        })
+    }
+
+    fn channel(&self) -> Option<Channel> {
+        self.channel.lock().unwrap().clone()
+    }
+
+    /// Opens a channel to the target. The channel reconnects through the
+    /// same connector, which picks up reloaded TLS config.
+    async fn connect(&self) -> Result<Channel, tonic::transport::Error> {
+        match &self.tls {
+            Some(tls) => self.endpoint.connect_with_connector(tls.clone()).await,
+            None => self.endpoint.connect().await,
+        }
+    }
+
+    /// Every pooled client balances on its own, so the gauge counts the
+    /// channels that currently have this target in rotation.
+    fn set_channel(&self, channel: Option<Channel>) {
+        let up = channel.is_some();
+        let was_up = std::mem::replace(&mut *self.channel.lock().unwrap(), channel).is_some();
+        if up {
+            self.ready.notify_waiters();
+        }
+        let delta = match (was_up, up) {
+            (false, true) => 1.0,
+            (true, false) => -1.0,
+            _ => 0.0,
+        };
+        REGISTRY.add_gauge(
+            ENDPOINT_UP,
+            "Balanced channels that have a backend target in rotation.",
+            &[("target", &self.uri)],
+            delta,
+        );
+    }
+
+    /// Takes the target out of rotation and starts probing it.
+    fn eject(self: &Arc<Self>, reason: &dyn std::fmt::Display) {
+        if self.channel.lock().unwrap().is_none() {
+            return;
+        }
+        warn!("Ejecting backend {}: {}", self.uri, reason);
+        REGISTRY.inc_counter(
+            EJECTIONS_TOTAL,
+            "Backend targets taken out of rotation after a connection failure.",
+            &[("target", &self.uri)],
+        );
+        self.set_channel(None);
+        self.probe(PROBE_INITIAL_BACKOFF);
+    }
+
+    /// Reconnects in the background, first after `delay`, then with
+    /// exponential backoff.
+    fn probe(self: &Arc<Self>, delay: Duration) {
+        if self.probing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let target = self.clone();
+        tokio::spawn(async move {
+            let mut delay = delay;
+            loop {
+                tokio::time::sleep(delay).await;
+                match target.connect().await {
+                    Ok(channel) => {
+                        info!("Backend {} is reachable again", target.uri);
+                        target.set_channel(Some(channel));
+                        target.probing.store(false, Ordering::Release);
+                        return;
+                    }
+                    Err(e) => {
+                        delay =
+                            std::cmp::min(delay * 2, PROBE_MAX_BACKOFF).max(PROBE_INITIAL_BACKOFF);
+                        warn!(
+                            "Probe of backend {} failed: {}, retrying in {:?}",
+                            target.uri, e, delay
+                        );
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// A tower service spreading calls over several backend channels.
+#[derive(Clone)]
+pub struct BalancedChannel {
+    targets: Arc<Vec<Arc<Target>>>,
+    policy: LbPolicy,
+    next: Arc<AtomicUsize>,
+    ready: Arc<Notify>,
+}
+
+impl BalancedChannel {
+    /// Connects to every endpoint, over TLS through `tls` when given.
+    /// Unreachable endpoints start ejected; the first connection error is
+    /// returned only when none could be reached.
+    pub async fn connect(
+        endpoints: Vec<Endpoint>,
+        policy: LbPolicy,
+        tls: Option<TlsConnector>,
+    ) -> Result<Self, tonic::transport::Error> {
+        let ready = Arc::new(Notify::new());
+        let mut targets = Vec::with_capacity(endpoints.len());
+        let mut first_error = None;
+        for endpoint in endpoints {
+            let target = Target::new(endpoint, tls.clone(), ready.clone());
+            match target.connect().await {
+                Ok(channel) => target.set_channel(Some(channel)),
+                Err(e) => {
+                    warn!("Backend {} is unreachable: {}", target.uri, e);
+                    target.set_channel(None);
+                    first_error.get_or_insert(e);
+                }
+            }
+            targets.push(target);
+        }
+
+        if targets.iter().all(|target| target.channel().is_none())
+            && let Some(e) = first_error
+        {
+            return Err(e);
+        }
+        for target in targets.iter().filter(|target| target.channel().is_none()) {
+            target.probe(PROBE_INITIAL_BACKOFF);
+        }
+        Ok(BalancedChannel::new(targets, policy, ready))
+    }
+
+    /// Returns at once with every endpoint ejected and connects to them in
+    /// the background, so the channel can be built before the backends are
+    /// up. Calls fail fast (or wait, with [`WaitForReady`]) until one is.
+    pub fn lazy(endpoints: Vec<Endpoint>, policy: LbPolicy, tls: Option<TlsConnector>) -> Self {
+        let ready = Arc::new(Notify::new());
+        let targets: Vec<_> = endpoints
+            .into_iter()
+            .map(|endpoint| Target::new(endpoint, tls.clone(), ready.clone()))
+            .collect();
+        for target in &targets {
+            target.set_channel(None);
+            target.probe(Duration::ZERO);
+        }
+        BalancedChannel::new(targets, policy, ready)
+    }
+
+    fn new(targets: Vec<Arc<Target>>, policy: LbPolicy, ready: Arc<Notify>) -> Self {
+        BalancedChannel {
+            targets: Arc::new(targets),
+            policy,
+            next: Arc::new(AtomicUsize::new(0)),
+            ready,
+        }
+    }
+
+    /// Whether at least one target is connected.
+    pub fn is_ready(&self) -> bool {
+        self.targets.iter().any(|target| target.channel().is_some())
+    }
+
+    fn pick(&self) -> Option<(Arc<Target>, Channel)> {
+        let up: Vec<(&Arc<Target>, Channel)> = self
+            .targets
+            .iter()
+            .filter_map(|target| target.channel().map(|channel| (target, channel)))
+            .collect();
+        let (target, channel) = match (self.policy, up.len()) {
+            (_, 0) => return None,
+            (LbPolicy::PickFirst, _) | (_, 1) => up.into_iter().next()?,
+            (LbPolicy::RoundRobin, n) => {
+                let i = self.next.fetch_add(1, Ordering::Relaxed) % n;
+                up.into_iter().nth(i)?
+            }
+            (LbPolicy::PowerOfTwoChoices, n) => {
+                let mut rng = rand::rng();
+                let a = rng.random_range(0..n);
+                let b = (a + rng.random_range(1..n)) % n;
+                let load = |i: usize| up[i].0.in_flight.load(Ordering::Relaxed);
+                let i = if load(b) < load(a) { b } else { a };
+                up.into_iter().nth(i)?
+            }
+        };
+        Some((target.clone(), channel))
+    }
+}
+
+impl Service<http::Request<tonic::body::Body>> for BalancedChannel {
+    type Response = http::Response<tonic::body::Body>;
+    type Error = StdError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Readiness is checked on the picked channel inside the call
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::Body>) -> Self::Future {
+        let balancer = self.clone();
+        let wait_for_ready = request.extensions().get::<WaitForReady>().is_some();
+        Box::pin(async move {
+            let (target, channel) = loop {
+                // Registered before picking so a target coming up in between
+                // is not missed
+                let ready = balancer.ready.notified();
+                tokio::pin!(ready);
+                ready.as_mut().enable();
+                if let Some(picked) = balancer.pick() {
+                    break picked;
+                }
+                if !wait_for_ready {
+                    return Err(Status::unavailable("no backend target is reachable").into());
+                }
+                ready.await;
+            };
+            // In flight until the response body finishes, so long-lived
+            // streams weigh on p2c for as long as they run
+            target.in_flight.fetch_add(1, Ordering::Relaxed);
+            let counted = target.clone();
+            let finish = Finish::new(Box::new(move |_code: Code, _messages: u64| {
+                counted.in_flight.fetch_sub(1, Ordering::Relaxed);
+            }));
+
+            let result = channel.oneshot(request).await;
+            // Only transport failures come back as `Err`; gRPC errors are
+            // regular responses carrying a `grpc-status`
+            if let Err(e) = &result {
+                target.eject(e);
+            }
+            let result = result.map(|response| {
+                let (parts, body) = response.into_parts();
+                let body = ObservedBody::new(body, &parts.headers, finish);
+                http::Response::from_parts(parts, tonic::body::Body::new(body))
+            });
+            result.map_err(Into::into)
+        })
+    }
+}
@@ -0,0 +1,441 @@
+//! Client-side circuit breaker.
+//!
+//! Every channel built by `conn.rs` calls through the [`CircuitBreaker`] of
+//! its backend target, so the proxy's pooled connections to one backend
+//! share a breaker. The breaker is:
+//!
+//! - **closed** while calls go through normally. It opens after
+//!   `GRPC_HELLO_BREAKER_FAILURES` failed calls in a row (default 5), or
+//!   once at least `GRPC_HELLO_BREAKER_MIN_CALLS` calls (default 20) in a
+//!   `GRPC_HELLO_BREAKER_WINDOW_MS` window (default 10s) failed at a rate of
+//!   `GRPC_HELLO_BREAKER_ERROR_RATE` or more (default 0.5). Either trigger is
+//!   off when set to 0;
+//! - **open** for `GRPC_HELLO_BREAKER_OPEN_MS` (default 5s), failing every
+//!   call at once with `UNAVAILABLE`;
+//! - **half-open** after that, letting `GRPC_HELLO_BREAKER_HALF_OPEN_CALLS`
+//!   trial calls through (default 1). It closes when they all succeed and
+//!   opens again as soon as one fails.
+//!
+//! A call counts by its final status, after retries: `UNAVAILABLE`,
+//! `DEADLINE_EXCEEDED`, `INTERNAL` and `UNKNOWN` are failures, `CANCELLED`
+//! is ignored and any other code is a success. State changes are logged and
+//! exported as `grpc_client_circuit_state{target}` (0 closed, 1 open,
+//! 2 half-open), `grpc_client_circuit_transitions_total{target,from,to}` and
+//! `grpc_client_circuit_rejected_total{target}`.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use tonic::body::Body;
+use tonic::codegen::StdError;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+
+use crate::common::framing::{Finish, ObservedBody};
+use crate::common::metrics::REGISTRY;
+
+const STATE: &str = "grpc_client_circuit_state";
+const TRANSITIONS_TOTAL: &str = "grpc_client_circuit_transitions_total";
+const REJECTED_TOTAL: &str = "grpc_client_circuit_rejected_total";
+
+/// Breakers by backend target, shared by every channel to that target.
+static BREAKERS: Lazy<Mutex<HashMap<String, Arc<CircuitBreaker>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The breaker for `target`, created from [`BreakerConfig::from_env`] on
+/// first use.
+pub fn shared(target: &str) -> Arc<CircuitBreaker> {
+    BREAKERS
+        .lock()
+        .unwrap()
+        .entry(target.to_string())
+        .or_insert_with(|| Arc::new(CircuitBreaker::new(target, BreakerConfig::from_env())))
+        .clone()
+}
+
+/// Whether `status` is a breaker failing a call fast, rather than a
+/// failure seen on the wire.
+pub fn is_rejection(status: &Status) -> bool {
+    std::error::Error::source(status).is_some_and(|source| source.is::<CircuitOpen>())
+}
+
+/// When a [`CircuitBreaker`] trips and how it recovers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerConfig {
+    /// Failed calls in a row that open the breaker; 0 disables.
+    pub consecutive_failures: u32,
+    /// Failure rate in a window that opens the breaker; 0 disables.
+    pub error_rate: f64,
+    /// Calls a window needs before its failure rate counts.
+    pub min_calls: u32,
+    pub window: Duration,
+    /// How long the breaker stays open before letting trial calls through.
+    pub open_for: Duration,
+    pub half_open_calls: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            consecutive_failures: 5,
+            error_rate: 0.5,
+            min_calls: 20,
+            window: Duration::from_secs(10),
+            open_for: Duration::from_secs(5),
+            half_open_calls: 1,
+        }
+    }
+}
+
+impl BreakerConfig {
+    /// Reads the `GRPC_HELLO_BREAKER_*` variables over the defaults.
+    pub fn from_env() -> Self {
+        let defaults = BreakerConfig::default();
+        BreakerConfig {
+            consecutive_failures: env_or(
+                "GRPC_HELLO_BREAKER_FAILURES",
+                defaults.consecutive_failures,
+            ),
+            error_rate: env_or("GRPC_HELLO_BREAKER_ERROR_RATE", defaults.error_rate),
+            min_calls: env_or("GRPC_HELLO_BREAKER_MIN_CALLS", defaults.min_calls),
+            window: Duration::from_millis(env_or(
+                "GRPC_HELLO_BREAKER_WINDOW_MS",
+                defaults.window.as_millis() as u64,
+            )),
+            open_for: Duration::from_millis(env_or(
+                "GRPC_HELLO_BREAKER_OPEN_MS",
+                defaults.open_for.as_millis() as u64,
+            )),
+            half_open_calls: env_or(
+                "GRPC_HELLO_BREAKER_HALF_OPEN_CALLS",
+                defaults.half_open_calls,
+            )
+            .max(1),
+        }
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            warn!("Invalid {}={:?}, using the default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn label(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+
+    fn gauge(self) -> f64 {
+        match self {
+            BreakerState::Closed => 0.0,
+            BreakerState::Open => 1.0,
+            BreakerState::HalfOpen => 2.0,
+        }
+    }
+}
+
+/// The error behind the `UNAVAILABLE` status of a rejected call.
+#[derive(Debug)]
+struct CircuitOpen;
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("circuit breaker open")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+struct Inner {
+    state: BreakerState,
+    /// Bumped on every transition, so calls admitted in an earlier state
+    /// do not count towards the current one.
+    generation: u64,
+    consecutive_failures: u32,
+    window_start: Instant,
+    window_calls: u32,
+    window_failures: u32,
+    opened_at: Instant,
+    trials: u32,
+    trial_successes: u32,
+}
+
+/// A call let through by [`CircuitBreaker::admit`], to be passed back to
+/// [`CircuitBreaker::record`] with its outcome.
+#[derive(Debug, Clone, Copy)]
+pub struct Permit {
+    generation: u64,
+}
+
+pub struct CircuitBreaker {
+    target: String,
+    config: BreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(target: &str, config: BreakerConfig) -> Self {
+        let now = Instant::now();
+        let breaker = CircuitBreaker {
+            target: target.to_string(),
+            config,
+            inner: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                generation: 0,
+                consecutive_failures: 0,
+                window_start: now,
+                window_calls: 0,
+                window_failures: 0,
+                opened_at: now,
+                trials: 0,
+                trial_successes: 0,
+            }),
+        };
+        breaker.publish(BreakerState::Closed);
+        breaker
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Lets a call through, or fails it with `UNAVAILABLE` while the breaker
+    /// is open or its half-open trial calls are all taken.
+    pub fn admit(&self) -> Result<Permit, Status> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == BreakerState::Open && inner.opened_at.elapsed() >= self.config.open_for {
+            self.transition(&mut inner, BreakerState::HalfOpen);
+        }
+        let admitted = match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen if inner.trials < self.config.half_open_calls => {
+                inner.trials += 1;
+                true
+            }
+            BreakerState::HalfOpen => false,
+        };
+        if admitted {
+            return Ok(Permit {
+                generation: inner.generation,
+            });
+        }
+        drop(inner);
+        REGISTRY.inc_counter(
+            REJECTED_TOTAL,
+            "Calls failed fast because the target's circuit breaker was open.",
+            &[("target", &self.target)],
+        );
+        let mut status =
+            Status::unavailable(format!("circuit breaker for {} is open", self.target));
+        status.set_source(Arc::new(CircuitOpen));
+        Err(status)
+    }
+
+    /// Records the final status of an admitted call.
+    pub fn record(&self, permit: Permit, code: Code) {
+        let mut inner = self.inner.lock().unwrap();
+        if permit.generation != inner.generation {
+            return;
+        }
+        let failed = match code {
+            Code::Unavailable | Code::DeadlineExceeded | Code::Internal | Code::Unknown => true,
+            Code::Cancelled => {
+                // The trial told us nothing; let another call try
+                if inner.state == BreakerState::HalfOpen {
+                    inner.trials -= 1;
+                }
+                return;
+            }
+            _ => false,
+        };
+        match inner.state {
+            BreakerState::Closed => {
+                if inner.window_start.elapsed() >= self.config.window {
+                    inner.window_start = Instant::now();
+                    inner.window_calls = 0;
+                    inner.window_failures = 0;
+                }
+                inner.window_calls += 1;
+                if !failed {
+                    inner.consecutive_failures = 0;
+                    return;
+                }
+                inner.consecutive_failures += 1;
+                inner.window_failures += 1;
+                if self.trips(&inner) {
+                    warn!(
+                        "Circuit breaker for {} opened after {} failures in a row, {}/{} in the window",
+                        self.target,
+                        inner.consecutive_failures,
+                        inner.window_failures,
+                        inner.window_calls
+                    );
+                    self.transition(&mut inner, BreakerState::Open);
+                }
+            }
+            BreakerState::HalfOpen if failed => {
+                warn!(
+                    "Circuit breaker for {} reopened after a failed trial call ({:?})",
+                    self.target, code
+                );
+                self.transition(&mut inner, BreakerState::Open);
+            }
+            BreakerState::HalfOpen => {
+                inner.trial_successes += 1;
+                if inner.trial_successes >= self.config.half_open_calls {
+                    self.transition(&mut inner, BreakerState::Closed);
+                }
+            }
+            BreakerState::Open => {}
+        }
+    }
+
+    fn trips(&self, inner: &Inner) -> bool {
+        let consecutive = self.config.consecutive_failures > 0
+            && inner.consecutive_failures >= self.config.consecutive_failures;
+        let rate = self.config.error_rate > 0.0
+            && inner.window_calls >= self.config.min_calls.max(1)
+            && f64::from(inner.window_failures)
+                >= self.config.error_rate * f64::from(inner.window_calls);
+        consecutive || rate
+    }
+
+    fn transition(&self, inner: &mut Inner, to: BreakerState) {
+        let from = inner.state;
+        let now = Instant::now();
+        inner.state = to;
+        inner.generation += 1;
+        inner.consecutive_failures = 0;
+        inner.window_start = now;
+        inner.window_calls = 0;
+        inner.window_failures = 0;
+        inner.trials = 0;
+        inner.trial_successes = 0;
+        if to == BreakerState::Open {
+            inner.opened_at = now;
+        }
+        if to != BreakerState::Open {
+            info!(
+                "Circuit breaker for {} {} -> {}",
+                self.target,
+                from.label(),
+                to.label()
+            );
+        }
+        REGISTRY.inc_counter(
+            TRANSITIONS_TOTAL,
+            "Circuit breaker state changes, by target.",
+            &[
+                ("target", &self.target),
+                ("from", from.label()),
+                ("to", to.label()),
+            ],
+        );
+        self.publish(to);
+    }
+
+    fn publish(&self, state: BreakerState) {
+        REGISTRY.set_gauge(
+            STATE,
+            "Circuit breaker state by target: 0 closed, 1 open, 2 half-open.",
+            &[("target", &self.target)],
+            state.gauge(),
+        );
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreakerLayer {
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(breaker: Arc<CircuitBreaker>) -> Self {
+        CircuitBreakerLayer { breaker }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreakerService<S> {
+    inner: S,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl<S> Service<http::Request<Body>> for CircuitBreakerService<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>, Error = StdError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<ObservedBody<Body>>;
+    type Error = StdError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let breaker = self.breaker.clone();
+        Box::pin(async move {
+            let permit = breaker.admit()?;
+            let mut finish =
+                Finish::new(Box::new(move |code: Code, _| breaker.record(permit, code)));
+            match inner.call(request).await {
+                Ok(response) => {
+                    let (parts, body) = response.into_parts();
+                    let body = ObservedBody::new(body, &parts.headers, finish);
+                    Ok(http::Response::from_parts(parts, body))
+                }
+                Err(e) => {
+                    finish.set_code(
+                        e.downcast_ref::<Status>()
+                            .map_or(Code::Unavailable, Status::code),
+                    );
+                    Err(e)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,182 @@
+//! Expiry monitoring for the loaded TLS certificates.
+//!
+//! Every time the server or client loads a certificate (see tls.rs) its
+//! expiry is recorded in [`MONITOR`]. The seconds left are exported as the
+//! `tls_certificate_expiry_seconds` gauge, labelled with the certificate
+//! (`server` or `client`), and as the `certificates` detail of `GET /health`
+//! on the metrics port, and are refreshed by [`spawn_monitor`]. The server,
+//! and so a proxy with its client certificate, always runs both; the client
+//! binary does when `GRPC_HELLO_CLIENT_METRICS_PORT` is set.
+//!
+//! A warning is logged when a certificate has fewer days left than one of
+//! the thresholds in `GRPC_HELLO_CERT_EXPIRY_WARN_DAYS` (default `30,7,1`),
+//! once per threshold, and an error once it has expired. Loading a new
+//! certificate starts over.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use once_cell::sync::Lazy;
+use serde_json::{Value, json};
+use tokio::task::JoinHandle;
+
+use crate::common::metrics::REGISTRY;
+
+const EXPIRY_SECONDS: &str = "tls_certificate_expiry_seconds";
+const EXPIRY_HELP: &str = "Seconds until the loaded TLS certificate expires.";
+const DEFAULT_WARN_DAYS: &[u32] = &[30, 7, 1];
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The monitor of this process, with the thresholds from
+/// `GRPC_HELLO_CERT_EXPIRY_WARN_DAYS`.
+pub static MONITOR: Lazy<ExpiryMonitor> = Lazy::new(|| {
+    let days = env::var("GRPC_HELLO_CERT_EXPIRY_WARN_DAYS").unwrap_or_default();
+    match parse_warn_days(&days) {
+        Ok(days) => ExpiryMonitor::new(days),
+        Err(e) => {
+            warn!("{}, using {:?}", e, DEFAULT_WARN_DAYS);
+            ExpiryMonitor::new(DEFAULT_WARN_DAYS.to_vec())
+        }
+    }
+});
+
+/// Parses comma-separated warning thresholds in days; empty means the
+/// defaults.
+pub fn parse_warn_days(value: &str) -> Result<Vec<u32>, String> {
+    if value.trim().is_empty() {
+        return Ok(DEFAULT_WARN_DAYS.to_vec());
+    }
+    value
+        .split(',')
+        .map(|days| {
+            days.trim().parse().map_err(|_| {
+                format!(
+                    "GRPC_HELLO_CERT_EXPIRY_WARN_DAYS: invalid number of days {:?}",
+                    days.trim()
+                )
+            })
+        })
+        .collect()
+}
+
+struct Tracked {
+    not_after: DateTime<Utc>,
+    /// The lowest threshold already warned about.
+    warned: Option<u32>,
+    expired: bool,
+}
+
+/// Expiry of the loaded certificates, by name.
+pub struct ExpiryMonitor {
+    /// Highest first.
+    warn_days: Vec<u32>,
+    certs: Mutex<BTreeMap<&'static str, Tracked>>,
+}
+
+impl ExpiryMonitor {
+    pub fn new(mut warn_days: Vec<u32>) -> Self {
+        warn_days.sort_unstable_by(|a, b| b.cmp(a));
+        warn_days.dedup();
+        ExpiryMonitor {
+            warn_days,
+            certs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records a newly loaded certificate and checks it at once.
+    pub fn record(&self, what: &'static str, not_after: DateTime<Utc>) {
+        self.certs.lock().unwrap().insert(
+            what,
+            Tracked {
+                not_after,
+                warned: None,
+                expired: false,
+            },
+        );
+        self.check(Utc::now());
+    }
+
+    /// Updates the gauges and logs certificates that crossed a threshold
+    /// since the last check. Returns them with the threshold crossed (0 for
+    /// expired).
+    pub fn check(&self, now: DateTime<Utc>) -> Vec<(&'static str, u32)> {
+        let mut crossed = Vec::new();
+        let mut certs = self.certs.lock().unwrap();
+        for (what, cert) in certs.iter_mut() {
+            let left = cert.not_after - now;
+            REGISTRY.set_gauge(
+                EXPIRY_SECONDS,
+                EXPIRY_HELP,
+                &[("certificate", what)],
+                left.num_seconds() as f64,
+            );
+            if left.num_seconds() <= 0 {
+                if !cert.expired {
+                    cert.expired = true;
+                    error!("The {} certificate expired at {}", what, cert.not_after);
+                    crossed.push((*what, 0));
+                }
+                continue;
+            }
+            let threshold = self
+                .warn_days
+                .iter()
+                .copied()
+                .filter(|&days| left.num_seconds() < i64::from(days) * 86_400)
+                .min();
+            if let Some(days) = threshold
+                && cert.warned.is_none_or(|warned| days < warned)
+            {
+                cert.warned = Some(days);
+                warn!(
+                    "The {} certificate expires in less than {} days, at {}",
+                    what, days, cert.not_after
+                );
+                crossed.push((*what, days));
+            }
+        }
+        crossed
+    }
+
+    /// The `certificates` detail of the health endpoint.
+    pub fn detail(&self, now: DateTime<Utc>) -> Value {
+        let first_warning = self.warn_days.first().copied().unwrap_or(0);
+        let certs = self.certs.lock().unwrap();
+        let detail: serde_json::Map<String, Value> = certs
+            .iter()
+            .map(|(what, cert)| {
+                let left = (cert.not_after - now).num_seconds();
+                let status = if left <= 0 {
+                    "expired"
+                } else if left < i64::from(first_warning) * 86_400 {
+                    "expiring"
+                } else {
+                    "ok"
+                };
+                let detail = json!({
+                    "not_after": cert.not_after.to_rfc3339(),
+                    "expires_in_seconds": left,
+                    "status": status,
+                });
+                (what.to_string(), detail)
+            })
+            .collect();
+        Value::Object(detail)
+    }
+}
+
+/// Checks [`MONITOR`] every minute, so the gauges stay current and the
+/// thresholds are noticed while the process runs.
+pub fn spawn_monitor() -> JoinHandle<()> {
+    tokio::spawn(async {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            MONITOR.check(Utc::now());
+        }
+    })
+}
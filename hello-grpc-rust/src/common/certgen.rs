@@ -0,0 +1,242 @@
+//! Development certificates without openssl.
+//!
+//! `proto-server gen-certs` (or `proto-client gen-certs`) creates a throwaway
+//! CA and a server and client certificate signed by it, in the layout the
+//! binaries read from `CERT_BASE_PATH`:
+//!
+//! ```text
+//! <out>/ca.crt, ca.key
+//! <out>/server_certs/cert.pem, full_chain.pem, private.key, private.pkcs8.key, myssl_root.cer
+//! <out>/client_certs/cert.pem, full_chain.pem, private.key, private.pkcs8.key, myssl_root.cer
+//! ```
+//!
+//! The server certificate names `hello.grpc.io`, `localhost`, `127.0.0.1` and
+//! `::1` plus any `--san`; the client certificate has the subject
+//! `O=HelloGRPC, OU=Client, CN=hello.grpc.client`. Keys are ECDSA P-256.
+//! These certificates are for tests and local runs only.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Duration, Utc};
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyPair, KeyUsagePurpose, date_time_ymd,
+};
+
+use crate::common::{tls, trans};
+
+/// The name clients check the server certificate against.
+pub const SERVER_NAME: &str = tls::DEFAULT_SERVER_NAME;
+const DEFAULT_SANS: &[&str] = &[SERVER_NAME, "localhost", "127.0.0.1", "::1"];
+const DEFAULT_DAYS: u32 = 365;
+
+const USAGE: &str = "\
+Usage: gen-certs [--out DIR] [--san NAME]... [--days N]
+
+Creates a throwaway CA plus server and client certificates.
+
+  --out DIR    Where to write them (default: CERT_BASE_PATH or /var/hello_grpc)
+  --san NAME   Extra server hostname or IP address; repeat or comma-separate
+  --days N     Validity in days (default: 365)";
+
+/// What `gen-certs` writes.
+#[derive(Debug, Clone)]
+pub struct CertOptions {
+    pub out: PathBuf,
+    pub extra_sans: Vec<String>,
+    pub days: u32,
+}
+
+impl Default for CertOptions {
+    fn default() -> Self {
+        CertOptions {
+            out: trans::cert_base_path(),
+            extra_sans: Vec::new(),
+            days: DEFAULT_DAYS,
+        }
+    }
+}
+
+impl CertOptions {
+    /// Parses the arguments after `gen-certs`. `Ok(None)` means `--help`.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+        let mut options = CertOptions::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs a value\n\n{}", flag, USAGE))
+            };
+            match flag.as_str() {
+                "--out" => options.out = PathBuf::from(value()?),
+                "--san" => options.extra_sans.extend(
+                    value()?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from),
+                ),
+                "--days" => {
+                    let days = value()?;
+                    options.days = days
+                        .parse()
+                        .ok()
+                        .filter(|&days| days > 0)
+                        .ok_or_else(|| format!("--days: invalid number of days {:?}", days))?;
+                }
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(format!("unknown argument {:?}\n\n{}", flag, USAGE)),
+            }
+        }
+        Ok(Some(options))
+    }
+}
+
+/// Runs `gen-certs` with the arguments that follow it.
+pub fn cli<I: IntoIterator<Item = String>>(args: I) -> Result<(), String> {
+    let Some(options) = CertOptions::parse(args)? else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    generate(&options)?;
+    println!(
+        "Wrote a CA and server/client certificates valid for {} days to {}",
+        options.days,
+        options.out.display()
+    );
+    println!("Use them with CERT_BASE_PATH={}", options.out.display());
+    Ok(())
+}
+
+fn validity(params: &mut CertificateParams, days: u32) {
+    let from = Utc::now() - Duration::days(1);
+    let until = Utc::now() + Duration::days(i64::from(days));
+    params.not_before = date_time_ymd(from.year(), from.month() as u8, from.day() as u8);
+    params.not_after = date_time_ymd(until.year(), until.month() as u8, until.day() as u8);
+}
+
+fn subject(unit: &str, common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::OrganizationName, "HelloGRPC");
+    name.push(DnType::OrganizationalUnitName, unit);
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+fn leaf_params(
+    sans: Vec<String>,
+    unit: &str,
+    common_name: &str,
+    usage: ExtendedKeyUsagePurpose,
+    days: u32,
+) -> Result<CertificateParams, String> {
+    let mut params = CertificateParams::new(sans).map_err(|e| e.to_string())?;
+    params.distinguished_name = subject(unit, common_name);
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![usage];
+    params.use_authority_key_identifier_extension = true;
+    validity(&mut params, days);
+    Ok(params)
+}
+
+fn write(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Private keys are readable by the owner only.
+fn write_key(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+fn write_identity(dir: &Path, cert: &str, key: &str, ca: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    write(&dir.join("cert.pem"), cert)?;
+    write(&dir.join("full_chain.pem"), &format!("{}{}", cert, ca))?;
+    write_key(&dir.join("private.key"), key)?;
+    write_key(&dir.join("private.pkcs8.key"), key)?;
+    write(&dir.join("myssl_root.cer"), ca)
+}
+
+/// Creates the CA and both certificates under `options.out`, replacing any
+/// that are there.
+pub fn generate(options: &CertOptions) -> Result<(), String> {
+    let failed = |e: rcgen::Error| format!("Failed to generate certificates: {}", e);
+
+    let ca_key = KeyPair::generate().map_err(failed)?;
+    let mut ca_params = CertificateParams::default();
+    ca_params.distinguished_name = subject("CA", "Hello gRPC Development CA");
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    ca_params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    validity(&mut ca_params, options.days);
+    let ca_pem = ca_params.self_signed(&ca_key).map_err(failed)?.pem();
+    let issuer = Issuer::new(ca_params, &ca_key);
+
+    let mut sans: Vec<String> = DEFAULT_SANS.iter().map(|s| s.to_string()).collect();
+    for san in &options.extra_sans {
+        if !sans.contains(san) {
+            sans.push(san.clone());
+        }
+    }
+    let server_key = KeyPair::generate().map_err(failed)?;
+    let server = leaf_params(
+        sans,
+        "Server",
+        SERVER_NAME,
+        ExtendedKeyUsagePurpose::ServerAuth,
+        options.days,
+    )?
+    .signed_by(&server_key, &issuer)
+    .map_err(failed)?;
+
+    let client_key = KeyPair::generate().map_err(failed)?;
+    let client = leaf_params(
+        Vec::new(),
+        "Client",
+        "hello.grpc.client",
+        ExtendedKeyUsagePurpose::ClientAuth,
+        options.days,
+    )?
+    .signed_by(&client_key, &issuer)
+    .map_err(failed)?;
+
+    let out = &options.out;
+    let written = (|| -> io::Result<()> {
+        fs::create_dir_all(out)?;
+        write(&out.join("ca.crt"), &ca_pem)?;
+        write_key(&out.join("ca.key"), &ca_key.serialize_pem())?;
+        write_identity(
+            &out.join("server_certs"),
+            &server.pem(),
+            &server_key.serialize_pem(),
+            &ca_pem,
+        )?;
+        write_identity(
+            &out.join("client_certs"),
+            &client.pem(),
+            &client_key.serialize_pem(),
+            &ca_pem,
+        )
+    })();
+    written.map_err(|e| format!("Failed to write certificates to {:?}: {}", out, e))
+}
@@ -0,0 +1,116 @@
+//! Client-side RPC metrics.
+//!
+//! [`ClientMetricsLayer`] sits between `LandingServiceClient` and the tonic
+//! `Channel`, so every call made through a client from
+//! [`ClientBuilder`](crate::common::conn::ClientBuilder) is measured: the
+//! proxy's backend calls as well as `proto-client`'s own. It records
+//! `grpc_client_*` series into the shared Prometheus registry and the OTel
+//! `rpc.client.*` instruments.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tonic::Code;
+use tower::{Layer, Service};
+
+use crate::common::framing::{CountingBody, Finish, ObservedBody, split_grpc_path};
+use crate::common::metrics::{REGISTRY, code_name};
+use crate::otel;
+
+const HANDLED_TOTAL: &str = "grpc_client_handled_total";
+const HANDLING_SECONDS: &str = "grpc_client_handling_seconds";
+
+#[derive(Clone, Default)]
+pub struct ClientMetricsLayer;
+
+impl<S> Layer<S> for ClientMetricsLayer {
+    type Service = ClientMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientMetricsService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClientMetricsService<S> {
+    inner: S,
+}
+
+impl<S, ResBody> Service<http::Request<tonic::body::Body>> for ClientMetricsService<S>
+where
+    S: Service<http::Request<tonic::body::Body>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<ObservedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::Body>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let (service, method) = split_grpc_path(request.uri().path());
+        let requests = Arc::new(AtomicU64::new(0));
+        let request =
+            request.map(|body| tonic::body::Body::new(CountingBody::new(body, requests.clone())));
+
+        let started = Instant::now();
+        let mut finish = Finish::new(Box::new(move |code: Code, responses: u64| {
+            record_handled(&service, &method, code, started);
+            otel::record_client_call(
+                &service,
+                &method,
+                code,
+                started.elapsed(),
+                requests.load(Ordering::Relaxed),
+                responses,
+            );
+        }));
+
+        Box::pin(async move {
+            match inner.call(request).await {
+                Ok(response) => {
+                    let (parts, body) = response.into_parts();
+                    let body = ObservedBody::new(body, &parts.headers, finish);
+                    Ok(http::Response::from_parts(parts, body))
+                }
+                Err(e) => {
+                    // Transport failures surface to the caller as UNAVAILABLE
+                    finish.set_code(Code::Unavailable);
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+fn record_handled(service: &str, method: &str, code: Code, started: Instant) {
+    let code = code_name(code);
+    REGISTRY.observe(
+        HANDLING_SECONDS,
+        "Histogram of response latency of RPCs made by the client, in seconds.",
+        &[("grpc_service", service), ("grpc_method", method)],
+        started.elapsed().as_secs_f64(),
+    );
+    REGISTRY.inc_counter(
+        HANDLED_TOTAL,
+        "Total number of RPCs completed by the client, regardless of success or failure.",
+        &[
+            ("grpc_service", service),
+            ("grpc_method", method),
+            ("grpc_code", code),
+        ],
+    );
+}
@@ -2,77 +2,56 @@
 #![allow(unused_variables)]
 
 use std::env;
-use std::fs;
-use std::future::Future;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 
 use log::{error, info, warn};
+use once_cell::sync::OnceCell;
 use tonic::codec::CompressionEncoding;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
-use tonic::{Code, Status};
+use tonic::transport::Endpoint;
+use tower::Layer;
 
+use crate::common::balancer::{BalancedChannel, LbPolicy, parse_targets};
+use crate::common::breaker::{self, CircuitBreakerLayer, CircuitBreakerService};
+use crate::common::cert_expiry::MONITOR;
+use crate::common::client_metrics::{ClientMetricsLayer, ClientMetricsService};
+use crate::common::credentials::{BearerTokenLayer, BearerTokenService};
 use crate::common::etcd;
 use crate::common::landing::landing_service_client::LandingServiceClient;
-use crate::common::trans;
-
-const DOMAIN_NAME: &str = "hello.grpc.io";
+use crate::common::propagation::{ClientTraceLayer, ClientTraceService};
+use crate::common::retry::{RetryLayer, RetryService};
+use crate::common::service_config::{self, ServiceConfig};
+use crate::common::tls::{ClientTlsSettings, Reloadable, TlsConnector};
 pub const CONFIG_PATH: &str = "config/log4rs.yml";
 
-/// A6 client-retry parameters for hello.LandingService, mirroring the
-/// grpc.service_config retryPolicy used by the other language clients:
-/// https://github.com/grpc/proposal/blob/master/A6-client-retries.md
-///
-/// tonic (0.14.x) has no built-in service-config/retryPolicy support, so
-/// this is applied at the application level around unary calls instead of
-/// via channel configuration.
-pub const RETRY_MAX_ATTEMPTS: u32 = 4;
-pub const RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
-pub const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(1);
-pub const RETRY_BACKOFF_MULTIPLIER: f64 = 2.0;
-
-/// Returns true when `code` matches the retryableStatusCodes used by the
-/// shared A6 retry policy (`UNAVAILABLE` only).
-fn is_retryable(code: Code) -> bool {
-    code == Code::Unavailable
-}
-
-/// Runs `attempt` up to [`RETRY_MAX_ATTEMPTS`] times, retrying only on
-/// `UNAVAILABLE` with exponential backoff (initial 0.1s, multiplier 2.0,
-/// capped at 1s) — the same policy encoded in the other languages'
-/// `retryPolicy` service config for `hello.LandingService`.
-pub async fn call_with_retry<T, F, Fut>(method: &str, mut attempt: F) -> Result<T, Status>
-where
-    F: FnMut() -> Fut,
-    Fut: Future<Output = Result<T, Status>>,
-{
-    let mut backoff = RETRY_INITIAL_BACKOFF;
-    let mut last_err = None;
-    for attempt_no in 1..=RETRY_MAX_ATTEMPTS {
-        match attempt().await {
-            Ok(value) => return Ok(value),
-            Err(status) => {
-                if attempt_no == RETRY_MAX_ATTEMPTS || !is_retryable(status.code()) {
-                    last_err = Some(status);
-                    break;
-                }
-                warn!(
-                    "{} attempt {}/{} failed with {:?}, retrying in {:?}",
-                    method,
-                    attempt_no,
-                    RETRY_MAX_ATTEMPTS,
-                    status.code(),
-                    backoff
-                );
-                tokio::time::sleep(backoff).await;
-                backoff = std::cmp::min(
-                    Duration::from_secs_f64(backoff.as_secs_f64() * RETRY_BACKOFF_MULTIPLIER),
-                    RETRY_MAX_BACKOFF,
-                );
-                last_err = Some(status);
-            }
-        }
-    }
-    Err(last_err.unwrap_or_else(|| Status::unavailable("retry loop exhausted")))
+/// Transport stack under every client built by [`ClientBuilder`]: the
+/// load-balanced backend channels, the service config (retries, timeouts,
+/// waitForReady) applied per call, the target's circuit breaker, the client
+/// metrics layer, the client trace layer that injects `traceparent` and,
+/// outermost, the bearer token layer that sets `authorization`.
+pub type ClientChannel = BearerTokenService<
+    ClientTraceService<ClientMetricsService<CircuitBreakerService<RetryService<BalancedChannel>>>>,
+>;
+
+/// The `LandingService` client type used by `proto-client` and the proxy.
+pub type LandingClient = LandingServiceClient<ClientChannel>;
+
+/// Wraps a connected channel in the client layers and enables gzip
+/// compression for outgoing/incoming messages. `target` names the backend
+/// whose circuit breaker the client shares.
+fn landing_client(
+    channel: BalancedChannel,
+    config: Arc<ServiceConfig>,
+    target: &str,
+) -> LandingClient {
+    let channel = RetryLayer::new(config).layer(channel);
+    let channel = CircuitBreakerLayer::new(breaker::shared(target)).layer(channel);
+    let channel = ClientTraceLayer.layer(ClientMetricsLayer.layer(channel));
+    LandingServiceClient::new(BearerTokenLayer::from_env().layer(channel))
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip)
 }
 
 /// HTTP/2 keepalive settings shared by secure and insecure channels.
@@ -83,108 +62,265 @@ fn with_keepalive(endpoint: Endpoint) -> Endpoint {
         .keep_alive_while_idle(true)
 }
 
-pub async fn build_client() -> LandingServiceClient<Channel> {
-    // Check etcd service discovery first
-    if etcd::is_etcd_discovery() {
-        match etcd::resolve_from_etcd().await {
-            Ok(address) => {
-                info!("Resolved service via etcd: {}", address);
-                let endpoint = Endpoint::from_shared(address.clone())
-                    .unwrap_or_else(|error| panic!("Invalid etcd-resolved address: {:?}", error));
-                let channel = with_keepalive(endpoint)
-                    .connect()
-                    .await
-                    .unwrap_or_else(|error| {
-                        panic!("Failed to connect to etcd-resolved address: {:?}", error)
-                    });
-                return LandingServiceClient::new(channel)
-                    .send_compressed(CompressionEncoding::Gzip)
-                    .accept_compressed(CompressionEncoding::Gzip);
-            }
-            Err(e) => {
-                error!("etcd discovery enabled but resolution failed: {}", e);
-                panic!(
-                    "GRPC_HELLO_DISCOVERY=etcd but no service instance found: {}",
-                    e
-                );
+/// Why a client could not be built.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The service config is not valid.
+    ServiceConfig(String),
+    /// `GRPC_HELLO_DISCOVERY=etcd` is set but no instance was resolved.
+    Discovery(String),
+    /// `GRPC_HELLO_BACKEND` lists no target.
+    NoTarget,
+    /// A target does not make a valid URI.
+    InvalidAddress {
+        address: String,
+        source: tonic::transport::Error,
+    },
+    /// `GRPC_HELLO_SECURE=Y` but the certificates or key could not be
+    /// loaded.
+    Tls(String),
+    /// No target could be connected to.
+    Connect(tonic::transport::Error),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::ServiceConfig(e) => write!(f, "invalid service config: {}", e),
+            ConnectError::Discovery(e) => write!(
+                f,
+                "GRPC_HELLO_DISCOVERY=etcd but no service instance found: {}",
+                e
+            ),
+            ConnectError::NoTarget => f.write_str("no backend target configured"),
+            ConnectError::InvalidAddress { address, source } => {
+                write!(f, "invalid gRPC server address {}: {}", address, source)
             }
+            ConnectError::Tls(e) => write!(f, "GRPC_HELLO_SECURE=Y but {}", e),
+            ConnectError::Connect(e) => write!(f, "failed to connect to gRPC server: {}", e),
         }
     }
+}
 
-    let is_tls = env::var("GRPC_HELLO_SECURE").is_ok_and(|v| v == "Y");
+impl Error for ConnectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConnectError::InvalidAddress { source, .. } => Some(source),
+            ConnectError::Connect(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-    if is_tls {
-        let address = format!("https://{}:{}", grpc_backend_host(), grpc_backend_port());
+/// The client plus the balanced channel under it, which tells whether a
+/// backend is connected.
+pub struct Backend {
+    pub client: LandingClient,
+    pub channel: BalancedChannel,
+}
 
-        // Load certificates at runtime. Resolution order: CERT_BASE_PATH env
-        // var, then the platform default. Fail fast when TLS is requested but
-        // the certificates cannot be read.
-        let cert = fs::read(trans::client_cert_chain()).unwrap_or_else(|e| {
-            panic!(
-                "GRPC_HELLO_SECURE=Y but failed to read client cert chain {:?}: {}",
-                trans::client_cert_chain(),
-                e
-            )
-        });
-        let key = fs::read(trans::client_cert_key()).unwrap_or_else(|e| {
-            panic!(
-                "GRPC_HELLO_SECURE=Y but failed to read client key {:?}: {}",
-                trans::client_cert_key(),
-                e
-            )
-        });
-        let ca = fs::read(trans::client_root_cert()).unwrap_or_else(|e| {
-            panic!(
-                "GRPC_HELLO_SECURE=Y but failed to read root cert {:?}: {}",
-                trans::client_root_cert(),
-                e
-            )
-        });
-
-        // creating identity from key and certificate
-        let identity_cert = Identity::from_pem(cert, key);
-        let ca = Certificate::from_pem(ca);
-
-        // telling the client what is the identity of our server
-        let tls = ClientTlsConfig::new()
-            .domain_name(DOMAIN_NAME)
-            .identity(identity_cert)
-            .ca_certificate(ca);
-
-        let endpoint = Endpoint::from_shared(address)
-            .unwrap_or_else(|error| panic!("Invalid gRPC server address: {:?}", error));
-        if let Ok(channel_builder) = endpoint.tls_config(tls) {
-            let channel_builder = with_keepalive(channel_builder);
-            if let Ok(channel) = channel_builder.connect().await {
-                info!("Connect with TLS(:{})", grpc_backend_port());
-                // Enables gzip compression for outgoing/incoming messages.
-                return LandingServiceClient::new(channel)
-                    .send_compressed(CompressionEncoding::Gzip)
-                    .accept_compressed(CompressionEncoding::Gzip);
-            } else {
-                error!("Failed to connect with TLS");
+impl Backend {
+    fn new(channel: BalancedChannel, config: Arc<ServiceConfig>, target: &str) -> Self {
+        Backend {
+            client: landing_client(channel.clone(), config, target),
+            channel,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.channel.is_ready()
+    }
+}
+
+/// Builds clients for the configured backend: the etcd-resolved address
+/// when `GRPC_HELLO_DISCOVERY=etcd`, otherwise `GRPC_HELLO_BACKEND` over TLS
+/// when `GRPC_HELLO_SECURE=Y` or plaintext. Calls follow the service config
+/// from [`service_config::load`].
+///
+/// By default [`build`](ClientBuilder::build) connects before returning and
+/// fails if no target can be reached; a client asked for TLS never falls back
+/// to plaintext. A [`lazy`](ClientBuilder::lazy) builder returns at once and
+/// connects in the background, reconnecting with backoff.
+#[derive(Debug, Clone, Default)]
+pub struct ClientBuilder {
+    lazy: bool,
+    backend: Option<(String, String)>,
+    tls: Option<ClientTlsSettings>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        ClientBuilder::default()
+    }
+
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Connects to `host` (a comma-separated list, like `GRPC_HELLO_BACKEND`)
+    /// on `port` instead of the backend from the environment.
+    pub fn backend(mut self, host: impl Into<String>, port: impl Into<String>) -> Self {
+        self.backend = Some((host.into(), port.into()));
+        self
+    }
+
+    /// Connects over TLS with `settings` instead of following
+    /// `GRPC_HELLO_SECURE` and the `GRPC_HELLO_TLS_*` variables.
+    pub fn tls(mut self, settings: ClientTlsSettings) -> Self {
+        self.tls = Some(settings);
+        self
+    }
+
+    pub async fn build(self) -> Result<Backend, ConnectError> {
+        let config = service_config::load().map_err(ConnectError::ServiceConfig)?;
+
+        // Check etcd service discovery first
+        if etcd::is_etcd_discovery() {
+            let address = etcd::resolve_from_etcd().await.map_err(|e| {
+                error!("etcd discovery enabled but resolution failed: {}", e);
+                ConnectError::Discovery(e.to_string())
+            })?;
+            info!("Resolved service via etcd: {}", address);
+            let endpoint = endpoint(&address)?;
+            return self
+                .channel(vec![endpoint], LbPolicy::PickFirst, None)
+                .await
+                .map(|channel| Backend::new(channel, config, &address));
+        }
+
+        let (host, port) = self
+            .backend
+            .clone()
+            .unwrap_or_else(|| (grpc_backend_host(), grpc_backend_port()));
+        let targets = parse_targets(&host, &port);
+        if targets.is_empty() {
+            return Err(ConnectError::NoTarget);
+        }
+        let target = targets
+            .iter()
+            .map(|(host, port)| format!("{}:{}", host, port))
+            .collect::<Vec<_>>()
+            .join(",");
+        let policy = LbPolicy::from_env();
+        if targets.len() > 1 {
+            info!(
+                "Balancing across {} backends with {:?}",
+                targets.len(),
+                policy
+            );
+        }
+
+        let tls = match &self.tls {
+            Some(settings) => Some(tls_connector(settings).map_err(ConnectError::Tls)?),
+            None if env::var("GRPC_HELLO_SECURE").is_ok_and(|v| v == "Y") => Some(client_tls()?),
+            None => None,
+        };
+        if let Some(tls) = tls {
+            // TLS is done by the connector, so the endpoints stay http://
+            let mut endpoints = Vec::with_capacity(targets.len());
+            for (host, port) in &targets {
+                endpoints.push(endpoint(&format!("http://{}:{}", host, port))?);
             }
-        } else {
-            error!("Failed to build TLS client configuration");
+            // Never retried in plaintext: that would hand the traffic to
+            // whoever answers on the port without TLS
+            let channel = self
+                .channel(endpoints, policy, Some(tls))
+                .await
+                .inspect_err(|e| error!("Failed to connect with TLS: {}", e))?;
+            info!("Connect with TLS(:{})", port);
+            return Ok(Backend::new(channel, config, &target));
+        }
+
+        info!("Connect with insecure connection (:{})", port);
+        let mut endpoints = Vec::with_capacity(targets.len());
+        for (host, port) in &targets {
+            let address = format!("http://{}:{}", host, port);
+            info!("Connect with insecure address: {}", address);
+            endpoints.push(endpoint(&address)?);
+        }
+        let channel = self.channel(endpoints, policy, None).await?;
+        Ok(Backend::new(channel, config, &target))
+    }
+
+    async fn channel(
+        &self,
+        endpoints: Vec<Endpoint>,
+        policy: LbPolicy,
+        tls: Option<TlsConnector>,
+    ) -> Result<BalancedChannel, ConnectError> {
+        if self.lazy {
+            return Ok(BalancedChannel::lazy(endpoints, policy, tls));
         }
+        BalancedChannel::connect(endpoints, policy, tls)
+            .await
+            .map_err(ConnectError::Connect)
     }
+}
 
-    let address = format!("http://{}:{}", grpc_backend_host(), grpc_backend_port());
-    info!(
-        "Connect with insecure connection (:{})",
-        grpc_backend_port()
-    );
-    info!("Connect with insecure address: {}", address);
-    let endpoint = Endpoint::from_shared(address)
-        .unwrap_or_else(|error| panic!("Invalid gRPC server address: {:?}", error));
-    let channel = with_keepalive(endpoint)
-        .connect()
+/// Connects to the configured backend with a default [`ClientBuilder`].
+pub async fn try_build_client() -> Result<LandingClient, ConnectError> {
+    ClientBuilder::new()
+        .build()
         .await
-        .unwrap_or_else(|error| panic!("Failed to connect to gRPC server: {:?}", error));
-    // Enables gzip compression for outgoing/incoming messages.
-    LandingServiceClient::new(channel)
-        .send_compressed(CompressionEncoding::Gzip)
-        .accept_compressed(CompressionEncoding::Gzip)
+        .map(|backend| backend.client)
+}
+
+fn endpoint(address: &str) -> Result<Endpoint, ConnectError> {
+    Endpoint::from_shared(address.to_string())
+        .map(with_keepalive)
+        .map_err(|source| ConnectError::InvalidAddress {
+            address: address.to_string(),
+            source,
+        })
+}
+
+/// The TLS connector shared by every client of the process; its config is
+/// reloaded when the certificate files change.
+static CLIENT_TLS: OnceCell<TlsConnector> = OnceCell::new();
+
+/// The client TLS config from [`ClientTlsSettings::from_env`]: by default the
+/// client identity and root certificate under `CERT_BASE_PATH` or the
+/// platform default, checked against `hello.grpc.io`.
+fn client_tls() -> Result<TlsConnector, ConnectError> {
+    CLIENT_TLS
+        .get_or_try_init(|| tls_connector(&ClientTlsSettings::from_env()?))
+        .cloned()
+        .map_err(ConnectError::Tls)
+}
+
+/// A connector for `settings` whose config is reloaded when its files
+/// change.
+fn tls_connector(settings: &ClientTlsSettings) -> Result<TlsConnector, String> {
+    let (config, expiry) = settings.config()?;
+    match (&settings.identity, expiry) {
+        (Some((chain, _)), Some(expiry)) => {
+            info!(
+                "Loaded the client certificate {:?}, valid until {}",
+                chain, expiry
+            );
+            MONITOR.record("client", expiry);
+        }
+        _ => info!("Connecting without a client certificate"),
+    }
+    if settings.insecure_skip_verify {
+        warn!(
+            "GRPC_HELLO_TLS_INSECURE_SKIP_VERIFY=Y: server certificates are not verified, use this in a lab only"
+        );
+    } else {
+        info!(
+            "Checking server certificates for {:?} against {:?}",
+            settings.server_name.to_str(),
+            settings.roots
+        );
+    }
+    let config = Reloadable::new(config);
+    let files = settings.files();
+    if !files.is_empty() {
+        let reload = settings.clone();
+        config.watch("client", files, move || reload.config());
+    }
+    Ok(TlsConnector::new(config, settings.server_name.clone()))
 }
 
 fn grpc_server() -> String {
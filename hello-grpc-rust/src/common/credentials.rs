@@ -0,0 +1,178 @@
+//! Bearer tokens for outgoing calls.
+//!
+//! [`BearerTokenLayer`] sets `authorization: Bearer <token>` on every call
+//! that does not already carry an `authorization` header, so a token the
+//! proxy forwards from its caller takes precedence over its own. The token
+//! comes from `GRPC_HELLO_TOKEN`, or from `GRPC_HELLO_TOKEN_FILE`, which is
+//! watched in the background and read again whenever it changes so rotated
+//! tokens are picked up without touching the file on every call. A read that
+//! finds no token, such as a file caught halfway through a rewrite, keeps the
+//! previous one.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http::HeaderValue;
+use http::header::AUTHORIZATION;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use tower::{Layer, Service};
+
+use crate::common::watch::watch_files;
+
+/// How often the token file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where the client's token comes from.
+pub enum TokenSource {
+    Static(HeaderValue),
+    File {
+        path: PathBuf,
+        /// The token as of the last read of the file.
+        current: Arc<RwLock<Option<HeaderValue>>>,
+    },
+}
+
+impl TokenSource {
+    /// A fixed token.
+    pub fn token(token: &str) -> Option<Self> {
+        bearer(token).map(TokenSource::Static)
+    }
+
+    /// A token file, read now and again whenever it changes. Must be called
+    /// inside a Tokio runtime, which runs the watch.
+    pub fn file(path: PathBuf) -> Self {
+        let current = Arc::new(RwLock::new(read_token(&path)));
+        let reloaded = current.clone();
+        let watched = path.clone();
+        watch_files(vec![path.clone()], RELOAD_INTERVAL, move || {
+            if replace_token(&watched, &reloaded) {
+                info!("Reloaded the token from {:?}", watched);
+            }
+        });
+        TokenSource::File { path, current }
+    }
+
+    /// `GRPC_HELLO_TOKEN` or `GRPC_HELLO_TOKEN_FILE`; `None` when neither is
+    /// set.
+    pub fn from_env() -> Option<Self> {
+        if let Ok(token) = env::var("GRPC_HELLO_TOKEN") {
+            return TokenSource::token(&token);
+        }
+        env::var("GRPC_HELLO_TOKEN_FILE")
+            .ok()
+            .map(|path| TokenSource::file(PathBuf::from(path)))
+    }
+
+    /// The `authorization` value to send, if there is a token.
+    pub fn header(&self) -> Option<HeaderValue> {
+        match self {
+            TokenSource::Static(value) => Some(value.clone()),
+            TokenSource::File { current, .. } => current.read().unwrap().clone(),
+        }
+    }
+
+    /// Reads the token file again now instead of waiting for the watch.
+    pub fn reload(&self) {
+        if let TokenSource::File { path, current } = self {
+            replace_token(path, current);
+        }
+    }
+}
+
+/// Swaps in the token from the file, keeping the current one when the file
+/// has none. Returns whether it was replaced.
+fn replace_token(path: &PathBuf, current: &RwLock<Option<HeaderValue>>) -> bool {
+    match read_token(path) {
+        Some(token) => {
+            *current.write().unwrap() = Some(token);
+            true
+        }
+        None => {
+            warn!("Keeping the current token, {:?} has none", path);
+            false
+        }
+    }
+}
+
+fn read_token(path: &PathBuf) -> Option<HeaderValue> {
+    match fs::read_to_string(path) {
+        Ok(token) => bearer(&token),
+        Err(e) => {
+            warn!("Failed to read token file {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn bearer(token: &str) -> Option<HeaderValue> {
+    let token = token.trim();
+    if token.is_empty() {
+        return None;
+    }
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token)).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+/// The process-wide token used by clients built from the environment.
+static TOKEN: Lazy<Option<Arc<TokenSource>>> = Lazy::new(|| TokenSource::from_env().map(Arc::new));
+
+#[derive(Clone)]
+pub struct BearerTokenLayer {
+    source: Option<Arc<TokenSource>>,
+}
+
+impl BearerTokenLayer {
+    pub fn new(source: Option<Arc<TokenSource>>) -> Self {
+        BearerTokenLayer { source }
+    }
+
+    /// Attaches the token configured in the environment, if any.
+    pub fn from_env() -> Self {
+        BearerTokenLayer::new(TOKEN.clone())
+    }
+}
+
+impl<S> Layer<S> for BearerTokenLayer {
+    type Service = BearerTokenService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BearerTokenService {
+            inner,
+            source: self.source.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BearerTokenService<S> {
+    inner: S,
+    source: Option<Arc<TokenSource>>,
+}
+
+impl<S, B> Service<http::Request<B>> for BearerTokenService<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        if !request.headers().contains_key(AUTHORIZATION)
+            && let Some(value) = self.source.as_deref().and_then(TokenSource::header)
+        {
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+        self.inner.call(request)
+    }
+}
@@ -0,0 +1,102 @@
+//! Deadline propagation for proxied calls.
+//!
+//! A caller's deadline arrives as the `grpc-timeout` header (an ASCII
+//! integer of at most 8 digits followed by a unit: `H`, `M`, `S`, `m`, `u`
+//! or `n`). The proxy hands the backend what is left of it, minus
+//! `GRPC_HELLO_DEADLINE_MARGIN_MS` (default 100ms) for its own hop, so the
+//! backend gives up before the caller does and the error makes it back.
+//! A deadline too short for the margin still gets a small budget rather
+//! than failing outright.
+
+use std::env;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tonic::Status;
+use tonic::metadata::MetadataMap;
+
+const GRPC_TIMEOUT: &str = "grpc-timeout";
+const DEFAULT_MARGIN_MS: u64 = 100;
+/// The least a backend call is given when the margin would leave less.
+const MIN_BUDGET: Duration = Duration::from_millis(10);
+
+/// Parses a `grpc-timeout` value such as `250m` or `5S`.
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// Formats `timeout` as a `grpc-timeout` value, in the finest unit that
+/// fits the 8-digit limit.
+pub fn grpc_timeout_value(timeout: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+    let units = [
+        (timeout.as_nanos(), "n"),
+        (timeout.as_micros(), "u"),
+        (timeout.as_millis(), "m"),
+        (timeout.as_secs() as u128, "S"),
+        (timeout.as_secs() as u128 / 60, "M"),
+    ];
+    units
+        .iter()
+        .find(|(amount, _)| *amount <= MAX)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .unwrap_or_else(|| format!("{}H", (timeout.as_secs() / 3600).min(MAX as u64)))
+}
+
+/// Time reserved for the proxy's own hop, from `GRPC_HELLO_DEADLINE_MARGIN_MS`.
+pub fn deadline_margin() -> Duration {
+    let ms = env::var("GRPC_HELLO_DEADLINE_MARGIN_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MARGIN_MS);
+    Duration::from_millis(ms)
+}
+
+/// Budget for backend work on behalf of a caller: its `grpc-timeout` minus
+/// `margin` but at least 10ms (or all of it when less is left), or `None`
+/// when the caller set no deadline. Only a deadline that has expired is
+/// rejected with `DEADLINE_EXCEEDED`.
+pub fn backend_budget(
+    metadata: &MetadataMap,
+    margin: Duration,
+) -> Result<Option<Duration>, Status> {
+    let Some(timeout) = metadata
+        .get(GRPC_TIMEOUT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_grpc_timeout)
+    else {
+        return Ok(None);
+    };
+    if timeout.is_zero() {
+        return Err(Status::deadline_exceeded(
+            "deadline expired before the backend call",
+        ));
+    }
+    Ok(Some(
+        timeout.saturating_sub(margin).max(MIN_BUDGET.min(timeout)),
+    ))
+}
+
+/// Resolves at `deadline`, or never when there is none.
+pub async fn expired(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
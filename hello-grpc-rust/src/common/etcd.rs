@@ -13,6 +13,7 @@ use serde_json::json;
 use std::env;
 use std::time::Duration;
 use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
 const ETCD_KEY: &str = "/etcd/hello-grpc";
 const DEFAULT_TTL: i64 = 5;
@@ -72,10 +73,29 @@ pub async fn resolve_from_etcd() -> Result<String, Box<dyn std::error::Error + S
     Err("no service instance found in etcd".into())
 }
 
+/// A live etcd registration. The keepalive task refreshes the lease until
+/// [`EtcdRegistration::deregister`] fires the stop channel, at which point
+/// the lease is revoked so the key disappears immediately instead of after
+/// the TTL expires.
+pub struct EtcdRegistration {
+    stop: oneshot::Sender<()>,
+    keepalive: JoinHandle<()>,
+}
+
+impl EtcdRegistration {
+    /// Stops the keepalive task and waits for the lease revocation to finish.
+    pub async fn deregister(self) {
+        let _ = self.stop.send(());
+        if let Err(e) = self.keepalive.await {
+            warn!("etcd keepalive task failed: {}", e);
+        }
+    }
+}
+
 pub async fn register_to_etcd(
     host: &str,
     port: u16,
-) -> Result<oneshot::Sender<()>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<EtcdRegistration, Box<dyn std::error::Error + Send + Sync>> {
     let address = format!("{}:{}", host, port);
     // Grant lease
     let lease_resp = post("/v3/lease/grant", json!({ "TTL": DEFAULT_TTL })).await?;
@@ -99,9 +119,9 @@ pub async fn register_to_etcd(
     .await?;
     info!("Registered with etcd: {} (lease={})", address, lease_id);
 
-    // Start keepalive task
+    // Start keepalive task; revoke the lease once asked to stop
     let (tx, mut rx) = oneshot::channel::<()>();
-    tokio::spawn(async move {
+    let keepalive = tokio::spawn(async move {
         loop {
             tokio::select! {
                 _ = tokio::time::sleep(Duration::from_secs((DEFAULT_TTL - 1) as u64)) => {
@@ -115,6 +135,13 @@ pub async fn register_to_etcd(
                 }
             }
         }
+        match post("/v3/lease/revoke", json!({ "ID": lease_id.to_string() })).await {
+            Ok(_) => info!("Revoked etcd lease {}", lease_id),
+            Err(e) => warn!("etcd lease revoke error: {}", e),
+        }
     });
-    Ok(tx)
+    Ok(EtcdRegistration {
+        stop: tx,
+        keepalive,
+    })
 }
@@ -0,0 +1,229 @@
+//! Body wrappers that observe gRPC traffic at the HTTP/2 level.
+//!
+//! gRPC frames every message as a 1-byte compression flag, a 4-byte
+//! big-endian length and the payload. Counting those prefixes as DATA frames
+//! go by gives exact per-call message counts without decoding anything, and
+//! the final `grpc-status` is read from the response headers
+//! (trailers-only responses) or the trailers. Both the server metrics layer
+//! and the client metrics layer are built on these.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tonic::Code;
+
+/// Splits `/hello.LandingService/Talk` into `("hello.LandingService", "Talk")`.
+pub fn split_grpc_path(path: &str) -> (String, String) {
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(service), Some(method)) if !service.is_empty() && !method.is_empty() => {
+            (service.to_string(), method.to_string())
+        }
+        _ => ("unknown".to_string(), path.to_string()),
+    }
+}
+
+/// Reads `grpc-status` from a header or trailer map.
+pub fn status_from_headers(headers: &http::HeaderMap) -> Option<Code> {
+    headers
+        .get("grpc-status")
+        .map(|value| Code::from_bytes(value.as_bytes()))
+}
+
+/// Incremental parser for the 5-byte gRPC message prefix.
+#[derive(Default)]
+pub struct MessageCounter {
+    header_read: usize,
+    length: [u8; 4],
+    payload_left: usize,
+    messages: u64,
+    largest: usize,
+}
+
+impl MessageCounter {
+    /// Feeds one DATA chunk and returns how many new messages started in it.
+    pub fn feed(&mut self, mut data: &[u8]) -> u64 {
+        let before = self.messages;
+        while !data.is_empty() {
+            if self.payload_left > 0 {
+                let n = self.payload_left.min(data.len());
+                self.payload_left -= n;
+                data = &data[n..];
+                continue;
+            }
+            if self.header_read > 0 {
+                self.length[self.header_read - 1] = data[0];
+            }
+            self.header_read += 1;
+            data = &data[1..];
+            if self.header_read == 5 {
+                self.messages += 1;
+                self.header_read = 0;
+                self.payload_left = u32::from_be_bytes(self.length) as usize;
+                self.largest = self.largest.max(self.payload_left);
+            }
+        }
+        self.messages - before
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// Length of the largest message whose prefix has been seen.
+    pub fn largest(&self) -> usize {
+        self.largest
+    }
+}
+
+pin_project! {
+    /// Request body wrapper that publishes its message count through a
+    /// shared counter, so the count can be read after the body has been
+    /// handed to the inner service.
+    pub struct CountingBody<B> {
+        #[pin]
+        inner: B,
+        counter: MessageCounter,
+        total: Arc<AtomicU64>,
+    }
+}
+
+impl<B> CountingBody<B> {
+    pub fn new(inner: B, total: Arc<AtomicU64>) -> Self {
+        CountingBody {
+            inner,
+            counter: MessageCounter::default(),
+            total,
+        }
+    }
+}
+
+impl<B: Body<Data = Bytes>> Body for CountingBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let polled = this.inner.poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled
+            && let Some(data) = frame.data_ref()
+        {
+            let started = this.counter.feed(data);
+            this.total.fetch_add(started, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Callback run exactly once when an observed response finishes, with the
+/// final status and the number of response messages seen.
+pub type OnFinish = Box<dyn FnOnce(Code, u64) + Send>;
+
+/// Guard that runs its [`OnFinish`] callback when dropped. Created before
+/// the inner service is called so that a call abandoned before the response
+/// headers arrive is still reported (as `Cancelled`).
+pub struct Finish {
+    code: Option<Code>,
+    messages: u64,
+    on_finish: Option<OnFinish>,
+}
+
+impl Finish {
+    pub fn new(on_finish: OnFinish) -> Self {
+        Finish {
+            code: None,
+            messages: 0,
+            on_finish: Some(on_finish),
+        }
+    }
+
+    /// Records the final status unless one was already seen.
+    pub fn set_code(&mut self, code: Code) {
+        self.code.get_or_insert(code);
+    }
+}
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        // No status by the time the body is dropped means the peer went away
+        if let Some(on_finish) = self.on_finish.take() {
+            on_finish(self.code.unwrap_or(Code::Cancelled), self.messages);
+        }
+    }
+}
+
+pin_project! {
+    /// Response body wrapper that counts messages, picks the final
+    /// `grpc-status` out of the trailers and reports both on drop.
+    pub struct ObservedBody<B> {
+        #[pin]
+        inner: B,
+        counter: MessageCounter,
+        finish: Finish,
+    }
+}
+
+impl<B> ObservedBody<B> {
+    /// Picks up a trailers-only status from `headers` before any frame is polled.
+    pub fn new(inner: B, headers: &http::HeaderMap, mut finish: Finish) -> Self {
+        if let Some(code) = status_from_headers(headers) {
+            finish.set_code(code);
+        }
+        ObservedBody {
+            inner,
+            counter: MessageCounter::default(),
+            finish,
+        }
+    }
+}
+
+impl<B: Body<Data = Bytes>> Body for ObservedBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let polled = this.inner.poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.counter.feed(data);
+                    this.finish.messages = this.counter.messages();
+                } else if let Some(code) = frame.trailers_ref().and_then(status_from_headers) {
+                    this.finish.code = Some(code);
+                }
+            }
+            Poll::Ready(Some(Err(_))) => this.finish.set_code(Code::Internal),
+            Poll::Ready(None) => this.finish.set_code(Code::Unknown),
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,173 @@
+//! Which metadata the proxy carries across the hop.
+//!
+//! Three independent policies cover the caller's request metadata, the
+//! backend's response headers and the backend's trailers. Each policy is an
+//! allow list plus a deny list of comma-separated patterns. A pattern is
+//! either an exact key (`x-tenant`, `x-user-bin`) or a prefix ending in `*`
+//! (`x-*`, `x-b3-*`). A key must match an allow pattern and no deny pattern.
+//! Keys are compared in lowercase. They are read from:
+//!
+//! - `GRPC_HELLO_METADATA_REQUEST_ALLOW` / `_DENY`: defaults to
+//!   [`TRACING_KEYS`] and `traceparent` / `tracestate`.
+//! - `GRPC_HELLO_METADATA_HEADERS_ALLOW` / `_DENY`: defaults to none.
+//! - `GRPC_HELLO_METADATA_TRAILERS_ALLOW` / `_DENY`: defaults to none.
+//!
+//! Protocol headers (`grpc-*`, `content-type`, `te`, `user-agent`, ...) are
+//! owned by the transport and never copied.
+
+use std::env;
+
+use bytes::Bytes;
+use tonic::Status;
+use tonic::metadata::{KeyAndValueRef, MetadataMap};
+
+use crate::common::trans::TRACING_KEYS;
+
+/// Keys that belong to HTTP/2 or the gRPC protocol itself.
+const RESERVED: &[&str] = &[
+    "grpc-*",
+    "content-type",
+    "content-length",
+    "te",
+    "user-agent",
+    "host",
+    "connection",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(match pattern.strip_suffix('*') {
+            Some(prefix) => Pattern::Prefix(prefix.to_string()),
+            None => Pattern::Exact(pattern),
+        })
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Pattern::Exact(exact) => key == exact,
+            Pattern::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+fn parse_patterns(list: &str) -> Vec<Pattern> {
+    list.split(',').filter_map(Pattern::parse).collect()
+}
+
+/// An allow/deny filter over metadata keys.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataPolicy {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl MetadataPolicy {
+    /// Builds a policy from comma-separated allow and deny pattern lists.
+    pub fn new(allow: &str, deny: &str) -> Self {
+        let mut deny = parse_patterns(deny);
+        deny.extend(RESERVED.iter().filter_map(|key| Pattern::parse(key)));
+        MetadataPolicy {
+            allow: parse_patterns(allow),
+            deny,
+        }
+    }
+
+    /// Reads `GRPC_HELLO_METADATA_<scope>_ALLOW` and `_DENY`.
+    fn from_env(scope: &str, default_allow: &str) -> Self {
+        let allow = env::var(format!("GRPC_HELLO_METADATA_{}_ALLOW", scope))
+            .unwrap_or_else(|_| default_allow.to_string());
+        let deny = env::var(format!("GRPC_HELLO_METADATA_{}_DENY", scope)).unwrap_or_default();
+        MetadataPolicy::new(&allow, &deny)
+    }
+
+    pub fn allows(&self, key: &str) -> bool {
+        let key = key.to_ascii_lowercase();
+        self.allow.iter().any(|pattern| pattern.matches(&key))
+            && !self.deny.iter().any(|pattern| pattern.matches(&key))
+    }
+
+    /// Appends every entry of `from` that the policy allows to `to`, keeping
+    /// repeated values and binary (`-bin`) keys intact.
+    pub fn copy(&self, from: &MetadataMap, to: &mut MetadataMap) {
+        copy_where(from, to, |key| self.allows(key));
+    }
+
+    /// Rebuilds a backend error with only the metadata the policy allows;
+    /// code, message and details are kept.
+    pub fn filter_status(&self, status: Status) -> Status {
+        let mut metadata = MetadataMap::new();
+        self.copy(status.metadata(), &mut metadata);
+        Status::with_details_and_metadata(
+            status.code(),
+            status.message().to_string(),
+            Bytes::copy_from_slice(status.details()),
+            metadata,
+        )
+    }
+}
+
+fn copy_where(from: &MetadataMap, to: &mut MetadataMap, keep: impl Fn(&str) -> bool) {
+    for entry in from.iter() {
+        match entry {
+            KeyAndValueRef::Ascii(key, value) if keep(key.as_str()) => {
+                to.append(key.clone(), value.clone());
+            }
+            KeyAndValueRef::Binary(key, value) if keep(key.as_str()) => {
+                to.append_bin(key.clone(), value.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The three policies applied by the proxy.
+#[derive(Debug, Clone, Default)]
+pub struct PropagationPolicy {
+    /// Caller metadata forwarded on the backend request.
+    pub request: MetadataPolicy,
+    /// Backend response headers forwarded to the caller.
+    pub headers: MetadataPolicy,
+    /// Backend trailers (and error metadata) forwarded to the caller.
+    pub trailers: MetadataPolicy,
+}
+
+impl PropagationPolicy {
+    pub fn from_env() -> Self {
+        let default_request = TRACING_KEYS
+            .iter()
+            .copied()
+            .chain(["traceparent", "tracestate"])
+            .collect::<Vec<_>>()
+            .join(",");
+        PropagationPolicy {
+            request: MetadataPolicy::from_env("REQUEST", &default_request),
+            headers: MetadataPolicy::from_env("HEADERS", ""),
+            trailers: MetadataPolicy::from_env("TRAILERS", ""),
+        }
+    }
+
+    /// Splits the metadata of a unary or client-streaming response. tonic
+    /// merges those responses' trailers into their headers, so each key goes
+    /// wherever a policy lets it through, headers first.
+    pub fn split_response(
+        &self,
+        merged: &MetadataMap,
+        headers: &mut MetadataMap,
+        trailers: &mut MetadataMap,
+    ) {
+        self.headers.copy(merged, headers);
+        copy_where(merged, trailers, |key| {
+            self.trailers.allows(key) && !self.headers.allows(key)
+        });
+    }
+}
@@ -0,0 +1,261 @@
+//! Process-wide Prometheus metrics registry.
+//!
+//! A deliberately small registry (counters, gauges, histograms) that renders
+//! the Prometheus text exposition format (version 0.0.4). Server layers and
+//! client helpers record into [`REGISTRY`]; the server's side HTTP listener
+//! serves [`Registry::render`] on `/metrics`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use tonic::Code;
+
+/// Prometheus' default latency buckets, in seconds.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content type expected by Prometheus scrapers for the text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::default);
+
+/// The canonical name of a status code (`OK`, `UNAVAILABLE`, ...), as
+/// gRPC dashboards expect in the `grpc_code` label.
+pub fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::Unknown => "UNKNOWN",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+enum Series {
+    Value(f64),
+    Histogram {
+        counts: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+struct Family {
+    help: &'static str,
+    kind: Kind,
+    series: BTreeMap<Labels, Series>,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl Registry {
+    fn with_series<F>(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        update: F,
+    ) where
+        F: FnOnce(&mut Series),
+    {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind,
+            series: BTreeMap::new(),
+        });
+        if family.kind != kind {
+            return;
+        }
+        let key: Labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let series = family.series.entry(key).or_insert_with(|| match kind {
+            Kind::Histogram => Series::Histogram {
+                counts: vec![0; DEFAULT_BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            },
+            _ => Series::Value(0.0),
+        });
+        update(series);
+    }
+
+    /// Adds `value` to a monotonically increasing counter.
+    pub fn add_counter(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.with_series(name, help, Kind::Counter, labels, |series| {
+            if let Series::Value(v) = series {
+                *v += value;
+            }
+        });
+    }
+
+    /// Increments a counter by one.
+    pub fn inc_counter(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+        self.add_counter(name, help, labels, 1.0);
+    }
+
+    /// Sets a gauge to an absolute value.
+    pub fn set_gauge(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.with_series(name, help, Kind::Gauge, labels, |series| {
+            if let Series::Value(v) = series {
+                *v = value;
+            }
+        });
+    }
+
+    /// Moves a gauge up or down by `delta`.
+    pub fn add_gauge(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        delta: f64,
+    ) {
+        self.with_series(name, help, Kind::Gauge, labels, |series| {
+            if let Series::Value(v) = series {
+                *v += delta;
+            }
+        });
+    }
+
+    /// Records one observation into a histogram using [`DEFAULT_BUCKETS`].
+    pub fn observe(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.with_series(name, help, Kind::Histogram, labels, |series| {
+            if let Series::Histogram { counts, sum, count } = series {
+                for (bucket, upper) in counts.iter_mut().zip(DEFAULT_BUCKETS) {
+                    if value <= *upper {
+                        *bucket += 1;
+                    }
+                }
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+
+    /// Renders every family in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, family.kind.as_str());
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Value(v) => {
+                        let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), v);
+                    }
+                    Series::Histogram { counts, sum, count } => {
+                        for (bucket, upper) in counts.iter().zip(DEFAULT_BUCKETS) {
+                            let le = upper.to_string();
+                            let _ = writeln!(
+                                out,
+                                "{}_bucket{} {}",
+                                name,
+                                format_labels(labels, Some(&le)),
+                                bucket
+                            );
+                        }
+                        let _ = writeln!(
+                            out,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(labels, Some("+Inf")),
+                            count
+                        );
+                        let _ =
+                            writeln!(out, "{}_sum{} {}", name, format_labels(labels, None), sum);
+                        let _ = writeln!(
+                            out,
+                            "{}_count{} {}",
+                            name,
+                            format_labels(labels, None),
+                            count
+                        );
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
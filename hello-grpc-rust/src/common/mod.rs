@@ -4,7 +4,23 @@ pub mod landing {
 
 pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptor");
 
+pub mod balancer;
+pub mod breaker;
+pub mod cert_expiry;
+pub mod certgen;
+pub mod client_metrics;
 pub mod conn;
+pub mod credentials;
+pub mod deadline;
 pub mod etcd;
+pub mod framing;
+pub mod metadata_policy;
+pub mod metrics;
+pub mod propagation;
+pub mod replay;
+pub mod retry;
+pub mod service_config;
+pub mod tls;
 pub mod trans;
 pub mod utils;
+pub mod watch;
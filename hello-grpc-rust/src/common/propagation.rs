@@ -0,0 +1,226 @@
+//! Trace context propagation over gRPC metadata.
+//!
+//! Incoming calls may carry a W3C `traceparent` (Go, Java and the OTel SDKs)
+//! or Zipkin B3 headers (older Envoy / Istio meshes). [`propagator`] accepts
+//! both; outgoing calls are always stamped with `traceparent` by
+//! [`ClientTraceLayer`], which opens the client span the backend's server
+//! span hangs off.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use once_cell::sync::Lazy;
+use opentelemetry::global;
+use opentelemetry::propagation::{
+    Extractor, Injector, TextMapCompositePropagator, TextMapPropagator,
+    text_map_propagator::FieldIter,
+};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tonic::Code;
+use tower::{Layer, Service};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::common::framing::{Finish, ObservedBody, split_grpc_path};
+use crate::otel;
+
+const B3_SINGLE: &str = "b3";
+const B3_TRACE_ID: &str = "x-b3-traceid";
+const B3_SPAN_ID: &str = "x-b3-spanid";
+const B3_SAMPLED: &str = "x-b3-sampled";
+const B3_FLAGS: &str = "x-b3-flags";
+
+static B3_FIELDS: Lazy<Vec<String>> = Lazy::new(|| {
+    [B3_SINGLE, B3_TRACE_ID, B3_SPAN_ID, B3_SAMPLED, B3_FLAGS]
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+});
+
+/// The propagator installed by `init_otel`: B3 first, then W3C trace
+/// context, so `traceparent` overrides B3 when a caller sends both.
+pub fn propagator() -> TextMapCompositePropagator {
+    TextMapCompositePropagator::new(vec![
+        Box::new(B3Propagator),
+        Box::new(TraceContextPropagator::new()),
+    ])
+}
+
+/// Extract-only B3 propagator understanding both the single `b3` header and
+/// the multi-header `x-b3-*` form. Injection is left to the W3C propagator.
+#[derive(Debug, Default)]
+pub struct B3Propagator;
+
+impl B3Propagator {
+    fn extract_single(value: &str) -> Option<SpanContext> {
+        let mut parts = value.trim().split('-');
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let sampled = parts.next().is_none_or(|s| s == "1" || s == "d");
+        span_context(trace_id, span_id, sampled)
+    }
+
+    fn extract_multi(extractor: &dyn Extractor) -> Option<SpanContext> {
+        let trace_id = extractor.get(B3_TRACE_ID)?;
+        let span_id = extractor.get(B3_SPAN_ID)?;
+        let debug = extractor.get(B3_FLAGS) == Some("1");
+        let sampled = debug
+            || extractor
+                .get(B3_SAMPLED)
+                .is_none_or(|s| s == "1" || s.eq_ignore_ascii_case("true"));
+        span_context(trace_id, span_id, sampled)
+    }
+}
+
+/// Builds a remote span context from B3 ids; 64-bit trace ids are
+/// left-padded to 128 bits as the B3 spec allows.
+fn span_context(trace_id: &str, span_id: &str, sampled: bool) -> Option<SpanContext> {
+    let trace_id = match trace_id.len() {
+        16 => format!("{:0>32}", trace_id),
+        32 => trace_id.to_string(),
+        _ => return None,
+    };
+    if span_id.len() != 16 {
+        return None;
+    }
+    let trace_id = TraceId::from_hex(&trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let flags = if sampled {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::default()
+    };
+    let context = SpanContext::new(trace_id, span_id, flags, true, TraceState::default());
+    context.is_valid().then_some(context)
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, _cx: &opentelemetry::Context, _injector: &mut dyn Injector) {}
+
+    fn extract_with_context(
+        &self,
+        cx: &opentelemetry::Context,
+        extractor: &dyn Extractor,
+    ) -> opentelemetry::Context {
+        let extracted = extractor
+            .get(B3_SINGLE)
+            .and_then(Self::extract_single)
+            .or_else(|| Self::extract_multi(extractor));
+        match extracted {
+            Some(span_context) => cx.with_remote_span_context(span_context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(B3_FIELDS.as_slice())
+    }
+}
+
+/// Reads propagation fields out of HTTP/2 request headers.
+pub struct HeaderExtractor<'a>(pub &'a http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Writes propagation fields into HTTP/2 request headers.
+pub struct HeaderInjector<'a>(pub &'a mut http::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let Ok(name) = http::header::HeaderName::from_bytes(key.as_bytes())
+            && let Ok(value) = http::header::HeaderValue::from_str(&value)
+        {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Parent context carried by an incoming request, if any.
+pub fn extract_context(headers: &http::HeaderMap) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Stamps `span`'s context onto outgoing request headers.
+pub fn inject_context(span: &tracing::Span, headers: &mut http::HeaderMap) {
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+/// Opens a client span per outgoing call and injects its context, replacing
+/// any `traceparent` copied over from the incoming request.
+#[derive(Clone, Default)]
+pub struct ClientTraceLayer;
+
+impl<S> Layer<S> for ClientTraceLayer {
+    type Service = ClientTraceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientTraceService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClientTraceService<S> {
+    inner: S,
+}
+
+impl<S, ResBody> Service<http::Request<tonic::body::Body>> for ClientTraceService<S>
+where
+    S: Service<http::Request<tonic::body::Body>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<ObservedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<tonic::body::Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        // Child of the caller's current span: the server span when proxying
+        let (service, method) = split_grpc_path(request.uri().path());
+        let span = otel::client_span_with_rpc_attrs(&method, &service);
+        inject_context(&span, request.headers_mut());
+
+        let finished = span.clone();
+        let mut finish = Finish::new(Box::new(move |code: Code, _responses: u64| {
+            otel::record_span_status(&finished, code);
+        }));
+
+        Box::pin(
+            async move {
+                match inner.call(request).await {
+                    Ok(response) => {
+                        let (parts, body) = response.into_parts();
+                        let body = ObservedBody::new(body, &parts.headers, finish);
+                        Ok(http::Response::from_parts(parts, body))
+                    }
+                    Err(e) => {
+                        finish.set_code(Code::Unavailable);
+                        Err(e)
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,125 @@
+//! Replayable request streams for retried calls.
+//!
+//! A client or bidirectional stream can only be sent again if what was
+//! already sent is still around. [`Replay`] takes over the caller's request
+//! body and records its DATA frames, up to a byte limit, as attempts pull
+//! them. Each attempt reads through a [`ReplayBody`]: first the recorded
+//! frames, then new ones from the caller. Once the stream outgrows the limit
+//! the recording is dropped and the call can no longer be retried.
+//!
+//! The limit comes from `GRPC_HELLO_RETRY_BUFFER_BYTES` (default 256 KiB,
+//! gRPC core's per-RPC retry buffer size).
+
+use std::env;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame};
+use tonic::Status;
+use tonic::body::Body;
+
+const DEFAULT_BUFFER_BYTES: usize = 256 * 1024;
+
+/// Per-call replay buffer limit, from `GRPC_HELLO_RETRY_BUFFER_BYTES`.
+pub fn retry_buffer_limit() -> usize {
+    env::var("GRPC_HELLO_RETRY_BUFFER_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BUFFER_BYTES)
+}
+
+struct State {
+    /// The caller's stream; `None` once it has ended.
+    source: Option<Body>,
+    recorded: Vec<Bytes>,
+    recorded_bytes: usize,
+    limit: usize,
+    overflowed: bool,
+    /// Only the latest attempt may read; older ones see the end of stream.
+    generation: u32,
+}
+
+/// A request stream shared by the attempts of one call.
+#[derive(Clone)]
+pub struct Replay(Arc<Mutex<State>>);
+
+impl Replay {
+    pub fn new(source: Body, limit: usize) -> Self {
+        Replay(Arc::new(Mutex::new(State {
+            source: Some(source),
+            recorded: Vec::new(),
+            recorded_bytes: 0,
+            limit,
+            overflowed: false,
+            generation: 0,
+        })))
+    }
+
+    /// The body for a new attempt. Attempts made earlier stop reading.
+    pub fn body(&self) -> ReplayBody {
+        let mut state = self.0.lock().unwrap();
+        state.generation += 1;
+        ReplayBody {
+            state: self.0.clone(),
+            generation: state.generation,
+            position: 0,
+        }
+    }
+
+    /// Whether everything sent so far is still recorded.
+    pub fn is_replayable(&self) -> bool {
+        !self.0.lock().unwrap().overflowed
+    }
+}
+
+/// One attempt's view of a [`Replay`].
+pub struct ReplayBody {
+    state: Arc<Mutex<State>>,
+    generation: u32,
+    position: usize,
+}
+
+impl HttpBody for ReplayBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let mut state = this.state.lock().unwrap();
+        if state.generation != this.generation {
+            return Poll::Ready(None);
+        }
+        if let Some(chunk) = state.recorded.get(this.position) {
+            this.position += 1;
+            return Poll::Ready(Some(Ok(Frame::data(chunk.clone()))));
+        }
+        let Some(source) = state.source.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let polled = Pin::new(source).poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref()
+                    && !state.overflowed
+                {
+                    if state.recorded_bytes + data.len() > state.limit {
+                        state.overflowed = true;
+                        state.recorded = Vec::new();
+                    } else {
+                        state.recorded_bytes += data.len();
+                        state.recorded.push(data.clone());
+                        this.position += 1;
+                    }
+                }
+            }
+            Poll::Ready(None) => state.source = None,
+            _ => {}
+        }
+        polled
+    }
+}
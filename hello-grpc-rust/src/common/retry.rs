@@ -0,0 +1,732 @@
+//! Applies the service config to every client call.
+//!
+//! [`RetryLayer`] sits under the client metrics layer, so metrics and spans
+//! see one call however many attempts it takes. For each call it looks up
+//! the [`MethodConfig`] for the call's path and:
+//!
+//! - caps the deadline at `timeout` and sends what is left of it as
+//!   `grpc-timeout` on every attempt. The call fails with
+//!   `DEADLINE_EXCEEDED` if no response headers arrive in time;
+//! - marks the call [`WaitForReady`] when `waitForReady` is set;
+//! - rejects request messages over `maxRequestMessageBytes`, measured as
+//!   sent (after compression, like tonic's own limit), with
+//!   `RESOURCE_EXHAUSTED`;
+//! - resets a request stream marked with [`AbortStream`] once its
+//!   [`AbortHandle`] is dropped, where ending the stream would half-close it
+//!   and let the server finish the call;
+//! - retries per `retryPolicy` while the call has not committed, i.e. the
+//!   attempt failed on the transport or with a trailers-only response. The
+//!   n-th retry waits a random delay up to
+//!   `min(initialBackoff * backoffMultiplier^(n-1), maxBackoff)`, and
+//!   carries `grpc-previous-rpc-attempts`. A `grpc-retry-pushback-ms` from
+//!   the server replaces that delay and restarts the backoff; a negative or
+//!   malformed one stops the retries;
+//! - or, with `hedgingPolicy`, sends a new copy of the call every
+//!   `hedgingDelay` until `maxAttempts` copies are out, or at once when one
+//!   fails with a `nonFatalStatusCodes` code. The first copy to commit or
+//!   fail fatally wins; the others are cancelled. Extra copies and the
+//!   winning attempt are counted as `grpc_client_hedges_total` and
+//!   `grpc_client_hedge_wins_total{attempt}`.
+//!
+//! With `retryThrottling`, every channel built by `conn.rs` holds one
+//! [`RetryThrottle`] for all of its calls. Retryable failures and pushbacks
+//! take a token, successful attempts give back `tokenRatio`, and no
+//! retry or hedge starts while half the tokens or more are gone. An outage
+//! then costs about one attempt per call instead of `maxAttempts`. Retries and
+//! throttled retries are counted as `grpc_client_retries_total` and
+//! `grpc_client_retries_throttled_total`.
+//!
+//! Client and bidirectional streams are retried the same way, but never
+//! hedged: each attempt replays the messages sent so far from a [`Replay`]
+//! buffer before taking new ones from the caller. A stream that outgrows
+//! `GRPC_HELLO_RETRY_BUFFER_BYTES` (256 KiB by default) is no longer
+//! buffered and is not retried after that.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::Shared;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::{BodyExt, Full};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use pin_project_lite::pin_project;
+use prost::Message;
+use rand::Rng;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tonic::body::Body;
+use tonic::codegen::StdError;
+use tonic::{Code, Status};
+use tower::{Layer, Service, ServiceExt};
+
+use crate::common::FILE_DESCRIPTOR_SET;
+use crate::common::balancer::WaitForReady;
+use crate::common::deadline::{grpc_timeout_value, parse_grpc_timeout};
+use crate::common::framing::{MessageCounter, split_grpc_path, status_from_headers};
+use crate::common::metrics::REGISTRY;
+use crate::common::replay::{Replay, retry_buffer_limit};
+use crate::common::service_config::{
+    HedgingPolicy, MethodConfig, RetryPolicy, RetryThrottling, ServiceConfig,
+};
+
+const GRPC_TIMEOUT: &str = "grpc-timeout";
+const PREVIOUS_ATTEMPTS: &str = "grpc-previous-rpc-attempts";
+const RETRY_PUSHBACK: &str = "grpc-retry-pushback-ms";
+
+const RETRIES_TOTAL: &str = "grpc_client_retries_total";
+const THROTTLED_TOTAL: &str = "grpc_client_retries_throttled_total";
+const HEDGES_TOTAL: &str = "grpc_client_hedges_total";
+const HEDGE_WINS_TOTAL: &str = "grpc_client_hedge_wins_total";
+
+/// Paths of the methods that take a request stream, from the compiled
+/// descriptor set.
+static REQUEST_STREAMING: Lazy<HashSet<String>> = Lazy::new(|| {
+    let Ok(descriptors) = prost_types::FileDescriptorSet::decode(FILE_DESCRIPTOR_SET) else {
+        return HashSet::new();
+    };
+    let mut paths = HashSet::new();
+    for file in &descriptors.file {
+        for service in &file.service {
+            let service_name = match file.package() {
+                "" => service.name().to_string(),
+                package => format!("{}.{}", package, service.name()),
+            };
+            for method in service.method.iter().filter(|m| m.client_streaming()) {
+                paths.insert(format!("/{}/{}", service_name, method.name()));
+            }
+        }
+    }
+    paths
+});
+
+/// A6 retry token bucket, counted in thousandths of a token.
+pub struct RetryThrottle {
+    max_tokens: u64,
+    token_ratio: u64,
+    tokens: Mutex<u64>,
+}
+
+impl RetryThrottle {
+    pub fn new(throttling: RetryThrottling) -> Self {
+        let max_tokens = u64::from(throttling.max_tokens) * 1000;
+        RetryThrottle {
+            max_tokens,
+            // A6 keeps three decimal places of tokenRatio
+            token_ratio: (throttling.token_ratio * 1000.0) as u64,
+            tokens: Mutex::new(max_tokens),
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = tokens.saturating_sub(1000);
+    }
+
+    pub fn record_success(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.token_ratio).min(self.max_tokens);
+    }
+
+    /// Retries are allowed while more than half of the tokens are left.
+    pub fn allows_retry(&self) -> bool {
+        *self.tokens.lock().unwrap() * 2 > self.max_tokens
+    }
+}
+
+#[derive(Clone)]
+pub struct RetryLayer {
+    config: Arc<ServiceConfig>,
+    throttle: Option<Arc<RetryThrottle>>,
+    buffer_limit: usize,
+}
+
+impl RetryLayer {
+    /// Every service built from this layer shares one retry throttle.
+    pub fn new(config: Arc<ServiceConfig>) -> Self {
+        let throttle = config
+            .retry_throttling()
+            .map(|throttling| Arc::new(RetryThrottle::new(throttling)));
+        RetryLayer {
+            config,
+            throttle,
+            buffer_limit: retry_buffer_limit(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = RetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryService {
+            inner,
+            config: self.config.clone(),
+            throttle: self.throttle.clone(),
+            buffer_limit: self.buffer_limit,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RetryService<S> {
+    inner: S,
+    config: Arc<ServiceConfig>,
+    throttle: Option<Arc<RetryThrottle>>,
+    buffer_limit: usize,
+}
+
+impl<S> Service<http::Request<Body>> for RetryService<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>, Error = StdError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = http::Response<Body>;
+    type Error = StdError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let config = self
+            .config
+            .method_config(request.uri().path())
+            .unwrap_or_default();
+        let inner = self.inner.clone();
+        let throttle = self.throttle.clone();
+        let buffer_limit = self.buffer_limit;
+        Box::pin(async move {
+            call_with_config(inner, request, &config, throttle.as_deref(), buffer_limit).await
+        })
+    }
+}
+
+/// How an attempt ended, as far as retrying is concerned.
+struct Outcome {
+    /// The failure code, or `None` once the call has committed.
+    failed: Option<Code>,
+    /// The server's `grpc-retry-pushback-ms`: `Some(None)` if malformed.
+    pushback: Option<Option<Duration>>,
+}
+
+impl Outcome {
+    fn of(result: &Result<http::Response<Body>, StdError>) -> Self {
+        match result {
+            // A response with headers has committed the call; only failures
+            // before that (transport errors, trailers-only) may be retried
+            Ok(response) => Outcome {
+                failed: status_from_headers(response.headers()).filter(|c| *c != Code::Ok),
+                pushback: response.headers().get(RETRY_PUSHBACK).map(|value| {
+                    value
+                        .to_str()
+                        .ok()
+                        .and_then(|ms| ms.parse::<u64>().ok())
+                        .map(Duration::from_millis)
+                }),
+            },
+            Err(error) => Outcome {
+                failed: Some(
+                    error
+                        .downcast_ref::<Status>()
+                        .map_or(Code::Unavailable, Status::code),
+                ),
+                pushback: None,
+            },
+        }
+    }
+
+    /// Feeds the attempt into the channel's retry throttle. Failures with a
+    /// code the policy would retry (or hedge past) take a token.
+    fn record(&self, throttle: Option<&RetryThrottle>, retryable: impl Fn(Code) -> bool) {
+        let Some(throttle) = throttle else {
+            return;
+        };
+        match self.failed {
+            None => throttle.record_success(),
+            Some(code) if self.pushback.is_some() || retryable(code) => throttle.record_failure(),
+            Some(_) => {}
+        }
+    }
+}
+
+async fn call_with_config<S>(
+    inner: S,
+    request: http::Request<Body>,
+    config: &MethodConfig,
+    throttle: Option<&RetryThrottle>,
+    buffer_limit: usize,
+) -> Result<http::Response<Body>, StdError>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>, Error = StdError> + Clone,
+{
+    let (mut parts, body) = request.into_parts();
+    let deadline = call_deadline(&parts.headers, config.timeout);
+    if config.wait_for_ready == Some(true) {
+        parts.extensions.insert(WaitForReady);
+    }
+
+    let streaming = REQUEST_STREAMING.contains(parts.uri.path());
+    // An oversized message resets the stream, which the caller would only
+    // see as a protocol error; the limit is reported instead
+    let rejected = Arc::new(Mutex::new(None));
+    let source = if streaming {
+        let body = match parts.extensions.get::<AbortStream>() {
+            Some(abort) => Body::new(AbortableBody {
+                inner: body,
+                abort: abort.clone(),
+            }),
+            None => body,
+        };
+        let body = match config.max_request_message_bytes {
+            Some(limit) => Body::new(LimitedBody::new(body, limit, rejected.clone())),
+            None => body,
+        };
+        Source::Stream(Replay::new(body, buffer_limit))
+    } else {
+        let message = body.collect().await?.to_bytes();
+        if let Some(limit) = config.max_request_message_bytes {
+            let mut counter = MessageCounter::default();
+            counter.feed(&message);
+            if counter.largest() > limit {
+                return Err(too_large(counter.largest(), limit).into());
+            }
+        }
+        Source::Message(message)
+    };
+
+    let attempts = Attempts {
+        parts,
+        source,
+        rejected: rejected.clone(),
+        deadline,
+    };
+    let call = async {
+        match (&config.retry_policy, &config.hedging_policy) {
+            // Hedged copies would all read the one request stream
+            (_, Some(hedging)) if !streaming => hedge(inner, &attempts, hedging, throttle).await,
+            (retry, _) => self::retry(inner, &attempts, retry.as_ref(), throttle).await,
+        }
+    };
+    let result = with_deadline(deadline, call).await;
+    if let Some(status) = rejected.lock().unwrap().take() {
+        return Err(status.into());
+    }
+    if !streaming {
+        return result;
+    }
+    result.map(|response| {
+        response.map(|body| {
+            Body::new(body.map_err(move |status| rejected.lock().unwrap().take().unwrap_or(status)))
+        })
+    })
+}
+
+/// Where each attempt's request body comes from.
+enum Source {
+    /// The single request message, sent again as is.
+    Message(Bytes),
+    /// A request stream, replayed from its buffer on each attempt.
+    Stream(Replay),
+}
+
+/// Builds the attempts of a call from its buffered request.
+struct Attempts {
+    parts: http::request::Parts,
+    source: Source,
+    /// Set once a request message went over `maxRequestMessageBytes`.
+    rejected: Arc<Mutex<Option<Status>>>,
+    deadline: Option<Instant>,
+}
+
+impl Attempts {
+    /// Request for attempt number `attempt` (1-based), carrying what is left
+    /// of the deadline and, after the first, `grpc-previous-rpc-attempts`.
+    fn request(&self, attempt: u32) -> Result<http::Request<Body>, Status> {
+        let mut headers = self.parts.headers.clone();
+        set_timeout(&mut headers, self.deadline)?;
+        if attempt > 1 {
+            headers.insert(PREVIOUS_ATTEMPTS, http::HeaderValue::from(attempt - 1));
+        }
+        let body = match &self.source {
+            Source::Message(message) => Body::new(Full::new(message.clone())),
+            Source::Stream(replay) => Body::new(replay.body()),
+        };
+        let mut request = http::Request::new(body);
+        *request.method_mut() = self.parts.method.clone();
+        *request.uri_mut() = self.parts.uri.clone();
+        *request.version_mut() = self.parts.version;
+        *request.headers_mut() = headers;
+        *request.extensions_mut() = self.parts.extensions.clone();
+        Ok(request)
+    }
+
+    /// Whether another attempt can send the same request again: the stream
+    /// still fits in its replay buffer and no message was rejected.
+    fn can_retry(&self) -> bool {
+        let replayable = match &self.source {
+            Source::Message(_) => true,
+            Source::Stream(replay) => replay.is_replayable(),
+        };
+        replayable && self.rejected.lock().unwrap().is_none()
+    }
+
+    fn path(&self) -> &str {
+        self.parts.uri.path()
+    }
+
+    /// `grpc_service` / `grpc_method` labels for this call.
+    fn labels(&self) -> (String, String) {
+        split_grpc_path(self.path())
+    }
+}
+
+/// Sends attempts one after another per `policy` until one commits.
+async fn retry<S>(
+    inner: S,
+    attempts: &Attempts,
+    policy: Option<&RetryPolicy>,
+    throttle: Option<&RetryThrottle>,
+) -> Result<http::Response<Body>, StdError>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>, Error = StdError> + Clone,
+{
+    let path = attempts.path();
+    let mut attempt = 1;
+    // Retry number the next backoff is computed for; pushback restarts it
+    let mut backoff_step = 1;
+    loop {
+        let request = attempts.request(attempt)?;
+        let result = inner.clone().oneshot(request).await;
+        let outcome = Outcome::of(&result);
+        outcome.record(throttle, |code| {
+            policy.is_some_and(|p| p.is_retryable(code))
+        });
+        let Some(code) = outcome.failed else {
+            return result;
+        };
+        let Some(policy) = policy.filter(|p| p.is_retryable(code) && attempt < p.max_attempts)
+        else {
+            return result;
+        };
+        if !attempts.can_retry() {
+            debug!(
+                "{} attempt {} failed with {:?}, request stream outgrew the retry buffer",
+                path, attempt, code
+            );
+            return result;
+        }
+        let backoff = match outcome.pushback {
+            Some(Some(pushback)) => {
+                backoff_step = 1;
+                pushback
+            }
+            // The server asked not to retry
+            Some(None) => return result,
+            None => {
+                let ceiling = policy.backoff_ceiling(backoff_step);
+                backoff_step += 1;
+                ceiling.mul_f64(rand::rng().random_range(0.0..=1.0))
+            }
+        };
+        if attempts
+            .deadline
+            .is_some_and(|deadline| Instant::now() + backoff >= deadline)
+        {
+            return result;
+        }
+        let (service, method) = attempts.labels();
+        let labels = [
+            ("grpc_service", service.as_str()),
+            ("grpc_method", method.as_str()),
+        ];
+        if throttle.is_some_and(|throttle| !throttle.allows_retry()) {
+            warn!(
+                "{} attempt {}/{} failed with {:?}, retry throttled",
+                path, attempt, policy.max_attempts, code
+            );
+            REGISTRY.inc_counter(
+                THROTTLED_TOTAL,
+                "Retries not attempted because the channel's retry tokens ran low.",
+                &labels,
+            );
+            return result;
+        }
+        warn!(
+            "{} attempt {}/{} failed with {:?}, retrying in {:?}",
+            path, attempt, policy.max_attempts, code, backoff
+        );
+        REGISTRY.inc_counter(
+            RETRIES_TOTAL,
+            "Retry attempts made by the client after a failed attempt.",
+            &labels,
+        );
+        drop(result);
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// Sends a new copy of the call every `hedgingDelay` (or as soon as one
+/// fails with a non-fatal code) until one commits or fails fatally. That one
+/// wins and the copies still in flight are dropped, which resets their
+/// streams.
+async fn hedge<S>(
+    inner: S,
+    attempts: &Attempts,
+    policy: &HedgingPolicy,
+    throttle: Option<&RetryThrottle>,
+) -> Result<http::Response<Body>, StdError>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>, Error = StdError> + Clone,
+{
+    let (service, method) = attempts.labels();
+    let labels = [
+        ("grpc_service", service.as_str()),
+        ("grpc_method", method.as_str()),
+    ];
+    let mut in_flight = FuturesUnordered::new();
+    let mut sent = 0;
+    let mut next_at = Some(Instant::now());
+    let mut last_failure = None;
+    loop {
+        if let Some(at) = next_at
+            && at <= Instant::now()
+        {
+            if sent > 0 && throttle.is_some_and(|throttle| !throttle.allows_retry()) {
+                REGISTRY.inc_counter(
+                    THROTTLED_TOTAL,
+                    "Retries not attempted because the channel's retry tokens ran low.",
+                    &labels,
+                );
+                next_at = None;
+            } else {
+                sent += 1;
+                let request = attempts.request(sent)?;
+                let attempt = sent;
+                in_flight.push(inner.clone().oneshot(request).map(move |r| (attempt, r)));
+                if sent > 1 {
+                    debug!(
+                        "{} sent hedge {}/{}",
+                        attempts.path(),
+                        sent,
+                        policy.max_attempts
+                    );
+                    REGISTRY.inc_counter(
+                        HEDGES_TOTAL,
+                        "Hedged copies of a call sent after the first attempt.",
+                        &labels,
+                    );
+                }
+                next_at =
+                    (sent < policy.max_attempts).then(|| Instant::now() + policy.hedging_delay);
+            }
+        }
+        if in_flight.is_empty() && next_at.is_none() {
+            return last_failure
+                .unwrap_or_else(|| Err(Status::unavailable("no hedged attempt was sent").into()));
+        }
+
+        tokio::select! {
+            Some((attempt, result)) = in_flight.next() => {
+                let outcome = Outcome::of(&result);
+                outcome.record(throttle, |code| policy.is_non_fatal(code));
+                match outcome.failed {
+                    Some(code) if policy.is_non_fatal(code) => {
+                        debug!("{} hedge {} failed with {:?}", attempts.path(), attempt, code);
+                        if sent < policy.max_attempts {
+                            next_at = match outcome.pushback {
+                                Some(Some(pushback)) => Some(Instant::now() + pushback),
+                                // The server asked not to hedge any further
+                                Some(None) => None,
+                                None => Some(Instant::now()),
+                            };
+                        }
+                        last_failure = Some(result);
+                    }
+                    _ => {
+                        let attempt = attempt.to_string();
+                        REGISTRY.inc_counter(
+                            HEDGE_WINS_TOTAL,
+                            "Hedged calls by the attempt whose response was used.",
+                            &[labels[0], labels[1], ("attempt", &attempt)],
+                        );
+                        return result;
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(next_at.unwrap_or_else(Instant::now)), if next_at.is_some() => {}
+        }
+    }
+}
+
+/// The earlier of the caller's `grpc-timeout` and the configured timeout.
+fn call_deadline(headers: &http::HeaderMap, timeout: Option<Duration>) -> Option<Instant> {
+    let requested = headers
+        .get(GRPC_TIMEOUT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_grpc_timeout);
+    let timeout = match (requested, timeout) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    timeout.map(|timeout| Instant::now() + timeout)
+}
+
+/// Sends what is left of `deadline` as `grpc-timeout`.
+fn set_timeout(headers: &mut http::HeaderMap, deadline: Option<Instant>) -> Result<(), Status> {
+    let Some(deadline) = deadline else {
+        return Ok(());
+    };
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(Status::deadline_exceeded(
+            "deadline exceeded before the call was sent",
+        ));
+    }
+    if let Ok(value) = grpc_timeout_value(remaining).parse() {
+        headers.insert(GRPC_TIMEOUT, value);
+    }
+    Ok(())
+}
+
+async fn with_deadline<F>(
+    deadline: Option<Instant>,
+    call: F,
+) -> Result<http::Response<Body>, StdError>
+where
+    F: Future<Output = Result<http::Response<Body>, StdError>>,
+{
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, call)
+            .await
+            .unwrap_or_else(|_| {
+                Err(Status::deadline_exceeded("deadline exceeded waiting for the response").into())
+            }),
+        None => call.await,
+    }
+}
+
+fn too_large(size: usize, limit: usize) -> Status {
+    Status::resource_exhausted(format!(
+        "request message of {} bytes exceeds maxRequestMessageBytes of {}",
+        size, limit
+    ))
+}
+
+pin_project! {
+    /// Request stream body that fails once a message exceeds `limit` bytes,
+    /// before any of it is sent, and leaves the error in `rejected`.
+    struct LimitedBody<B> {
+        #[pin]
+        inner: B,
+        counter: MessageCounter,
+        limit: usize,
+        rejected: Arc<Mutex<Option<Status>>>,
+    }
+}
+
+impl<B> LimitedBody<B> {
+    fn new(inner: B, limit: usize, rejected: Arc<Mutex<Option<Status>>>) -> Self {
+        LimitedBody {
+            inner,
+            counter: MessageCounter::default(),
+            limit,
+            rejected,
+        }
+    }
+}
+
+impl<B: HttpBody<Data = Bytes, Error = Status>> HttpBody for LimitedBody<B> {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let polled = this.inner.poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled
+            && let Some(data) = frame.data_ref()
+        {
+            this.counter.feed(data);
+            if this.counter.largest() > *this.limit {
+                let status = too_large(this.counter.largest(), *this.limit);
+                *this.rejected.lock().unwrap() = Some(status.clone());
+                return Poll::Ready(Some(Err(status)));
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Request extension for client and bidirectional streams: once the paired
+/// [`AbortHandle`] is dropped the request stream fails, so the server sees
+/// the stream reset rather than half-closed.
+#[derive(Clone)]
+pub struct AbortStream(Shared<oneshot::Receiver<()>>);
+
+/// Aborts its [`AbortStream`] when dropped.
+pub struct AbortHandle {
+    _release: oneshot::Sender<()>,
+}
+
+impl AbortStream {
+    pub fn new() -> (Self, AbortHandle) {
+        let (tx, rx) = oneshot::channel();
+        (AbortStream(rx.shared()), AbortHandle { _release: tx })
+    }
+}
+
+pin_project! {
+    /// Request stream body that fails once its [`AbortStream`] fires.
+    struct AbortableBody<B> {
+        #[pin]
+        inner: B,
+        abort: AbortStream,
+    }
+}
+
+impl<B: HttpBody<Data = Bytes, Error = Status>> HttpBody for AbortableBody<B> {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if this.abort.0.poll_unpin(cx).is_ready() {
+            return Poll::Ready(Some(Err(Status::cancelled("request stream aborted"))));
+        }
+        this.inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,382 @@
+//! `grpc.service_config` support for clients built by `conn.rs`.
+//!
+//! The config uses the standard JSON format
+//! (https://github.com/grpc/grpc/blob/master/doc/service_config.md). Each
+//! `methodConfig` entry applies to the methods listed in its `name`, and
+//! supports `retryPolicy` or `hedgingPolicy`, `timeout`, `waitForReady` and
+//! `maxRequestMessageBytes`. The top-level `retryThrottling` limits retries
+//! per channel; it is off unless the config sets it, e.g.
+//! `"retryThrottling": {"maxTokens": 10, "tokenRatio": 0.1}` as in A6.
+//! Other fields are ignored. The config is read from, in order:
+//!
+//! - `GRPC_HELLO_SERVICE_CONFIG`: the JSON itself.
+//! - `GRPC_HELLO_SERVICE_CONFIG_FILE`: a path to a JSON file.
+//! - [`DEFAULT_SERVICE_CONFIG`]: the A6 retry policy shared with the Go and
+//!   Java clients.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tonic::Code;
+
+use crate::common::framing::split_grpc_path;
+
+/// Retry policy for `hello.LandingService`, following gRPC A6 client
+/// retries: https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+pub const DEFAULT_SERVICE_CONFIG: &str = r#"{
+  "methodConfig": [{
+    "name": [{"service": "hello.LandingService"}],
+    "waitForReady": true,
+    "retryPolicy": {
+      "maxAttempts": 4,
+      "initialBackoff": "0.1s",
+      "maxBackoff": "1s",
+      "backoffMultiplier": 2.0,
+      "retryableStatusCodes": ["UNAVAILABLE"]
+    }
+  }]
+}"#;
+
+/// gRPC caps `maxAttempts` at 5 whatever the config says.
+const MAX_ATTEMPTS_LIMIT: u32 = 5;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawServiceConfig {
+    #[serde(default)]
+    method_config: Vec<RawMethodConfig>,
+    retry_throttling: Option<RawRetryThrottling>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawRetryThrottling {
+    max_tokens: u32,
+    token_ratio: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawMethodConfig {
+    #[serde(default)]
+    name: Vec<RawName>,
+    wait_for_ready: Option<bool>,
+    timeout: Option<String>,
+    max_request_message_bytes: Option<u64>,
+    retry_policy: Option<RawRetryPolicy>,
+    hedging_policy: Option<RawHedgingPolicy>,
+}
+
+#[derive(Deserialize)]
+struct RawName {
+    #[serde(default)]
+    service: String,
+    #[serde(default)]
+    method: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawRetryPolicy {
+    max_attempts: u32,
+    initial_backoff: String,
+    max_backoff: String,
+    backoff_multiplier: f64,
+    retryable_status_codes: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawHedgingPolicy {
+    max_attempts: u32,
+    hedging_delay: Option<String>,
+    #[serde(default)]
+    non_fatal_status_codes: Vec<serde_json::Value>,
+}
+
+/// A6 retry parameters for one method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub backoff_multiplier: f64,
+    pub retryable_status_codes: Vec<Code>,
+}
+
+/// A6 hedging parameters for one method: up to `max_attempts` copies of a
+/// call, one every `hedging_delay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HedgingPolicy {
+    pub max_attempts: u32,
+    pub hedging_delay: Duration,
+    pub non_fatal_status_codes: Vec<Code>,
+}
+
+/// A6 `retryThrottling` parameters: each channel keeps a bucket of
+/// `max_tokens` tokens, and retries stop while it is half empty or less.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryThrottling {
+    pub max_tokens: u32,
+    pub token_ratio: f64,
+}
+
+/// What the service config says about one method.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodConfig {
+    pub wait_for_ready: Option<bool>,
+    pub timeout: Option<Duration>,
+    pub max_request_message_bytes: Option<usize>,
+    pub retry_policy: Option<RetryPolicy>,
+    pub hedging_policy: Option<HedgingPolicy>,
+}
+
+/// A parsed service config, looked up by gRPC path.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceConfig {
+    /// Keyed by `(service, method)`; an empty method is the service default
+    /// and an empty service the config-wide default.
+    methods: HashMap<(String, String), Arc<MethodConfig>>,
+    retry_throttling: Option<RetryThrottling>,
+}
+
+impl ServiceConfig {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let raw: RawServiceConfig =
+            serde_json::from_str(json).map_err(|e| format!("invalid service config: {}", e))?;
+        let mut methods = HashMap::new();
+        for entry in raw.method_config {
+            let config = Arc::new(MethodConfig::from_raw(&entry)?);
+            for name in &entry.name {
+                if name.service.is_empty() && !name.method.is_empty() {
+                    return Err(format!(
+                        "service config name with method {:?} has no service",
+                        name.method
+                    ));
+                }
+                let key = (name.service.clone(), name.method.clone());
+                if methods.insert(key, config.clone()).is_some() {
+                    return Err(format!(
+                        "duplicate service config name {}/{}",
+                        name.service, name.method
+                    ));
+                }
+            }
+        }
+        let retry_throttling = raw
+            .retry_throttling
+            .map(|raw| {
+                if raw.max_tokens == 0 || raw.max_tokens > 1000 {
+                    return Err("retryThrottling.maxTokens must be in (0, 1000]".to_string());
+                }
+                if raw.token_ratio.is_nan() || raw.token_ratio <= 0.0 {
+                    return Err("retryThrottling.tokenRatio must be greater than zero".to_string());
+                }
+                Ok(RetryThrottling {
+                    max_tokens: raw.max_tokens,
+                    token_ratio: raw.token_ratio,
+                })
+            })
+            .transpose()?;
+        Ok(ServiceConfig {
+            methods,
+            retry_throttling,
+        })
+    }
+
+    pub fn retry_throttling(&self) -> Option<RetryThrottling> {
+        self.retry_throttling
+    }
+
+    /// The config for `/package.Service/Method`: an exact match first, then
+    /// the service default, then the config-wide default.
+    pub fn method_config(&self, path: &str) -> Option<Arc<MethodConfig>> {
+        let (service, method) = split_grpc_path(path);
+        [(service.clone(), method), (service, String::new())]
+            .iter()
+            .chain(std::iter::once(&(String::new(), String::new())))
+            .find_map(|key| self.methods.get(key))
+            .cloned()
+    }
+}
+
+impl MethodConfig {
+    fn from_raw(raw: &RawMethodConfig) -> Result<Self, String> {
+        let timeout = raw.timeout.as_deref().map(parse_duration).transpose()?;
+        if raw.retry_policy.is_some() && raw.hedging_policy.is_some() {
+            return Err("retryPolicy and hedgingPolicy are mutually exclusive".to_string());
+        }
+        let retry_policy = raw
+            .retry_policy
+            .as_ref()
+            .map(RetryPolicy::from_raw)
+            .transpose()?;
+        let hedging_policy = raw
+            .hedging_policy
+            .as_ref()
+            .map(HedgingPolicy::from_raw)
+            .transpose()?;
+        Ok(MethodConfig {
+            wait_for_ready: raw.wait_for_ready,
+            timeout,
+            max_request_message_bytes: raw.max_request_message_bytes.map(|bytes| bytes as usize),
+            retry_policy,
+            hedging_policy,
+        })
+    }
+
+    /// Whether a failure with `code` may be followed by another attempt,
+    /// under either policy.
+    pub fn is_retryable(&self, code: Code) -> bool {
+        self.retry_policy
+            .as_ref()
+            .is_some_and(|policy| policy.is_retryable(code))
+            || self
+                .hedging_policy
+                .as_ref()
+                .is_some_and(|policy| policy.is_non_fatal(code))
+    }
+}
+
+impl RetryPolicy {
+    fn from_raw(raw: &RawRetryPolicy) -> Result<Self, String> {
+        if raw.max_attempts < 2 {
+            return Err("retryPolicy.maxAttempts must be at least 2".to_string());
+        }
+        let initial_backoff = parse_duration(&raw.initial_backoff)?;
+        let max_backoff = parse_duration(&raw.max_backoff)?;
+        if initial_backoff.is_zero() || max_backoff.is_zero() {
+            return Err("retryPolicy backoffs must be greater than zero".to_string());
+        }
+        if raw.backoff_multiplier.is_nan() || raw.backoff_multiplier <= 0.0 {
+            return Err("retryPolicy.backoffMultiplier must be greater than zero".to_string());
+        }
+        let retryable_status_codes = raw
+            .retryable_status_codes
+            .iter()
+            .map(parse_code)
+            .collect::<Result<Vec<_>, _>>()?;
+        if retryable_status_codes.is_empty() {
+            return Err("retryPolicy.retryableStatusCodes must not be empty".to_string());
+        }
+        Ok(RetryPolicy {
+            max_attempts: raw.max_attempts.min(MAX_ATTEMPTS_LIMIT),
+            initial_backoff,
+            max_backoff,
+            backoff_multiplier: raw.backoff_multiplier,
+            retryable_status_codes,
+        })
+    }
+
+    pub fn is_retryable(&self, code: Code) -> bool {
+        self.retryable_status_codes.contains(&code)
+    }
+
+    /// Upper bound of the randomized delay before retry number `retry`
+    /// (1-based): `initialBackoff * backoffMultiplier^(retry-1)`, capped at
+    /// `maxBackoff`.
+    pub fn backoff_ceiling(&self, retry: u32) -> Duration {
+        let factor = self.backoff_multiplier.powi(retry.saturating_sub(1) as i32);
+        let ceiling = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(ceiling.min(self.max_backoff.as_secs_f64()))
+    }
+}
+
+impl HedgingPolicy {
+    fn from_raw(raw: &RawHedgingPolicy) -> Result<Self, String> {
+        if raw.max_attempts < 2 {
+            return Err("hedgingPolicy.maxAttempts must be at least 2".to_string());
+        }
+        let hedging_delay = raw
+            .hedging_delay
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?
+            .unwrap_or_default();
+        let non_fatal_status_codes = raw
+            .non_fatal_status_codes
+            .iter()
+            .map(parse_code)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(HedgingPolicy {
+            max_attempts: raw.max_attempts.min(MAX_ATTEMPTS_LIMIT),
+            hedging_delay,
+            non_fatal_status_codes,
+        })
+    }
+
+    /// A non-fatal failure lets the other copies carry on; any other
+    /// outcome ends the call.
+    pub fn is_non_fatal(&self, code: Code) -> bool {
+        self.non_fatal_status_codes.contains(&code)
+    }
+}
+
+/// Parses a protobuf JSON duration such as `1s`, `0.1s` or `.25s`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    value
+        .strip_suffix('s')
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("invalid duration {:?}", value))
+}
+
+/// Parses a status code given by name (`"UNAVAILABLE"`) or number (`14`).
+pub fn parse_code(value: &serde_json::Value) -> Result<Code, String> {
+    let code = match value {
+        serde_json::Value::Number(number) => number
+            .as_i64()
+            .and_then(|n| i32::try_from(n).ok())
+            .filter(|n| (0..=16).contains(n))
+            .map(Code::from_i32),
+        serde_json::Value::String(name) => match name.to_ascii_uppercase().as_str() {
+            "OK" => Some(Code::Ok),
+            "CANCELLED" => Some(Code::Cancelled),
+            "UNKNOWN" => Some(Code::Unknown),
+            "INVALID_ARGUMENT" => Some(Code::InvalidArgument),
+            "DEADLINE_EXCEEDED" => Some(Code::DeadlineExceeded),
+            "NOT_FOUND" => Some(Code::NotFound),
+            "ALREADY_EXISTS" => Some(Code::AlreadyExists),
+            "PERMISSION_DENIED" => Some(Code::PermissionDenied),
+            "RESOURCE_EXHAUSTED" => Some(Code::ResourceExhausted),
+            "FAILED_PRECONDITION" => Some(Code::FailedPrecondition),
+            "ABORTED" => Some(Code::Aborted),
+            "OUT_OF_RANGE" => Some(Code::OutOfRange),
+            "UNIMPLEMENTED" => Some(Code::Unimplemented),
+            "INTERNAL" => Some(Code::Internal),
+            "UNAVAILABLE" => Some(Code::Unavailable),
+            "DATA_LOSS" => Some(Code::DataLoss),
+            "UNAUTHENTICATED" => Some(Code::Unauthenticated),
+            _ => None,
+        },
+        _ => None,
+    };
+    code.ok_or_else(|| format!("invalid status code {}", value))
+}
+
+static LOADED: Lazy<Result<Arc<ServiceConfig>, String>> = Lazy::new(|| {
+    let (source, json) = if let Ok(json) = env::var("GRPC_HELLO_SERVICE_CONFIG") {
+        ("GRPC_HELLO_SERVICE_CONFIG".to_string(), json)
+    } else if let Ok(path) = env::var("GRPC_HELLO_SERVICE_CONFIG_FILE") {
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read service config {}: {}", path, e))?;
+        (path, json)
+    } else {
+        return ServiceConfig::parse(DEFAULT_SERVICE_CONFIG).map(Arc::new);
+    };
+    let config = ServiceConfig::parse(&json).map_err(|e| format!("{}: {}", source, e))?;
+    info!("Using service config from {}", source);
+    Ok(Arc::new(config))
+});
+
+/// The service config for this process, read once.
+pub fn load() -> Result<Arc<ServiceConfig>, String> {
+    LOADED.clone()
+}
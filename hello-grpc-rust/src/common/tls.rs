@@ -0,0 +1,518 @@
+//! TLS material that reloads without a restart.
+//!
+//! Certificates under `CERT_BASE_PATH` rotate every few days. A
+//! [`Reloadable`] holds the rustls config built from them and builds it again
+//! whenever one of the files changes (see watch.rs). Only new connections
+//! pick up a new config; established ones keep the one they were made with.
+//! A reload that fails to read or parse the files keeps the previous config.
+//! Every load logs when the certificate expires.
+//!
+//! Clients connect through a [`TlsConnector`], which hands each new
+//! connection the current client config, so reconnects present the rotated
+//! client certificate.
+//!
+//! What the client trusts and presents comes from [`ClientTlsSettings`]:
+//!
+//! | Variable | Meaning | Default |
+//! |---|---|---|
+//! | `GRPC_HELLO_TLS_SERVER_NAME` | Name (SNI) the server certificate is checked against | `hello.grpc.io` |
+//! | `GRPC_HELLO_TLS_ROOTS` | `ca` (the private CA under `CERT_BASE_PATH`), `webpki` (bundled Mozilla roots) or `system` (the OS bundle, or `SSL_CERT_FILE`) | `ca` |
+//! | `GRPC_HELLO_TLS_CLIENT_CERT` | `N` connects without a client certificate | `Y` |
+//! | `GRPC_HELLO_TLS_INSECURE_SKIP_VERIFY` | `Y` accepts any server certificate; lab use only | `N` |
+//! | `GRPC_HELLO_TLS_PIN_SHA256` | Comma-separated `sha256/<base64>` hashes of the server's public key (SPKI); the certificate must also match one of them | No pinning |
+
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Utc};
+use http::Uri;
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tower::Service;
+use x509_parser::parse_x509_certificate;
+
+use crate::common::cert_expiry::MONITOR;
+use crate::common::trans;
+use crate::common::watch::watch_files;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+/// The name the sample and generated server certificates are issued for.
+pub const DEFAULT_SERVER_NAME: &str = "hello.grpc.io";
+/// Where Linux distributions keep the system CA bundle.
+const SYSTEM_CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e))
+}
+
+/// The process's crypto provider, or ring when none was installed.
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    CryptoProvider::get_default()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()))
+}
+
+/// Reads every certificate of a PEM file.
+pub fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let pem = fs::read(path)?;
+    let certs = CertificateDer::pem_slice_iter(&pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(path, e))?;
+    if certs.is_empty() {
+        return Err(invalid(path, "no certificate found"));
+    }
+    Ok(certs)
+}
+
+/// Reads the private key of a PEM file (PKCS#1, PKCS#8 or SEC1).
+pub fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let pem = fs::read(path)?;
+    PrivateKeyDer::from_pem_slice(&pem).map_err(|e| invalid(path, e))
+}
+
+/// Reads a CA bundle into a root store.
+pub fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| invalid(path, e))?;
+    }
+    Ok(roots)
+}
+
+/// Reads the system CA bundle: `SSL_CERT_FILE`, or the first bundle found
+/// at the usual locations. Certificates webpki cannot parse are skipped.
+pub fn load_system_roots() -> io::Result<RootCertStore> {
+    let path = match env::var("SSL_CERT_FILE") {
+        Ok(file) => PathBuf::from(file),
+        Err(_) => SYSTEM_CA_BUNDLES
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no system CA bundle found, set SSL_CERT_FILE",
+                )
+            })?,
+    };
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(load_certs(&path)?);
+    if roots.is_empty() {
+        return Err(invalid(&path, "no usable root certificate"));
+    }
+    Ok(roots)
+}
+
+/// When a certificate stops being valid.
+pub fn not_after(cert: &CertificateDer) -> Result<DateTime<Utc>, String> {
+    let (_, cert) = parse_x509_certificate(cert).map_err(|e| e.to_string())?;
+    DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| "expiry out of range".to_string())
+}
+
+/// The pin of a certificate: `sha256/` and the base64 SHA-256 of its
+/// SubjectPublicKeyInfo, as HPKP and curl's `--pinnedpubkey` write it.
+pub fn spki_pin(cert: &CertificateDer) -> Result<String, String> {
+    let (_, cert) = parse_x509_certificate(cert).map_err(|e| e.to_string())?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, cert.public_key().raw);
+    Ok(format!("sha256/{}", BASE64.encode(digest)))
+}
+
+/// Parses comma-separated pins; the `sha256/` prefix is optional.
+pub fn parse_pins(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pin| !pin.is_empty())
+        .map(|pin| {
+            let hash = pin.strip_prefix("sha256/").unwrap_or(pin);
+            match BASE64.decode(hash) {
+                Ok(bytes) if bytes.len() == 32 => Ok(format!("sha256/{}", hash)),
+                _ => Err(format!(
+                    "GRPC_HELLO_TLS_PIN_SHA256: invalid pin {:?}, expected sha256/<base64 SHA-256>",
+                    pin
+                )),
+            }
+        })
+        .collect()
+}
+
+/// A config that is swapped out when its files change.
+pub struct Reloadable<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T: Send + Sync + 'static> Reloadable<T> {
+    pub fn new(value: T) -> Arc<Self> {
+        Arc::new(Reloadable {
+            current: RwLock::new(Arc::new(value)),
+        })
+    }
+
+    /// The config for a new connection.
+    pub fn get(&self) -> Arc<T> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.current.write().unwrap() = Arc::new(value);
+    }
+
+    /// Builds the config again with `build` whenever one of `paths` changes.
+    /// `what` names the certificate in the log; `build` returns the expiry of
+    /// the certificate it loaded, if any.
+    pub fn watch<F>(
+        self: &Arc<Self>,
+        what: &'static str,
+        paths: Vec<PathBuf>,
+        build: F,
+    ) -> JoinHandle<()>
+    where
+        F: Fn() -> Result<(T, Option<DateTime<Utc>>), String> + Send + 'static,
+    {
+        let reloadable = self.clone();
+        watch_files(paths, RELOAD_INTERVAL, move || match build() {
+            Ok((value, Some(expiry))) => {
+                reloadable.set(value);
+                info!("Reloaded the {} certificate, valid until {}", what, expiry);
+                MONITOR.record(what, expiry);
+            }
+            Ok((value, None)) => {
+                reloadable.set(value);
+                info!("Reloaded the {} TLS config", what);
+            }
+            Err(e) => warn!("Keeping the current {} certificate: {}", what, e),
+        })
+    }
+}
+
+/// Which certificates the client trusts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustRoots {
+    /// A private CA bundle.
+    Ca(PathBuf),
+    /// The Mozilla roots bundled with webpki-roots.
+    Webpki,
+    /// The operating system's CA bundle.
+    System,
+}
+
+/// How the client sets up TLS.
+#[derive(Debug, Clone)]
+pub struct ClientTlsSettings {
+    /// The name sent as SNI and checked against the server certificate.
+    pub server_name: ServerName<'static>,
+    pub roots: TrustRoots,
+    /// The certificate chain and key presented to the server, if any.
+    pub identity: Option<(PathBuf, PathBuf)>,
+    /// Accepts any server certificate. Never outside a lab.
+    pub insecure_skip_verify: bool,
+    /// Pins of the server keys accepted (see [`spki_pin`]); empty accepts
+    /// any key the roots vouch for.
+    pub pins: Vec<String>,
+}
+
+impl ClientTlsSettings {
+    /// Reads the `GRPC_HELLO_TLS_*` variables.
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| env::var(name).unwrap_or_default();
+
+        let name = var("GRPC_HELLO_TLS_SERVER_NAME");
+        let name = if name.is_empty() {
+            DEFAULT_SERVER_NAME.to_string()
+        } else {
+            name
+        };
+        let server_name = ServerName::try_from(name.clone())
+            .map_err(|_| format!("GRPC_HELLO_TLS_SERVER_NAME: invalid server name {:?}", name))?;
+
+        let roots = match var("GRPC_HELLO_TLS_ROOTS")
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "" | "ca" => TrustRoots::Ca(trans::client_root_cert()),
+            "webpki" => TrustRoots::Webpki,
+            "system" => TrustRoots::System,
+            other => {
+                return Err(format!(
+                    "GRPC_HELLO_TLS_ROOTS: invalid value {:?}, expected ca, webpki or system",
+                    other
+                ));
+            }
+        };
+
+        let identity = (var("GRPC_HELLO_TLS_CLIENT_CERT") != "N")
+            .then(|| (trans::client_cert_chain(), trans::client_cert_key()));
+
+        Ok(ClientTlsSettings {
+            server_name,
+            roots,
+            identity,
+            insecure_skip_verify: var("GRPC_HELLO_TLS_INSECURE_SKIP_VERIFY") == "Y",
+            pins: parse_pins(&var("GRPC_HELLO_TLS_PIN_SHA256"))?,
+        })
+    }
+
+    /// The files the config is built from.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if let TrustRoots::Ca(ca) = &self.roots {
+            files.push(ca.clone());
+        }
+        if let Some((chain, key)) = &self.identity {
+            files.push(chain.clone());
+            files.push(key.clone());
+        }
+        files
+    }
+
+    /// Builds the client config. Returns it with the client certificate's
+    /// expiry when there is one.
+    pub fn config(&self) -> Result<(ClientConfig, Option<DateTime<Utc>>), String> {
+        let provider = crypto_provider();
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let verifier: Arc<dyn ServerCertVerifier> = if self.insecure_skip_verify {
+            Arc::new(SkipServerVerification(provider.clone()))
+        } else {
+            let roots = match &self.roots {
+                TrustRoots::Ca(ca) => load_roots(ca),
+                TrustRoots::Webpki => Ok(RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                }),
+                TrustRoots::System => load_system_roots(),
+            };
+            let roots = Arc::new(roots.map_err(|e| e.to_string())?);
+            WebPkiServerVerifier::builder_with_provider(roots, provider)
+                .build()
+                .map_err(|e| e.to_string())?
+        };
+        let verifier = if self.pins.is_empty() {
+            verifier
+        } else {
+            Arc::new(PinnedServerVerification {
+                inner: verifier,
+                pins: self.pins.clone(),
+            })
+        };
+        let builder = builder
+            .dangerous()
+            .with_custom_certificate_verifier(verifier);
+
+        let (mut config, expiry) = match &self.identity {
+            Some((chain, key)) => {
+                let certs = load_certs(chain).map_err(|e| e.to_string())?;
+                let expiry = not_after(&certs[0]).map_err(|e| format!("{:?}: {}", chain, e))?;
+                let key = load_key(key).map_err(|e| e.to_string())?;
+                let config = builder
+                    .with_client_auth_cert(certs, key)
+                    .map_err(|e| format!("{:?}: {}", chain, e))?;
+                (config, Some(expiry))
+            }
+            None => (builder.with_no_client_auth(), None),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok((config, expiry))
+    }
+}
+
+/// Builds the client config: `ca` as the only trusted root, presenting the
+/// identity in `chain` and `key`. Returns it with the identity's expiry.
+pub fn client_config(
+    ca: &Path,
+    chain: &Path,
+    key: &Path,
+) -> Result<(ClientConfig, DateTime<Utc>), String> {
+    let settings = ClientTlsSettings {
+        server_name: ServerName::try_from(DEFAULT_SERVER_NAME).expect("valid server name"),
+        roots: TrustRoots::Ca(ca.to_path_buf()),
+        identity: Some((chain.to_path_buf(), key.to_path_buf())),
+        insecure_skip_verify: false,
+        pins: Vec::new(),
+    };
+    let (config, expiry) = settings.config()?;
+    Ok((config, expiry.expect("the identity's expiry")))
+}
+
+/// `GRPC_HELLO_TLS_INSECURE_SKIP_VERIFY`: trusts any server certificate,
+/// while still checking that the server holds its key.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// `GRPC_HELLO_TLS_PIN_SHA256`: after `inner` accepted the server
+/// certificate, its key must also match one of `pins`.
+#[derive(Debug)]
+struct PinnedServerVerification {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<String>,
+}
+
+impl ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let pin = spki_pin(end_entity).map_err(rustls::Error::General)?;
+        if !self.pins.contains(&pin) {
+            warn!(
+                "Refusing the server certificate: its key {} is not pinned",
+                pin
+            );
+            return Err(rustls::Error::General(format!(
+                "server key {} is not pinned",
+                pin
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Connects to a backend over TCP and TLS with the current client config.
+/// The endpoint's URI only supplies host and port; the certificate is
+/// checked against `server_name`.
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<Reloadable<ClientConfig>>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    pub fn new(config: Arc<Reloadable<ClientConfig>>, server_name: ServerName<'static>) -> Self {
+        TlsConnector {
+            config,
+            server_name,
+        }
+    }
+}
+
+impl Service<Uri> for TlsConnector {
+    type Response = TokioIo<tokio_rustls::client::TlsStream<TcpStream>>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = tokio_rustls::TlsConnector::from(self.config.get());
+        let server_name = self.server_name.clone();
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let port = uri.port_u16().unwrap_or(443);
+            let tcp = match host.parse() {
+                Ok(ip) => TcpStream::connect(SocketAddr::new(ip, port)).await?,
+                Err(_) => TcpStream::connect((host, port)).await?,
+            };
+            tcp.set_nodelay(true)?;
+            let tls = connector.connect(server_name, tcp).await?;
+            Ok(TokioIo::new(tls))
+        })
+    }
+}
@@ -16,7 +16,7 @@ pub static TRACING_KEYS: &[&str] = &[
 
 /// Base directory that contains the `server_certs`/`client_certs` folders.
 /// Resolution order: `CERT_BASE_PATH` env var, then the platform default.
-fn cert_base_path() -> PathBuf {
+pub fn cert_base_path() -> PathBuf {
     if let Ok(base) = env::var("CERT_BASE_PATH") {
         return PathBuf::from(base);
     }
@@ -0,0 +1,40 @@
+//! File watching for configuration that reloads without a restart.
+//!
+//! [`watch_files`] polls the modification time and size of a set of files
+//! and calls back when any of them changes, is created or is removed. Polling
+//! keeps it free of platform notification APIs and works the same on bind
+//! mounts and ConfigMap volumes, where the files are swapped by symlink.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use tokio::task::JoinHandle;
+
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &PathBuf) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Checks `paths` every `interval` and runs `on_change` after any of them
+/// changed. The task runs until aborted.
+pub fn watch_files<F>(paths: Vec<PathBuf>, interval: Duration, mut on_change: F) -> JoinHandle<()>
+where
+    F: FnMut() + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut stamps: Vec<Stamp> = paths.iter().map(stamp).collect();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let current: Vec<Stamp> = paths.iter().map(stamp).collect();
+            if current != stamps {
+                stamps = current;
+                on_change();
+            }
+        }
+    })
+}
@@ -0,0 +1,239 @@
+//! JWT bearer authentication for `LandingService`.
+//!
+//! Callers send `authorization: Bearer <jwt>`. [`JwtVerifier`] checks the
+//! signature, `exp` (required), and `iss` / `aud` when configured; the
+//! verified [`Claims`] are put into the request extensions, where handlers
+//! and the request log read them. Calls without a valid token fail with
+//! `UNAUTHENTICATED`.
+//!
+//! Keys come from one of:
+//!
+//! - `GRPC_HELLO_JWT_KEY_FILE` with `GRPC_HELLO_JWT_ALGORITHM`: an HS256
+//!   secret (the file's bytes, trailing newline trimmed) or an RS256 / ES256
+//!   public key in PEM.
+//! - `GRPC_HELLO_JWKS_FILE`: a JWKS document. Tokens are matched to a key by
+//!   `kid`; a set with a single key also accepts tokens without one.
+//!
+//! `GRPC_HELLO_JWT_ISSUER` and `GRPC_HELLO_JWT_AUDIENCE` (comma-separated)
+//! restrict the accepted `iss` and `aud`. Without a key nothing is checked.
+
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use log::{debug, info};
+use serde_json::{Map, Value};
+use tonic::{Request, Status};
+
+/// The algorithms accepted for local key files.
+const ALGORITHMS: &[Algorithm] = &[Algorithm::HS256, Algorithm::RS256, Algorithm::ES256];
+
+/// The verified claims of a caller's token.
+#[derive(Debug, Clone, Default)]
+pub struct Claims(pub Map<String, Value>);
+
+impl Claims {
+    /// The token's subject, used as the caller's principal.
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(Value::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+}
+
+struct Key {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Verifies bearer tokens against a fixed set of keys.
+pub struct JwtVerifier {
+    keys: Vec<Key>,
+    issuer: Vec<String>,
+    audience: Vec<String>,
+}
+
+impl JwtVerifier {
+    /// A verifier for a single local key: an HS256 secret or an RS256 /
+    /// ES256 public key in PEM.
+    pub fn from_key(algorithm: Algorithm, material: &[u8]) -> Result<Self, String> {
+        let key = match algorithm {
+            Algorithm::HS256 => DecodingKey::from_secret(material.trim_ascii_end()),
+            Algorithm::RS256 => DecodingKey::from_rsa_pem(material).map_err(|e| e.to_string())?,
+            Algorithm::ES256 => DecodingKey::from_ec_pem(material).map_err(|e| e.to_string())?,
+            other => return Err(format!("unsupported algorithm {:?}", other)),
+        };
+        Ok(JwtVerifier {
+            keys: vec![Key {
+                kid: None,
+                algorithm,
+                key,
+            }],
+            issuer: Vec::new(),
+            audience: Vec::new(),
+        })
+    }
+
+    /// A verifier for the keys of a JWKS document.
+    pub fn from_jwks(json: &str) -> Result<Self, String> {
+        let set: JwkSet = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let keys = set
+            .keys
+            .iter()
+            .map(|jwk| {
+                Ok(Key {
+                    kid: jwk.common.key_id.clone(),
+                    algorithm: jwk_algorithm(jwk)?,
+                    key: DecodingKey::from_jwk(jwk).map_err(|e| e.to_string())?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if keys.is_empty() {
+            return Err("the key set is empty".to_string());
+        }
+        Ok(JwtVerifier {
+            keys,
+            issuer: Vec::new(),
+            audience: Vec::new(),
+        })
+    }
+
+    /// Only accepts tokens whose `iss` is one of `issuer`.
+    pub fn with_issuer(mut self, issuer: Vec<String>) -> Self {
+        self.issuer = issuer;
+        self
+    }
+
+    /// Only accepts tokens whose `aud` includes one of `audience`.
+    pub fn with_audience(mut self, audience: Vec<String>) -> Self {
+        self.audience = audience;
+        self
+    }
+
+    /// Checks a token and returns its claims, or why it was rejected.
+    pub fn verify(&self, token: &str) -> Result<Claims, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+        let key = match &header.kid {
+            Some(kid) => self.keys.iter().find(|k| k.kid.as_deref() == Some(kid)),
+            None if self.keys.len() == 1 => self.keys.first(),
+            None => None,
+        }
+        .ok_or("no key matches the token")?;
+        if header.alg != key.algorithm {
+            return Err(format!(
+                "algorithm {:?} does not match the key's {:?}",
+                header.alg, key.algorithm
+            ));
+        }
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_required_spec_claims(&["exp"]);
+        if !self.issuer.is_empty() {
+            validation.set_issuer(&self.issuer);
+        }
+        if self.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audience);
+        }
+        decode::<Map<String, Value>>(token, &key.key, &validation)
+            .map(|data| Claims(data.claims))
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The algorithm a JWK is used with: its `alg`, or the one its key type
+/// implies.
+fn jwk_algorithm(jwk: &Jwk) -> Result<Algorithm, String> {
+    if let Some(alg) = jwk.common.key_algorithm {
+        return alg
+            .to_string()
+            .parse()
+            .map_err(|_| format!("unsupported alg {}", alg));
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::OctetKey(_) => Ok(Algorithm::HS256),
+        AlgorithmParameters::RSA(_) => Ok(Algorithm::RS256),
+        AlgorithmParameters::EllipticCurve(ec) if ec.curve == EllipticCurve::P256 => {
+            Ok(Algorithm::ES256)
+        }
+        _ => Err("unsupported key type".to_string()),
+    }
+}
+
+fn list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Builds the verifier from the environment; `None` when no key is set.
+pub fn from_env() -> Result<Option<Arc<JwtVerifier>>, String> {
+    let verifier = if let Ok(path) = env::var("GRPC_HELLO_JWKS_FILE") {
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read JWKS {:?}: {}", path, e))?;
+        let verifier = JwtVerifier::from_jwks(&json).map_err(|e| format!("{:?}: {}", path, e))?;
+        info!("JWT keys loaded from {:?}", path);
+        verifier
+    } else if let Ok(path) = env::var("GRPC_HELLO_JWT_KEY_FILE") {
+        let name = env::var("GRPC_HELLO_JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string());
+        let algorithm = name
+            .parse()
+            .ok()
+            .filter(|alg| ALGORITHMS.contains(alg))
+            .ok_or_else(|| format!("GRPC_HELLO_JWT_ALGORITHM: unsupported {}", name))?;
+        let material =
+            fs::read(&path).map_err(|e| format!("failed to read JWT key {:?}: {}", path, e))?;
+        let verifier = JwtVerifier::from_key(algorithm, &material)
+            .map_err(|e| format!("{:?}: {}", path, e))?;
+        info!("JWT {:?} key loaded from {:?}", algorithm, path);
+        verifier
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(Arc::new(
+        verifier
+            .with_issuer(list("GRPC_HELLO_JWT_ISSUER"))
+            .with_audience(list("GRPC_HELLO_JWT_AUDIENCE")),
+    )))
+}
+
+/// Interceptor step: verifies the caller's bearer token and attaches its
+/// [`Claims`]. Passes every call through when `verifier` is `None`.
+pub fn authenticate(
+    verifier: Option<&JwtVerifier>,
+    mut req: Request<()>,
+) -> Result<Request<()>, Status> {
+    let Some(verifier) = verifier else {
+        return Ok(req);
+    };
+    let token = req
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value
+                .strip_prefix("Bearer ")
+                .or_else(|| value.strip_prefix("bearer "))
+        })
+        .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+    match verifier.verify(token.trim()) {
+        Ok(claims) => {
+            req.extensions_mut().insert(claims);
+            Ok(req)
+        }
+        Err(e) => {
+            debug!("Rejected bearer token: {}", e);
+            Err(Status::unauthenticated("invalid bearer token"))
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Per-method authorization for `LandingService`.
+//!
+//! Policies follow the gRPC authorization policy format (gRFC A43), in JSON
+//! or YAML:
+//!
+//! ```yaml
+//! name: landing
+//! deny_rules:
+//!   - name: no-bidi-for-guests
+//!     source: {principals: ["guest-*"]}
+//!     request: {paths: ["/hello.LandingService/TalkBidirectional"]}
+//! allow_rules:
+//!   - name: admins
+//!     source: {principals: ["admin", "spiffe://hello.grpc.io/*"]}
+//!   - name: rust-clients-talk
+//!     request:
+//!       paths: ["/hello.LandingService/Talk*"]
+//!       headers: [{key: client, values: ["rust-client"]}]
+//! ```
+//!
+//! A call is denied if it matches any deny rule, allowed if it matches any
+//! allow rule and denied otherwise. A rule matches when every part it sets
+//! matches: one of its principals, one of its paths, and for every header
+//! one of its values. Patterns are exact, or use `*` as a whole value, a
+//! prefix (`foo*`) or a suffix (`*foo`). A rule without principals matches
+//! unauthenticated callers too; `*` matches any authenticated one. The
+//! caller's principals are the subject of its verified JWT (see auth.rs) and
+//! the SANs and subject of its client certificate (see mtls.rs).
+//!
+//! [`AuthzLayer`] fails denied calls with `PERMISSION_DENIED`. Every
+//! decision is written to the `audit` log target. The policy comes from
+//! `GRPC_HELLO_AUTHZ_POLICY` (the policy itself) or
+//! `GRPC_HELLO_AUTHZ_POLICY_FILE`; the file is reloaded when it changes and a
+//! file that fails to parse leaves the previous policy in place.
+
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Deserialize;
+use tonic::Status;
+use tonic::body::Body;
+use tonic::server::NamedService;
+use tower::{Layer, Service};
+
+use crate::common::watch::watch_files;
+use crate::landing::auth::Claims;
+use crate::landing::mtls::PeerIdentity;
+use crate::landing::rate_limit::peer_ip;
+
+const AUDIT: &str = "audit";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct RawPolicy {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    deny_rules: Vec<RawRule>,
+    #[serde(default)]
+    allow_rules: Vec<RawRule>,
+}
+
+#[derive(Deserialize)]
+struct RawRule {
+    name: String,
+    #[serde(default)]
+    source: RawSource,
+    #[serde(default)]
+    request: RawRequest,
+}
+
+#[derive(Deserialize, Default)]
+struct RawSource {
+    #[serde(default)]
+    principals: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawRequest {
+    #[serde(default)]
+    paths: Vec<String>,
+    #[serde(default)]
+    headers: Vec<RawHeader>,
+}
+
+#[derive(Deserialize)]
+struct RawHeader {
+    key: String,
+    values: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Any,
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        if pattern == "*" {
+            Pattern::Any
+        } else if let Some(prefix) = pattern.strip_suffix('*') {
+            Pattern::Prefix(prefix.to_string())
+        } else if let Some(suffix) = pattern.strip_prefix('*') {
+            Pattern::Suffix(suffix.to_string())
+        } else {
+            Pattern::Exact(pattern.to_string())
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Exact(exact) => value == exact,
+            Pattern::Prefix(prefix) => value.starts_with(prefix.as_str()),
+            Pattern::Suffix(suffix) => value.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    principals: Vec<Pattern>,
+    paths: Vec<Pattern>,
+    headers: Vec<(String, Vec<Pattern>)>,
+}
+
+impl Rule {
+    fn parse(raw: RawRule) -> Result<Self, String> {
+        let headers = raw
+            .request
+            .headers
+            .into_iter()
+            .map(|header| {
+                let key = header.key.to_ascii_lowercase();
+                if key.starts_with(':') || key.starts_with("grpc-") || key == "host" {
+                    return Err(format!("{}: header {:?} cannot be matched", raw.name, key));
+                }
+                if header.values.is_empty() {
+                    return Err(format!("{}: header {:?} has no values", raw.name, key));
+                }
+                let values = header.values.iter().map(|v| Pattern::parse(v)).collect();
+                Ok((key, values))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Rule {
+            principals: raw
+                .source
+                .principals
+                .iter()
+                .map(|p| Pattern::parse(p))
+                .collect(),
+            paths: raw
+                .request
+                .paths
+                .iter()
+                .map(|p| Pattern::parse(p))
+                .collect(),
+            headers,
+            name: raw.name,
+        })
+    }
+
+    fn matches(&self, path: &str, principals: &[String], headers: &http::HeaderMap) -> bool {
+        let principal = self.principals.is_empty()
+            || self
+                .principals
+                .iter()
+                .any(|pattern| principals.iter().any(|p| pattern.matches(p)));
+        let path = self.paths.is_empty() || self.paths.iter().any(|p| p.matches(path));
+        principal
+            && path
+            && self.headers.iter().all(|(key, values)| {
+                headers
+                    .get_all(key)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .any(|value| values.iter().any(|p| p.matches(value)))
+            })
+    }
+}
+
+/// The outcome of evaluating a policy for one call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// The rule that matched; `None` when nothing allowed the call.
+    pub rule: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthzPolicy {
+    name: String,
+    deny: Vec<Rule>,
+    allow: Vec<Rule>,
+}
+
+impl AuthzPolicy {
+    /// Parses a policy in JSON, or in YAML when it is not a JSON object.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let raw: RawPolicy = if text.trim_start().starts_with('{') {
+            serde_json::from_str(text).map_err(|e| e.to_string())?
+        } else {
+            serde_yaml::from_str(text).map_err(|e| e.to_string())?
+        };
+        let rules = |raw: Vec<RawRule>| {
+            raw.into_iter()
+                .map(Rule::parse)
+                .collect::<Result<Vec<_>, String>>()
+        };
+        Ok(AuthzPolicy {
+            name: raw.name,
+            deny: rules(raw.deny_rules)?,
+            allow: rules(raw.allow_rules)?,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Decides a call by its path, the caller's principals and its headers.
+    pub fn evaluate(
+        &self,
+        path: &str,
+        principals: &[String],
+        headers: &http::HeaderMap,
+    ) -> Decision {
+        let matched = |rules: &[Rule]| {
+            rules
+                .iter()
+                .find(|rule| rule.matches(path, principals, headers))
+                .map(|rule| rule.name.clone())
+        };
+        if let Some(rule) = matched(&self.deny) {
+            return Decision {
+                allowed: false,
+                rule: Some(rule),
+            };
+        }
+        let rule = matched(&self.allow);
+        Decision {
+            allowed: rule.is_some(),
+            rule,
+        }
+    }
+}
+
+/// The current policy, shared by every call.
+pub struct Authorizer {
+    policy: RwLock<Arc<AuthzPolicy>>,
+}
+
+impl Authorizer {
+    pub fn new(policy: AuthzPolicy) -> Self {
+        Authorizer {
+            policy: RwLock::new(Arc::new(policy)),
+        }
+    }
+
+    pub fn reload(&self, policy: AuthzPolicy) {
+        *self.policy.write().unwrap() = Arc::new(policy);
+    }
+
+    pub fn policy(&self) -> Arc<AuthzPolicy> {
+        self.policy.read().unwrap().clone()
+    }
+}
+
+/// Reads the policy from `GRPC_HELLO_AUTHZ_POLICY` or
+/// `GRPC_HELLO_AUTHZ_POLICY_FILE`, watching the file for changes. `None`
+/// when neither is set.
+pub fn from_env() -> Result<Option<Arc<Authorizer>>, String> {
+    if let Ok(text) = env::var("GRPC_HELLO_AUTHZ_POLICY") {
+        let policy =
+            AuthzPolicy::parse(&text).map_err(|e| format!("GRPC_HELLO_AUTHZ_POLICY: {}", e))?;
+        info!(
+            "Authorization policy {:?} loaded from GRPC_HELLO_AUTHZ_POLICY",
+            policy.name()
+        );
+        return Ok(Some(Arc::new(Authorizer::new(policy))));
+    }
+    let Ok(path) = env::var("GRPC_HELLO_AUTHZ_POLICY_FILE") else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    let policy = read_policy(&path)?;
+    info!(
+        "Authorization policy {:?} loaded from {:?}",
+        policy.name(),
+        path
+    );
+    let authorizer = Arc::new(Authorizer::new(policy));
+
+    let reloaded = authorizer.clone();
+    let watched = path.clone();
+    watch_files(vec![path], RELOAD_INTERVAL, move || {
+        match read_policy(&watched) {
+            Ok(policy) => {
+                info!(
+                    "Authorization policy {:?} reloaded from {:?}",
+                    policy.name(),
+                    watched
+                );
+                reloaded.reload(policy);
+            }
+            Err(e) => warn!("Keeping the current authorization policy: {}", e),
+        }
+    });
+    Ok(Some(authorizer))
+}
+
+fn read_policy(path: &PathBuf) -> Result<AuthzPolicy, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read authorization policy {:?}: {}", path, e))?;
+    AuthzPolicy::parse(&text).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+/// The caller's authenticated identities: the JWT subject, then the client
+/// certificate's SANs and subject.
+pub fn principals<B>(request: &http::Request<B>) -> Vec<String> {
+    let extensions = request.extensions();
+    extensions
+        .get::<Claims>()
+        .and_then(Claims::subject)
+        .into_iter()
+        .chain(
+            extensions
+                .get::<PeerIdentity>()
+                .into_iter()
+                .flat_map(PeerIdentity::principals),
+        )
+        .map(String::from)
+        .collect()
+}
+
+/// Authorizes calls to the wrapped service; a pass-through without an
+/// authorizer.
+#[derive(Clone)]
+pub struct AuthzLayer {
+    authorizer: Option<Arc<Authorizer>>,
+}
+
+impl AuthzLayer {
+    pub fn new(authorizer: Option<Arc<Authorizer>>) -> Self {
+        AuthzLayer { authorizer }
+    }
+}
+
+impl<S> Layer<S> for AuthzLayer {
+    type Service = AuthzService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthzService {
+            inner,
+            authorizer: self.authorizer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthzService<S> {
+    inner: S,
+    authorizer: Option<Arc<Authorizer>>,
+}
+
+impl<S: NamedService> NamedService for AuthzService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for AuthzService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        if let Some(authorizer) = &self.authorizer {
+            let policy = authorizer.policy();
+            let path = request.uri().path();
+            let principals = principals(&request);
+            let decision = policy.evaluate(path, &principals, request.headers());
+            info!(
+                target: AUDIT,
+                "policy={} decision={} rule={} path={} principals={:?} peer={}",
+                policy.name(),
+                if decision.allowed { "allow" } else { "deny" },
+                decision.rule.as_deref().unwrap_or("-"),
+                path,
+                principals,
+                peer_ip(&request).map(|ip| ip.to_string()).unwrap_or_default()
+            );
+            if !decision.allowed {
+                let response =
+                    Status::permission_denied("denied by authorization policy").into_http();
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
@@ -15,12 +15,12 @@ use futures::stream;
 use log::{error, info};
 use tokio::time;
 use tonic::Request;
-use tonic::transport::Channel;
 
-use hello_grpc_rust::common::conn::{CONFIG_PATH, build_client, call_with_retry};
-use hello_grpc_rust::common::landing::landing_service_client::LandingServiceClient;
+use hello_grpc_rust::common::cert_expiry;
+use hello_grpc_rust::common::conn::{CONFIG_PATH, LandingClient, try_build_client};
 use hello_grpc_rust::common::landing::{TalkRequest, TalkResponse};
 use hello_grpc_rust::common::utils::{build_link_requests, get_version, random_id};
+use hello_grpc_rust::landing::metrics::serve_metrics;
 
 // Configuration constants
 const RETRY_ATTEMPTS: u32 = 3;
@@ -34,6 +34,15 @@ const DEFAULT_BATCH_SIZE: usize = 5;
 /// Client application entry point
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // `gen-certs` writes development certificates and exits
+    if std::env::args().nth(1).as_deref() == Some("gen-certs") {
+        if let Err(e) = hello_grpc_rust::common::certgen::cli(std::env::args().skip(2)) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        return Ok(());
+    }
+
     // Initialize rustls crypto provider
     let _ = rustls::crypto::ring::default_provider().install_default();
 
@@ -45,6 +54,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Starting gRPC client [version: {}]", get_version());
 
+    // The client's metrics, and the expiry of its certificate, are exposed
+    // only when asked for: a one-shot client has no port of its own
+    if let Some(port) = metrics_port() {
+        tokio::spawn(serve_metrics(port));
+        cert_expiry::spawn_monitor();
+    }
+
     // Retry logic for connection
     for attempt in 1..=RETRY_ATTEMPTS {
         match connect_and_run(attempt).await {
@@ -64,14 +80,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
 
     info!("Client execution completed successfully");
+    hello_grpc_rust::otel::shutdown_otel();
     Ok(())
 }
 
+/// `GRPC_HELLO_CLIENT_METRICS_PORT`, where the client serves `/metrics` and
+/// `/health`; unset or invalid serves nothing.
+fn metrics_port() -> Option<u16> {
+    let port = std::env::var("GRPC_HELLO_CLIENT_METRICS_PORT").ok()?;
+    match port.parse() {
+        Ok(port) => Some(port),
+        Err(_) => {
+            error!("GRPC_HELLO_CLIENT_METRICS_PORT: invalid port {:?}", port);
+            None
+        }
+    }
+}
+
 /// Connect to server and run all gRPC patterns
 async fn connect_and_run(attempt: u32) -> Result<bool, Box<dyn Error>> {
     info!("Connection attempt {}/{}", attempt, RETRY_ATTEMPTS);
 
-    let mut client = build_client().await;
+    let mut client = try_build_client().await?;
     info!("Successfully connected to gRPC server");
 
     run_grpc_calls(&mut client, REQUEST_DELAY_MS, ITERATION_COUNT).await
@@ -79,7 +109,7 @@ async fn connect_and_run(attempt: u32) -> Result<bool, Box<dyn Error>> {
 
 /// Run all gRPC call patterns multiple times
 async fn run_grpc_calls(
-    client: &mut LandingServiceClient<Channel>,
+    client: &mut LandingClient,
     delay_ms: u64,
     iterations: u32,
 ) -> Result<bool, Box<dyn Error>> {
@@ -117,9 +147,7 @@ async fn run_grpc_calls(
 }
 
 /// Execute unary RPC call
-async fn execute_unary_call(
-    client: &mut LandingServiceClient<Channel>,
-) -> Result<(), Box<dyn Error>> {
+async fn execute_unary_call(client: &mut LandingClient) -> Result<(), Box<dyn Error>> {
     let request_id = format!("unary-{}", uuid::Uuid::new_v4());
 
     let message = TalkRequest {
@@ -130,26 +158,17 @@ async fn execute_unary_call(
     info!("Sending unary request: data={}, meta=RUST", message.data);
     let start_time = Instant::now();
 
-    // A6 client retry: retries only on UNAVAILABLE with exponential
-    // backoff, matching the retryPolicy service config used by the other
-    // language clients (tonic has no built-in service-config support).
-    let result = call_with_retry("Talk", || {
-        let mut client = client.clone();
-        let message = message.clone();
-        let request_id = request_id.clone();
-        async move {
-            let mut request = Request::new(message);
-            request
-                .metadata_mut()
-                .insert("request-id", request_id.parse().unwrap());
-            request
-                .metadata_mut()
-                .insert("client", "rust-client".parse().unwrap());
-            request.set_timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS));
-            client.talk(request).await
-        }
-    })
-    .await;
+    // Retries (A6, UNAVAILABLE only by default) come from the service
+    // config applied to every call made through the client.
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("request-id", request_id.parse().unwrap());
+    request
+        .metadata_mut()
+        .insert("client", "rust-client".parse().unwrap());
+    request.set_timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS));
+    let result = client.talk(request).await;
 
     match result {
         Ok(response) => {
@@ -166,9 +185,7 @@ async fn execute_unary_call(
 }
 
 /// Execute server streaming RPC call
-async fn execute_server_streaming_call(
-    client: &mut LandingServiceClient<Channel>,
-) -> Result<(), Box<dyn Error>> {
+async fn execute_server_streaming_call(client: &mut LandingClient) -> Result<(), Box<dyn Error>> {
     let request_id = format!("server-stream-{}", uuid::Uuid::new_v4());
 
     let message = TalkRequest {
@@ -218,7 +235,7 @@ async fn execute_server_streaming_call(
 
 /// Execute client streaming RPC call
 async fn execute_client_streaming_call(
-    client: &mut LandingServiceClient<Channel>,
+    client: &mut LandingClient,
 ) -> Result<TalkResponse, Box<dyn Error>> {
     let request_id = format!("client-stream-{}", uuid::Uuid::new_v4());
 
@@ -255,7 +272,7 @@ async fn execute_client_streaming_call(
 
 /// Execute bidirectional streaming RPC call
 async fn execute_bidirectional_streaming_call(
-    client: &mut LandingServiceClient<Channel>,
+    client: &mut LandingClient,
 ) -> Result<(), Box<dyn Error>> {
     let request_id = format!("bidirectional-{}", uuid::Uuid::new_v4());
 
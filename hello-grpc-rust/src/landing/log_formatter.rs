@@ -2,6 +2,8 @@ use log::{error, info};
 use std::time::Instant;
 use tonic::{Request, Status, metadata::MetadataMap};
 
+use crate::landing::mtls::PeerIdentity;
+
 const SERVICE_NAME: &str = "rust";
 
 /// Extract request ID from metadata
@@ -30,6 +32,11 @@ pub fn extract_peer<T>(request: &Request<T>) -> String {
         .unwrap_or_else(|| "unknown".to_string())
 }
 
+/// Extract the verified client certificate identity from request
+pub fn extract_peer_identity<T>(request: &Request<T>) -> Option<&PeerIdentity> {
+    request.extensions().get::<PeerIdentity>()
+}
+
 /// Check if connection is secure
 pub fn is_secure() -> bool {
     std::env::var("GRPC_HELLO_SECURE")
@@ -0,0 +1,226 @@
+//! Server-side RPC metrics.
+//!
+//! [`RpcMetricsLayer`] wraps the whole tonic router so every call is counted
+//! without handlers having to remember to do it. The final gRPC status is
+//! read from the response headers (trailers-only responses) or from the
+//! trailers once the body finishes, which makes it work the same way for
+//! unary and streaming methods. [`serve_metrics`] exposes the shared
+//! registry on `/metrics` in the Prometheus text format, and the expiry of
+//! the loaded certificates on `/health` (see cert_expiry.rs).
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bytes::Bytes;
+use chrono::Utc;
+use log::{error, info};
+use serde_json::json;
+use tonic::Code;
+use tower::{Layer, Service};
+
+use crate::common::cert_expiry::MONITOR;
+use crate::common::framing::{CountingBody, Finish, ObservedBody, split_grpc_path};
+use crate::common::metrics::{CONTENT_TYPE, REGISTRY, code_name};
+use crate::otel;
+
+const STARTED_TOTAL: &str = "grpc_server_started_total";
+const HANDLED_TOTAL: &str = "grpc_server_handled_total";
+const HANDLING_SECONDS: &str = "grpc_server_handling_seconds";
+const IN_FLIGHT: &str = "grpc_server_in_flight";
+
+/// Tower layer recording started/handled counters, a latency histogram and
+/// an in-flight gauge per `grpc_method`, `grpc_code` and `mode`, plus the
+/// OTel RPC instruments when `GRPC_HELLO_OTEL=Y`.
+#[derive(Clone)]
+pub struct RpcMetricsLayer {
+    mode: &'static str,
+}
+
+impl RpcMetricsLayer {
+    /// `mode` is either `standalone` or `proxy`.
+    pub fn new(mode: &'static str) -> Self {
+        RpcMetricsLayer { mode }
+    }
+}
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetricsService {
+            inner,
+            mode: self.mode,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcMetricsService<S> {
+    inner: S,
+    mode: &'static str,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RpcMetricsService<S>
+where
+    S: Service<http::Request<tonic::body::Body>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    ReqBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ReqBody::Error: Into<tonic::codegen::StdError>,
+{
+    type Response = http::Response<ObservedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let (service, method) = split_grpc_path(request.uri().path());
+        let mode = self.mode;
+        let requests = Arc::new(AtomicU64::new(0));
+        let request =
+            request.map(|body| tonic::body::Body::new(CountingBody::new(body, requests.clone())));
+
+        let labels = [
+            ("grpc_service", service.as_str()),
+            ("grpc_method", method.as_str()),
+            ("mode", mode),
+        ];
+        REGISTRY.inc_counter(
+            STARTED_TOTAL,
+            "Total number of RPCs started on the server.",
+            &labels,
+        );
+        REGISTRY.add_gauge(
+            IN_FLIGHT,
+            "Number of RPCs currently being handled.",
+            &labels,
+            1.0,
+        );
+        let started = Instant::now();
+        let mut finish = Finish::new(Box::new(move |code: Code, responses: u64| {
+            record_handled(&service, &method, mode, code, started);
+            otel::record_server_call(
+                &service,
+                &method,
+                code,
+                started.elapsed(),
+                requests.load(Ordering::Relaxed),
+                responses,
+            );
+        }));
+
+        Box::pin(async move {
+            match inner.call(request).await {
+                Ok(response) => {
+                    let (parts, body) = response.into_parts();
+                    let body = ObservedBody::new(body, &parts.headers, finish);
+                    Ok(http::Response::from_parts(parts, body))
+                }
+                Err(e) => {
+                    finish.set_code(Code::Internal);
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+fn record_handled(service: &str, method: &str, mode: &str, code: Code, started: Instant) {
+    let code = code_name(code);
+    let labels = [
+        ("grpc_service", service),
+        ("grpc_method", method),
+        ("mode", mode),
+    ];
+    REGISTRY.add_gauge(
+        IN_FLIGHT,
+        "Number of RPCs currently being handled.",
+        &labels,
+        -1.0,
+    );
+    REGISTRY.observe(
+        HANDLING_SECONDS,
+        "Histogram of RPC handling latency in seconds.",
+        &labels,
+        started.elapsed().as_secs_f64(),
+    );
+    REGISTRY.inc_counter(
+        HANDLED_TOTAL,
+        "Total number of RPCs completed on the server, regardless of success or failure.",
+        &[
+            ("grpc_service", service),
+            ("grpc_method", method),
+            ("grpc_code", code),
+            ("mode", mode),
+        ],
+    );
+}
+
+/// Serves the Prometheus exposition on `GET /metrics` and the certificate
+/// expiry detail on `GET /health`; every other path is 404.
+pub async fn serve_metrics(port: u16) {
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    let metrics_address = format!("[::0]:{}", port);
+    info!("Starting metrics server on port {}", port);
+
+    let listener = match TcpListener::bind(&metrics_address).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to bind metrics server: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            let io = TokioIo::new(stream);
+            let service = service_fn(|req: Request<hyper::body::Incoming>| async move {
+                let response = match req.uri().path() {
+                    "/metrics" => Response::builder()
+                        .header(http::header::CONTENT_TYPE, CONTENT_TYPE)
+                        .body(REGISTRY.render()),
+                    "/health" => {
+                        let detail = json!({ "certificates": MONITOR.detail(Utc::now()) });
+                        Response::builder()
+                            .header(http::header::CONTENT_TYPE, "application/json")
+                            .body(format!("{}\n", detail))
+                    }
+                    _ => Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body("not found\n".to_string()),
+                };
+                Ok::<_, http::Error>(response.expect("static response parts are valid"))
+            });
+
+            if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
+                error!("Error serving metrics connection: {}", e);
+            }
+        });
+    }
+}
@@ -1 +1,10 @@
+pub mod auth;
+pub mod authz;
 pub mod log_formatter;
+pub mod metrics;
+pub mod mtls;
+pub mod pool;
+pub mod rate_limit;
+pub mod tls;
+pub mod trace_context;
+pub mod trailers;
@@ -0,0 +1,129 @@
+//! Mutual TLS on the server and the identity of the client certificate.
+//!
+//! `GRPC_HELLO_MTLS` selects whether TLS clients must present a certificate:
+//! `off` (the default), `optional` (verified when presented) or `required`.
+//! Client certificates are verified against `GRPC_HELLO_CLIENT_CA_FILE`,
+//! which defaults to [`trans::server_root_cert`].
+//!
+//! [`attach_peer_identity`] reads the verified client certificate of a call
+//! and puts its [`PeerIdentity`] (subject and SANs) into the request
+//! extensions, where handlers, the request log and the authorization policy
+//! read it.
+
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use tonic::Request;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use x509_parser::extensions::GeneralName;
+use x509_parser::parse_x509_certificate;
+
+use crate::common::trans;
+
+/// Whether the server asks TLS clients for a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    Off,
+    Optional,
+    Required,
+}
+
+impl ClientAuth {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "off" | "n" => Ok(ClientAuth::Off),
+            "optional" => Ok(ClientAuth::Optional),
+            "required" | "y" => Ok(ClientAuth::Required),
+            other => Err(format!(
+                "GRPC_HELLO_MTLS: invalid mode {:?}, expected off, optional or required",
+                other
+            )),
+        }
+    }
+
+    /// Reads `GRPC_HELLO_MTLS`.
+    pub fn from_env() -> Result<Self, String> {
+        ClientAuth::parse(&env::var("GRPC_HELLO_MTLS").unwrap_or_default())
+    }
+}
+
+/// The CA bundle client certificates are verified against.
+pub fn client_ca_file() -> PathBuf {
+    env::var("GRPC_HELLO_CLIENT_CA_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| trans::server_root_cert())
+}
+
+/// Who a verified client certificate belongs to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// The certificate subject, e.g. `C=CN, O=HelloGRPC, CN=hello.grpc.client`.
+    pub subject: String,
+    /// URI, DNS, email and IP subject alternative names, in certificate order.
+    pub sans: Vec<String>,
+}
+
+impl PeerIdentity {
+    /// Reads the identity of a DER-encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (_, cert) = parse_x509_certificate(der).map_err(|e| e.to_string())?;
+        let mut sans = Vec::new();
+        if let Some(extension) = cert.subject_alternative_name().map_err(|e| e.to_string())? {
+            for name in &extension.value.general_names {
+                match name {
+                    GeneralName::URI(value)
+                    | GeneralName::DNSName(value)
+                    | GeneralName::RFC822Name(value) => sans.push(value.to_string()),
+                    GeneralName::IPAddress(bytes) => {
+                        let ip = match bytes.len() {
+                            4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                            16 => <[u8; 16]>::try_from(*bytes).ok().map(IpAddr::from),
+                            _ => None,
+                        };
+                        sans.extend(ip.map(|ip| ip.to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(PeerIdentity {
+            subject: cert.subject().to_string(),
+            sans,
+        })
+    }
+
+    /// The names the authorization policy matches principals against: the
+    /// SANs, then the subject.
+    pub fn principals(&self) -> impl Iterator<Item = &str> {
+        self.sans
+            .iter()
+            .map(String::as_str)
+            .chain(Some(self.subject.as_str()))
+    }
+}
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subject=\"{}\"", self.subject)?;
+        if !self.sans.is_empty() {
+            write!(f, " sans={}", self.sans.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Interceptor step: attaches the [`PeerIdentity`] of the caller's client
+/// certificate, when the connection has one.
+pub fn attach_peer_identity(mut req: Request<()>) -> Request<()> {
+    let leaf = req
+        .extensions()
+        .get::<TlsConnectInfo<TcpConnectInfo>>()
+        .and_then(TlsConnectInfo::peer_certs)
+        .and_then(|certs| certs.first().map(|cert| PeerIdentity::from_der(cert)));
+    if let Some(Ok(identity)) = leaf {
+        req.extensions_mut().insert(identity);
+    }
+    req
+}
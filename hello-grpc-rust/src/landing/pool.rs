@@ -0,0 +1,325 @@
+//! Backend connection pool for proxy mode.
+//!
+//! The proxy keeps `size` independent channels to the backend and spreads
+//! calls across them with the policy from `GRPC_HELLO_POOL_POLICY`
+//! (`round_robin`, the default, or `least_request`). Every checkout hands
+//! out a [`PooledClient`] lease, which counts as one outstanding request
+//! until it is dropped, so streaming calls keep counting for as long as they
+//! run. Leases report their outcome back. A connection that fails
+//! [`FAILURE_THRESHOLD`] calls in a row is taken out of rotation and
+//! rebuilt in the background with exponential backoff.
+//!
+//! Connections are built lazily: the pool is ready at once and each channel
+//! connects, and reconnects, in the background. Only connected channels are
+//! checked out, and [`ConnectionPool::healthy`] stays at 0 until the backend
+//! is up.
+//!
+//! Pool state is exported to the shared registry as
+//! `grpc_proxy_pool_connection_healthy`, `grpc_proxy_pool_outstanding_requests`,
+//! `grpc_proxy_pool_checkouts_total`, `grpc_proxy_pool_failures_total` and
+//! `grpc_proxy_pool_rebuilds_total`, all labelled by `connection`.
+
+use std::env;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tonic::{Code, Status};
+
+use crate::common::breaker;
+use crate::common::conn::{Backend, ClientBuilder, ConnectError, LandingClient};
+use crate::common::metrics::REGISTRY;
+
+/// Consecutive connection-level failures after which a channel is rebuilt.
+pub const FAILURE_THRESHOLD: u32 = 3;
+const REBUILD_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const REBUILD_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+const HEALTHY: &str = "grpc_proxy_pool_connection_healthy";
+const OUTSTANDING: &str = "grpc_proxy_pool_outstanding_requests";
+const CHECKOUTS_TOTAL: &str = "grpc_proxy_pool_checkouts_total";
+const FAILURES_TOTAL: &str = "grpc_proxy_pool_failures_total";
+const REBUILDS_TOTAL: &str = "grpc_proxy_pool_rebuilds_total";
+
+/// How [`ConnectionPool::checkout`] picks a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolPolicy {
+    RoundRobin,
+    LeastRequest,
+}
+
+impl PoolPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "round_robin" | "round-robin" | "rr" => Some(PoolPolicy::RoundRobin),
+            "least_request" | "least-request" | "least_outstanding" => {
+                Some(PoolPolicy::LeastRequest)
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads `GRPC_HELLO_POOL_POLICY`, defaulting to round robin.
+    pub fn from_env() -> Self {
+        match env::var("GRPC_HELLO_POOL_POLICY") {
+            Ok(value) => PoolPolicy::parse(&value).unwrap_or_else(|| {
+                warn!(
+                    "Unknown GRPC_HELLO_POOL_POLICY={:?}, using round_robin",
+                    value
+                );
+                PoolPolicy::RoundRobin
+            }),
+            Err(_) => PoolPolicy::RoundRobin,
+        }
+    }
+}
+
+struct Slot {
+    label: String,
+    /// How the slot's connection is built, and rebuilt.
+    builder: ClientBuilder,
+    backend: Mutex<Option<Backend>>,
+    outstanding: AtomicUsize,
+    failures: AtomicU32,
+    rebuilding: AtomicBool,
+}
+
+impl Slot {
+    fn new(index: usize, builder: ClientBuilder) -> Self {
+        Slot {
+            label: index.to_string(),
+            builder,
+            backend: Mutex::new(None),
+            outstanding: AtomicUsize::new(0),
+            failures: AtomicU32::new(0),
+            rebuilding: AtomicBool::new(false),
+        }
+    }
+
+    /// The slot's client, while its channel is connected. Every check
+    /// republishes the healthy gauge, so it follows the channel connecting
+    /// and dropping as rotation sees it.
+    fn client(&self) -> Option<LandingClient> {
+        let client = self
+            .backend
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|backend| backend.is_ready())
+            .map(|backend| backend.client.clone());
+        self.publish_healthy(client.is_some());
+        client
+    }
+
+    fn install(&self, backend: Option<Backend>) {
+        *self.backend.lock().unwrap() = backend;
+        self.client();
+    }
+
+    fn publish_healthy(&self, healthy: bool) {
+        REGISTRY.set_gauge(
+            HEALTHY,
+            "Whether a pooled backend connection is connected and in rotation (1) or not (0).",
+            &[("connection", &self.label)],
+            if healthy { 1.0 } else { 0.0 },
+        );
+    }
+
+    fn publish_outstanding(&self) {
+        REGISTRY.set_gauge(
+            OUTSTANDING,
+            "Calls currently using a pooled backend connection.",
+            &[("connection", &self.label)],
+            self.outstanding.load(Ordering::Relaxed) as f64,
+        );
+    }
+}
+
+/// A fixed-size set of backend channels shared by all proxy handlers.
+pub struct ConnectionPool {
+    slots: Vec<Arc<Slot>>,
+    policy: PoolPolicy,
+    next: AtomicUsize,
+}
+
+impl ConnectionPool {
+    /// Builds `size` lazy connections to the backend configured in the
+    /// environment. Connections that cannot be built at all (bad address,
+    /// unreadable certificates) are retried in the background.
+    pub async fn connect(size: usize, policy: PoolPolicy) -> Self {
+        ConnectionPool::connect_with(size, policy, ClientBuilder::new()).await
+    }
+
+    /// Like [`connect`](ConnectionPool::connect), with every connection built
+    /// by `builder`.
+    pub async fn connect_with(size: usize, policy: PoolPolicy, builder: ClientBuilder) -> Self {
+        let pool = ConnectionPool {
+            slots: (0..size.max(1))
+                .map(|i| Arc::new(Slot::new(i, builder.clone())))
+                .collect(),
+            policy,
+            next: AtomicUsize::new(0),
+        };
+        for slot in &pool.slots {
+            slot.publish_outstanding();
+            match lazy_backend(&slot.builder).await {
+                Ok(backend) => {
+                    slot.install(Some(backend));
+                    info!("Created connection #{} in pool", slot.label);
+                }
+                Err(e) => {
+                    error!(
+                        "Connection #{} in pool failed to connect: {}",
+                        slot.label, e
+                    );
+                    slot.install(None);
+                    schedule_rebuild(slot.clone());
+                }
+            }
+        }
+        pool
+    }
+
+    /// Number of connections in rotation whose channel is connected.
+    pub fn healthy(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.client().is_some())
+            .count()
+    }
+
+    /// Leases a connection according to the pool policy, or `None` when
+    /// no connection is connected.
+    pub fn checkout(&self) -> Option<PooledClient> {
+        let n = self.slots.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % n;
+        let candidates = (0..n).map(|offset| &self.slots[(start + offset) % n]);
+
+        let (slot, client) = match self.policy {
+            PoolPolicy::RoundRobin => candidates
+                .filter_map(|slot| slot.client().map(|client| (slot, client)))
+                .next()?,
+            // Ties go to the round-robin order so idle connections share load
+            PoolPolicy::LeastRequest => candidates
+                .filter_map(|slot| slot.client().map(|client| (slot, client)))
+                .min_by_key(|(slot, _)| slot.outstanding.load(Ordering::Relaxed))?,
+        };
+
+        slot.outstanding.fetch_add(1, Ordering::Relaxed);
+        slot.publish_outstanding();
+        REGISTRY.inc_counter(
+            CHECKOUTS_TOTAL,
+            "Calls assigned to a pooled backend connection.",
+            &[("connection", &slot.label)],
+        );
+        Some(PooledClient {
+            client,
+            slot: slot.clone(),
+        })
+    }
+}
+
+/// A leased backend client. Derefs to [`LandingClient`]; dropping it ends
+/// the outstanding request.
+pub struct PooledClient {
+    client: LandingClient,
+    slot: Arc<Slot>,
+}
+
+impl PooledClient {
+    /// The connection the lease is on, as labelled in the pool metrics.
+    pub fn connection(&self) -> &str {
+        &self.slot.label
+    }
+
+    /// Feeds a call outcome into the connection's failure tracking. Only
+    /// `UNAVAILABLE`, which is what transport errors surface as, counts
+    /// against the connection; application errors and calls failed fast by
+    /// the circuit breaker say nothing about it.
+    pub fn report<T>(&self, result: &Result<T, Status>) {
+        match result {
+            Ok(_) => {
+                self.slot.failures.store(0, Ordering::Relaxed);
+            }
+            Err(status) if status.code() == Code::Unavailable && !breaker::is_rejection(status) => {
+                REGISTRY.inc_counter(
+                    FAILURES_TOTAL,
+                    "Connection-level failures seen on a pooled backend connection.",
+                    &[("connection", &self.slot.label)],
+                );
+                let failures = self.slot.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= FAILURE_THRESHOLD {
+                    warn!(
+                        "Connection #{} failed {} times in a row, rebuilding",
+                        self.slot.label, failures
+                    );
+                    self.slot.install(None);
+                    schedule_rebuild(self.slot.clone());
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = LandingClient;
+
+    fn deref(&self) -> &LandingClient {
+        &self.client
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut LandingClient {
+        &mut self.client
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        self.slot.outstanding.fetch_sub(1, Ordering::Relaxed);
+        self.slot.publish_outstanding();
+    }
+}
+
+/// Reconnects `slot` in the background until it succeeds. At most one
+/// rebuild runs per slot.
+fn schedule_rebuild(slot: Arc<Slot>) {
+    if slot.rebuilding.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut backoff = REBUILD_INITIAL_BACKOFF;
+        loop {
+            tokio::time::sleep(backoff).await;
+            match lazy_backend(&slot.builder).await {
+                Ok(backend) => {
+                    slot.failures.store(0, Ordering::Relaxed);
+                    slot.install(Some(backend));
+                    slot.rebuilding.store(false, Ordering::Release);
+                    REGISTRY.inc_counter(
+                        REBUILDS_TOTAL,
+                        "Pooled backend connections rebuilt after failing.",
+                        &[("connection", &slot.label)],
+                    );
+                    info!("Connection #{} rebuilt", slot.label);
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "Rebuilding connection #{} failed: {}, retrying in {:?}",
+                        slot.label, e, backoff
+                    );
+                    backoff = std::cmp::min(backoff * 2, REBUILD_MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
+
+async fn lazy_backend(builder: &ClientBuilder) -> Result<Backend, ConnectError> {
+    builder.clone().lazy(true).build().await
+}
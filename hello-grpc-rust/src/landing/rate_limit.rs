@@ -0,0 +1,414 @@
+//! Token-bucket rate limiting for `LandingService`.
+//!
+//! [`RateLimitLayer`] wraps the landing service and checks every call
+//! against a bucket chosen by its method and a key. Limits are JSON:
+//!
+//! ```json
+//! {
+//!   "key": "peer",
+//!   "default": {"rate": 50, "burst": 100},
+//!   "methods": {
+//!     "Talk": {"rate": 5, "burst": 10, "key": "header:client"},
+//!     "/hello.LandingService/TalkBidirectional": {"rate": 1}
+//!   }
+//! }
+//! ```
+//!
+//! `rate` is calls per second and `burst` the bucket size (defaults to
+//! `rate`, at least 1). A method is looked up by full path, then by name,
+//! then falls back to `default`; methods without a rule are not limited.
+//! Each method has its own buckets, one per key. The key is `global` (one
+//! bucket), `peer` (the caller's IP, the default) or `header:<name>` (the
+//! value of a request header such as `client`; callers without it share one
+//! bucket). At most [`MAX_BUCKETS`] buckets are held: full ones are swept
+//! out every few seconds, and keys arriving while the cap is reached share
+//! one overflow bucket.
+//!
+//! A limited call fails with `RESOURCE_EXHAUSTED`, carrying `retry-after`
+//! (whole seconds) and `grpc-retry-pushback-ms` trailers, and is counted as
+//! `grpc_server_rate_limited_total`. Limits come from
+//! `GRPC_HELLO_RATE_LIMIT` (the JSON itself) or
+//! `GRPC_HELLO_RATE_LIMIT_FILE`. The file is reloaded when it changes; a
+//! file that fails to parse leaves the previous limits in place.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tonic::Status;
+use tonic::body::Body;
+use tonic::metadata::MetadataValue;
+use tonic::server::NamedService;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tower::{Layer, Service};
+
+use crate::common::framing::split_grpc_path;
+use crate::common::metrics::REGISTRY;
+use crate::common::watch::watch_files;
+
+const RATE_LIMITED_TOTAL: &str = "grpc_server_rate_limited_total";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+/// Buckets held at most; new keys beyond it share the overflow bucket.
+pub const MAX_BUCKETS: usize = 10_000;
+/// How often buckets that have refilled are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct RawConfig {
+    key: Option<String>,
+    default: Option<RawRule>,
+    #[serde(default)]
+    methods: HashMap<String, RawRule>,
+}
+
+#[derive(Deserialize)]
+struct RawRule {
+    rate: f64,
+    burst: Option<f64>,
+    key: Option<String>,
+}
+
+/// What a call's bucket is keyed by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    Global,
+    Peer,
+    Header(String),
+}
+
+impl KeySource {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "global" => Ok(KeySource::Global),
+            "peer" => Ok(KeySource::Peer),
+            other => match other.strip_prefix("header:") {
+                Some(name) if !name.is_empty() => Ok(KeySource::Header(name.to_ascii_lowercase())),
+                _ => Err(format!(
+                    "invalid key {:?}, expected global, peer or header:<name>",
+                    value
+                )),
+            },
+        }
+    }
+
+    fn key(&self, peer: Option<IpAddr>, headers: &http::HeaderMap) -> String {
+        match self {
+            KeySource::Global => String::new(),
+            KeySource::Peer => peer.map(|ip| ip.to_string()).unwrap_or_default(),
+            KeySource::Header(name) => headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+}
+
+/// The limit for one method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub rate: f64,
+    pub burst: f64,
+    pub key: KeySource,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitConfig {
+    default: Option<Rule>,
+    methods: HashMap<String, Rule>,
+}
+
+impl RateLimitConfig {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let raw: RawConfig = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let key = match &raw.key {
+            Some(key) => KeySource::parse(key)?,
+            None => KeySource::Peer,
+        };
+        let rule = |name: &str, raw: &RawRule| -> Result<Rule, String> {
+            if raw.rate.is_nan() || raw.rate <= 0.0 {
+                return Err(format!("{}: rate must be above 0", name));
+            }
+            let burst = raw.burst.unwrap_or(raw.rate.max(1.0));
+            if burst.is_nan() || burst < 1.0 {
+                return Err(format!("{}: burst must be at least 1", name));
+            }
+            Ok(Rule {
+                rate: raw.rate,
+                burst,
+                key: match &raw.key {
+                    Some(key) => KeySource::parse(key).map_err(|e| format!("{}: {}", name, e))?,
+                    None => key.clone(),
+                },
+            })
+        };
+        let default = raw
+            .default
+            .as_ref()
+            .map(|raw| rule("default", raw))
+            .transpose()?;
+        let methods = raw
+            .methods
+            .iter()
+            .map(|(name, raw)| Ok((name.clone(), rule(name, raw)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(RateLimitConfig { default, methods })
+    }
+
+    /// The rule for a call path: by full path, then method name, then the
+    /// default.
+    pub fn rule(&self, path: &str) -> Option<&Rule> {
+        let (_, method) = split_grpc_path(path);
+        self.methods
+            .get(path)
+            .or_else(|| self.methods.get(&method))
+            .or(self.default.as_ref())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rule: &Rule, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rule.rate).min(rule.burst);
+        self.updated = now;
+    }
+}
+
+/// The buckets of every method and key.
+struct Buckets {
+    /// By method path and key.
+    by_key: HashMap<(String, String), Bucket>,
+    /// Shared by the keys that arrive while `by_key` is full.
+    overflow: Option<Bucket>,
+    next_sweep: Instant,
+}
+
+impl Buckets {
+    fn new() -> Self {
+        Buckets {
+            by_key: HashMap::new(),
+            overflow: None,
+            next_sweep: Instant::now() + SWEEP_INTERVAL,
+        }
+    }
+
+    /// Drops the buckets that have refilled, which are no different from new
+    /// ones, and those of methods no longer limited.
+    fn sweep(&mut self, config: &RateLimitConfig, now: Instant) {
+        self.by_key.retain(|(path, _), bucket| {
+            config.rule(path).is_some_and(|rule| {
+                bucket.refill(rule, now);
+                bucket.tokens < rule.burst
+            })
+        });
+        self.next_sweep = now + SWEEP_INTERVAL;
+    }
+
+    /// The bucket for a key, the overflow bucket when the key is new and
+    /// the cap is reached.
+    fn get(&mut self, key: (String, String), rule: &Rule, now: Instant) -> &mut Bucket {
+        let full = Bucket {
+            tokens: rule.burst,
+            updated: now,
+        };
+        if self.by_key.len() >= MAX_BUCKETS && !self.by_key.contains_key(&key) {
+            return self.overflow.get_or_insert(full);
+        }
+        self.by_key.entry(key).or_insert(full)
+    }
+}
+
+/// Buckets for the current limits, shared by every call.
+pub struct RateLimiter {
+    config: RwLock<Arc<RateLimitConfig>>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config: RwLock::new(Arc::new(config)),
+            buckets: Mutex::new(Buckets::new()),
+        }
+    }
+
+    /// Swaps in new limits. Buckets start over full.
+    pub fn reload(&self, config: RateLimitConfig) {
+        *self.config.write().unwrap() = Arc::new(config);
+        *self.buckets.lock().unwrap() = Buckets::new();
+    }
+
+    /// How many keys have a bucket of their own.
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.lock().unwrap().by_key.len()
+    }
+
+    /// Takes a token for a call, or returns how long until one is available.
+    pub fn check(
+        &self,
+        path: &str,
+        peer: Option<IpAddr>,
+        headers: &http::HeaderMap,
+    ) -> Result<(), Duration> {
+        let config = self.config.read().unwrap().clone();
+        let Some(rule) = config.rule(path) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now >= buckets.next_sweep {
+            buckets.sweep(&config, now);
+        }
+        let bucket = buckets.get((path.to_string(), rule.key.key(peer, headers)), rule, now);
+        bucket.refill(rule, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rule.rate))
+        }
+    }
+}
+
+/// Reads the limits from `GRPC_HELLO_RATE_LIMIT` or
+/// `GRPC_HELLO_RATE_LIMIT_FILE`, watching the file for changes. `None` when
+/// neither is set.
+pub fn from_env() -> Result<Option<Arc<RateLimiter>>, String> {
+    if let Ok(json) = env::var("GRPC_HELLO_RATE_LIMIT") {
+        let config =
+            RateLimitConfig::parse(&json).map_err(|e| format!("GRPC_HELLO_RATE_LIMIT: {}", e))?;
+        info!("Rate limits loaded from GRPC_HELLO_RATE_LIMIT");
+        return Ok(Some(Arc::new(RateLimiter::new(config))));
+    }
+    let Ok(path) = env::var("GRPC_HELLO_RATE_LIMIT_FILE") else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    let limiter = Arc::new(RateLimiter::new(read_config(&path)?));
+    info!("Rate limits loaded from {:?}", path);
+
+    let reloaded = limiter.clone();
+    let watched = path.clone();
+    watch_files(vec![path], RELOAD_INTERVAL, move || {
+        match read_config(&watched) {
+            Ok(config) => {
+                reloaded.reload(config);
+                info!("Rate limits reloaded from {:?}", watched);
+            }
+            Err(e) => warn!("Keeping the current rate limits: {}", e),
+        }
+    });
+    Ok(Some(limiter))
+}
+
+fn read_config(path: &PathBuf) -> Result<RateLimitConfig, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read rate limits {:?}: {}", path, e))?;
+    RateLimitConfig::parse(&json).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+/// The caller's IP, over plaintext or TLS.
+pub(crate) fn peer_ip<B>(request: &http::Request<B>) -> Option<IpAddr> {
+    let extensions = request.extensions();
+    extensions
+        .get::<TcpConnectInfo>()
+        .or_else(|| {
+            extensions
+                .get::<TlsConnectInfo<TcpConnectInfo>>()
+                .map(TlsConnectInfo::get_ref)
+        })
+        .and_then(TcpConnectInfo::remote_addr)
+        .map(|addr| addr.ip())
+}
+
+fn limited(retry_after: Duration) -> Status {
+    let mut status = Status::resource_exhausted("rate limit exceeded");
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(seconds.max(1)));
+    status.metadata_mut().insert(
+        "grpc-retry-pushback-ms",
+        MetadataValue::from(retry_after.as_millis().max(1) as u64),
+    );
+    status
+}
+
+/// Limits the wrapped service; a pass-through without a limiter.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Option<Arc<RateLimiter>>) -> Self {
+        RateLimitLayer { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<S: NamedService> NamedService for RateLimitService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        if let Some(limiter) = &self.limiter {
+            let path = request.uri().path();
+            if let Err(retry_after) = limiter.check(path, peer_ip(&request), request.headers()) {
+                let (service, method) = split_grpc_path(path);
+                debug!("{} rate limited, retry after {:?}", path, retry_after);
+                REGISTRY.inc_counter(
+                    RATE_LIMITED_TOTAL,
+                    "Calls rejected with RESOURCE_EXHAUSTED by the rate limiter.",
+                    &[("grpc_service", &service), ("grpc_method", &method)],
+                );
+                let response = limited(retry_after).into_http();
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
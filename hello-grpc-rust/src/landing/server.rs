@@ -1,92 +1,71 @@
 use chrono::prelude::*;
-use futures::stream;
 use futures::{Stream, StreamExt};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 use tonic::{
-    IntoRequest, Request, Response, Status, Streaming,
+    Request, Response, Status, Streaming,
     codec::CompressionEncoding,
     metadata::{KeyAndValueRef, MetadataMap},
-    transport::{Channel, Identity, Server, ServerTlsConfig},
+    transport::Server,
 };
-use tonic_health::server::health_reporter;
+use tonic_health::ServingStatus;
+use tonic_health::server::{HealthReporter, health_reporter};
 use tonic_reflection::server::Builder as ReflectionBuilder;
+use tower::Layer;
 use uuid::Uuid;
 
 use hello_grpc_rust::common::FILE_DESCRIPTOR_SET;
-use hello_grpc_rust::common::conn::{CONFIG_PATH, build_client, grpc_backend_host, has_backend};
+use hello_grpc_rust::common::cert_expiry;
+use hello_grpc_rust::common::conn::{CONFIG_PATH, grpc_backend_host, has_backend};
+use hello_grpc_rust::common::deadline::{backend_budget, deadline_margin, expired};
 use hello_grpc_rust::common::etcd;
-use hello_grpc_rust::common::landing::landing_service_client::LandingServiceClient;
 use hello_grpc_rust::common::landing::landing_service_server::{
     LandingService, LandingServiceServer,
 };
 use hello_grpc_rust::common::landing::{ResultType, TalkRequest, TalkResponse, TalkResult};
-use hello_grpc_rust::common::trans::{TRACING_KEYS, server_cert_chain, server_cert_key};
+use hello_grpc_rust::common::metadata_policy::PropagationPolicy;
+use hello_grpc_rust::common::retry::AbortStream;
 use hello_grpc_rust::common::utils::{HELLOS, get_version, thanks};
-
-// Add a lightweight metrics collector
-struct ServerMetrics {
-    requests_total: std::sync::atomic::AtomicUsize,
-    errors_total: std::sync::atomic::AtomicUsize,
-    last_request_time: Mutex<Option<DateTime<Utc>>>,
-}
-
-impl ServerMetrics {
-    fn new() -> Self {
-        ServerMetrics {
-            requests_total: std::sync::atomic::AtomicUsize::new(0),
-            errors_total: std::sync::atomic::AtomicUsize::new(0),
-            last_request_time: Mutex::new(None),
-        }
-    }
-
-    async fn record_request(&self) {
-        self.requests_total
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let mut last_time = self.last_request_time.lock().await;
-        *last_time = Some(Utc::now());
-    }
-
-    fn record_error(&self) {
-        self.errors_total
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    }
-
-    async fn get_stats(&self) -> HashMap<String, String> {
-        let mut stats = HashMap::new();
-        stats.insert(
-            "requests_total".to_string(),
-            self.requests_total
-                .load(std::sync::atomic::Ordering::Relaxed)
-                .to_string(),
-        );
-        stats.insert(
-            "errors_total".to_string(),
-            self.errors_total
-                .load(std::sync::atomic::Ordering::Relaxed)
-                .to_string(),
-        );
-        if let Some(last_time) = *self.last_request_time.lock().await {
-            stats.insert("last_request_time".to_string(), last_time.to_rfc3339());
-        }
-        stats
-    }
-}
+use hello_grpc_rust::landing::auth::{self, Claims};
+use hello_grpc_rust::landing::authz::{self, AuthzLayer};
+use hello_grpc_rust::landing::log_formatter::extract_peer_identity;
+use hello_grpc_rust::landing::metrics::{RpcMetricsLayer, serve_metrics};
+use hello_grpc_rust::landing::mtls::{self, ClientAuth};
+use hello_grpc_rust::landing::pool::{ConnectionPool, PoolPolicy, PooledClient};
+use hello_grpc_rust::landing::rate_limit::{self, RateLimitLayer};
+use hello_grpc_rust::landing::tls;
+use hello_grpc_rust::landing::trace_context::TraceContextLayer;
+use hello_grpc_rust::landing::trailers::{ForwardedTrailers, ForwardedTrailersLayer};
 
 // Configure connection pool size and timeouts
 const CONNECTION_POOL_SIZE: usize = 5;
 const REQUEST_TIMEOUT_MS: u64 = 5000;
 const GRACEFUL_SHUTDOWN_TIMEOUT_MS: u64 = 10000;
+const BACKEND_HEALTH_INTERVAL_MS: u64 = 1000;
 
 /// C5 — Logging interceptor: logs each incoming gRPC request before forwarding.
-fn log_request(req: Request<()>) -> Result<Request<()>, Status> {
-    info!("gRPC request intercepted: {:?}", req.metadata());
+/// The caller's token is marked sensitive so it is never logged; the
+/// principal of a verified token and the client certificate identity are
+/// logged instead.
+fn log_request(mut req: Request<()>) -> Result<Request<()>, Status> {
+    if let Some(token) = req.metadata_mut().get_mut("authorization") {
+        token.set_sensitive(true);
+    }
+    let mut caller = String::new();
+    if let Some(principal) = req.extensions().get::<Claims>().and_then(Claims::subject) {
+        caller.push_str(&format!("principal={} ", principal));
+    }
+    if let Some(identity) = extract_peer_identity(&req) {
+        caller.push_str(&format!("peer_cert=[{}] ", identity));
+    }
+    info!("gRPC request intercepted: {}{:?}", caller, req.metadata());
     Ok(req)
 }
 
@@ -94,6 +73,15 @@ fn log_request(req: Request<()>) -> Result<Request<()>, Status> {
 /// Configures and starts the server with appropriate TLS settings if enabled.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // `gen-certs` writes development certificates and exits
+    if std::env::args().nth(1).as_deref() == Some("gen-certs") {
+        if let Err(e) = hello_grpc_rust::common::certgen::cli(std::env::args().skip(2)) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        return Ok(());
+    }
+
     // Initialize rustls crypto provider
     let _ = rustls::crypto::ring::default_provider().install_default();
 
@@ -105,17 +93,15 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Initialize logging
     log4rs::init_file(CONFIG_PATH, Default::default())?;
 
-    // Register with etcd if discovery is enabled.
-    // The returned `oneshot::Sender` would be used to stop the keepalive task
-    // on graceful shutdown, but we currently rely on process-exit cleanup;
-    // suppress the unused warning by prefixing with `_`.
-    let _etcd_cleanup = if etcd::is_etcd_discovery() {
+    // Register with etcd if discovery is enabled. The registration is
+    // revoked as the last step of graceful shutdown.
+    let etcd_registration = if etcd::is_etcd_discovery() {
         let port: u16 = get_server_port().parse().unwrap_or(9996);
         let host = std::env::var("GRPC_SERVER").unwrap_or_else(|_| "localhost".to_string());
         match etcd::register_to_etcd(&host, port).await {
-            Ok(tx) => {
+            Ok(registration) => {
                 info!("Registered with etcd service discovery");
-                Some(tx)
+                Some(registration)
             }
             Err(e) => {
                 error!("Failed to register with etcd: {}", e);
@@ -126,61 +112,55 @@ async fn main() -> Result<(), Box<dyn Error>> {
         None
     };
 
-    let address = format!("[::0]:{}", get_server_port()).parse()?;
+    let address: std::net::SocketAddr = format!("[::0]:{}", get_server_port()).parse()?;
     let is_tls = env::var("GRPC_HELLO_SECURE").unwrap_or_default();
-
-    // Configure server with or without TLS
-    let mut server = if is_tls == "Y" {
-        let cert = tokio::fs::read(server_cert_chain()).await?;
-        let key = tokio::fs::read(server_cert_key()).await?;
-        let identity = Identity::from_pem(cert, key);
-
+    let client_auth = ClientAuth::from_env()?;
+
+    // With TLS the handshake runs in tls.rs with a config that is reloaded
+    // when the certificate files change; tonic serves the finished streams.
+    let tls_config = if is_tls == "Y" {
+        if client_auth != ClientAuth::Off {
+            info!(
+                "Mutual TLS {:?}, client CA bundle {:?}",
+                client_auth,
+                mtls::client_ca_file()
+            );
+        }
         info!(
             "Starting gRPC TLS server on port {} [version: {}]",
             get_server_port(),
             get_version()
         );
-
-        Server::builder()
-            .tls_config(ServerTlsConfig::new().identity(identity))?
-            // Server-side HTTP/2 keepalive, mirroring the Go server settings.
-            .http2_keepalive_interval(Some(Duration::from_secs(30)))
-            .http2_keepalive_timeout(Some(Duration::from_secs(5)))
-            .timeout(Duration::from_millis(REQUEST_TIMEOUT_MS)) // Add request timeout
+        Some(tls::reloading_server_config(client_auth)?)
     } else {
+        if client_auth != ClientAuth::Off {
+            warn!("GRPC_HELLO_MTLS has no effect without GRPC_HELLO_SECURE=Y");
+        }
         info!(
             "Starting gRPC server on port {} [version: {}]",
             get_server_port(),
             get_version()
         );
-
-        Server::builder()
-            // Server-side HTTP/2 keepalive, mirroring the Go server settings.
-            .http2_keepalive_interval(Some(Duration::from_secs(30)))
-            .http2_keepalive_timeout(Some(Duration::from_secs(5)))
-            .timeout(Duration::from_millis(REQUEST_TIMEOUT_MS)) // Add request timeout
+        None
     };
-
-    // Create shared metrics
-    let metrics = Arc::new(ServerMetrics::new());
+    let server = Server::builder()
+        // Server-side HTTP/2 keepalive, mirroring the Go server settings.
+        .http2_keepalive_interval(Some(Duration::from_secs(30)))
+        .http2_keepalive_timeout(Some(Duration::from_secs(5)))
+        .timeout(Duration::from_millis(REQUEST_TIMEOUT_MS)); // Add request timeout
 
     // Create connection pool if backend is configured
     let client_pool = if has_backend() {
+        let policy = PoolPolicy::from_env();
         info!(
-            "Operating in proxy mode with backend at {} (pool size: {})",
+            "Operating in proxy mode with backend at {} (pool size: {}, policy: {:?})",
             grpc_backend_host(),
-            CONNECTION_POOL_SIZE
+            CONNECTION_POOL_SIZE,
+            policy
         );
-
-        let mut pool = Vec::with_capacity(CONNECTION_POOL_SIZE);
-        for i in 0..CONNECTION_POOL_SIZE {
-            // build_client() returns the client directly, not a Result
-            let client = build_client().await;
-            pool.push(Some(client));
-            info!("Created connection #{} in pool", i);
-        }
-
-        Some(Arc::new(Mutex::new(pool)))
+        Some(Arc::new(
+            ConnectionPool::connect(CONNECTION_POOL_SIZE, policy).await,
+        ))
     } else {
         info!("Operating in standalone mode (no backend)");
         None
@@ -197,25 +177,47 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // `LandingService` trait object and does not let us pass an already-built
     // `LandingServiceServer`. Instead we construct the server with compression
     // enabled, then wrap it in tonic's `InterceptedService` directly.
+    // The interceptor checks the caller's bearer token (see auth.rs) before
+    // logging the call; calls are then checked against the authorization
+    // policy (see authz.rs) and rate limited per method (see rate_limit.rs).
+    let verifier = auth::from_env()?;
+    let authz = AuthzLayer::new(authz::from_env()?);
+    let rate_limit = RateLimitLayer::new(rate_limit::from_env()?);
     let service = tonic::service::interceptor::InterceptedService::new(
-        LandingServiceServer::new(ProtoServer {
-            backend: if has_backend() {
-                grpc_backend_host()
-            } else {
-                "".to_string()
-            },
-            client_pool,
-            metrics: metrics.clone(),
-        })
-        .accept_compressed(CompressionEncoding::Gzip),
-        log_request,
+        authz.layer(
+            rate_limit.layer(
+                LandingServiceServer::new(ProtoServer {
+                    backend: if has_backend() {
+                        grpc_backend_host()
+                    } else {
+                        "".to_string()
+                    },
+                    client_pool: client_pool.clone(),
+                    deadline_margin: deadline_margin(),
+                    propagation: Arc::new(PropagationPolicy::from_env()),
+                    forward_token: env::var("GRPC_HELLO_FORWARD_TOKEN").unwrap_or_default() != "N",
+                })
+                .accept_compressed(CompressionEncoding::Gzip),
+            ),
+        ),
+        move |req| {
+            let req = mtls::attach_peer_identity(req);
+            log_request(auth::authenticate(verifier.as_deref(), req)?)
+        },
     );
 
     // B7 — Health check service
     let (health_reporter, health_service) = health_reporter();
-    health_reporter
-        .set_serving::<LandingServiceServer<ProtoServer>>()
-        .await;
+    // A proxy serves only while it has a connected backend
+    let backend_health = match client_pool {
+        Some(pool) => Some(tokio::spawn(watch_backend(pool, health_reporter.clone()))),
+        None => {
+            health_reporter
+                .set_serving::<LandingServiceServer<ProtoServer>>()
+                .await;
+            None
+        }
+    };
     info!("Health check service registered");
 
     // C4 — Server reflection service
@@ -225,95 +227,128 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .expect("Failed to build reflection service");
     info!("Server reflection service registered");
 
-    // Add metrics endpoint using hyper 1.x API
-    let metrics_clone = metrics.clone();
-    tokio::spawn(async move {
-        use hyper::server::conn::http1;
-        use hyper::service::service_fn;
-        use hyper::{Request, Response};
-        use hyper_util::rt::TokioIo;
-        use tokio::net::TcpListener;
-
-        let metrics_port = get_server_port().parse::<u16>().unwrap_or(50051) + 1;
-        let metrics_address = format!("[::0]:{}", metrics_port);
-        info!("Starting metrics server on port {}", metrics_port);
-
-        let listener = match TcpListener::bind(&metrics_address).await {
-            Ok(l) => l,
-            Err(e) => {
-                error!("Failed to bind metrics server: {}", e);
-                return;
-            }
-        };
-
-        loop {
-            let (stream, _) = match listener.accept().await {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("Failed to accept connection: {}", e);
-                    continue;
-                }
-            };
-
-            let metrics = metrics_clone.clone();
-            tokio::spawn(async move {
-                let io = TokioIo::new(stream);
-                let service = service_fn(move |_req: Request<hyper::body::Incoming>| {
-                    let metrics = metrics.clone();
-                    async move {
-                        let stats = metrics.get_stats().await;
-                        let mut response_text = String::new();
-                        for (k, v) in stats {
-                            response_text.push_str(&format!("{}={}\n", k, v));
-                        }
-                        Ok::<_, hyper::Error>(Response::new(response_text))
-                    }
-                });
-
-                if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
-                    error!("Error serving metrics connection: {}", e);
-                }
-            });
-        }
-    });
-
-    // Create the server future with all services on the same router
-    let server_future = server
+    // Prometheus exposition endpoint on the port after the gRPC port
+    let metrics_port = get_server_port().parse::<u16>().unwrap_or(50051) + 1;
+    tokio::spawn(serve_metrics(metrics_port));
+    // Keeps the certificate expiry gauges current and warns before expiry
+    cert_expiry::spawn_monitor();
+
+    // Create the server future with all services on the same router.
+    // When `drain_rx` fires, tonic stops accepting new connections, sends an
+    // HTTP/2 GOAWAY on every open connection and resolves once all in-flight
+    // streams have completed.
+    let (drain_tx, drain_rx) = oneshot::channel::<()>();
+    // Every call through the router is counted per method, code and mode
+    let mode = if has_backend() { "proxy" } else { "standalone" };
+    let router = server
+        .layer(RpcMetricsLayer::new(mode))
+        .layer(TraceContextLayer)
+        .layer(ForwardedTrailersLayer)
         .add_service(service)
         .add_service(health_service)
-        .add_service(reflection_service)
-        .serve(address);
-
-    // Use a separate future for shutdown signal
-    let shutdown = shutdown_signal();
+        .add_service(reflection_service);
+    let drained = async {
+        let _ = drain_rx.await;
+    };
+    let mut server_task = match tls_config {
+        Some(config) => {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            tokio::spawn(
+                router.serve_with_incoming_shutdown(tls::incoming(listener, config), drained),
+            )
+        }
+        None => tokio::spawn(router.serve_with_shutdown(address, drained)),
+    };
 
     // Wait for either server completion or shutdown signal
-    tokio::select! {
-        result = server_future => {
+    let signalled = tokio::select! {
+        result = &mut server_task => {
             // This branch should only execute if the server encounters an error
             error!("Server exited unexpectedly: {:?}", result);
+            false
+        }
+        _ = shutdown_signal() => true,
+    };
+
+    if signalled {
+        // 1. Tell load balancers and health probes to stop routing to us
+        info!("Server shutting down gracefully, reporting NOT_SERVING");
+        if let Some(watcher) = backend_health {
+            watcher.abort();
         }
-        _ = shutdown => {
-            info!("Server shutting down gracefully");
+        health_reporter
+            .set_not_serving::<LandingServiceServer<ProtoServer>>()
+            .await;
+        health_reporter
+            .set_service_status("", ServingStatus::NotServing)
+            .await;
+
+        // 2. Stop accepting connections and send GOAWAY to existing ones
+        let _ = drain_tx.send(());
+
+        // 3. Wait for in-flight RPCs to finish, up to the deadline
+        let deadline = Duration::from_millis(GRACEFUL_SHUTDOWN_TIMEOUT_MS);
+        match tokio::time::timeout(deadline, &mut server_task).await {
+            Ok(Ok(Ok(()))) => info!("All connections drained"),
+            Ok(Ok(Err(e))) => error!("Server error while draining: {}", e),
+            Ok(Err(e)) => error!("Server task failed while draining: {}", e),
+            Err(_) => {
+                warn!(
+                    "Drain deadline of {}ms exceeded, aborting remaining RPCs",
+                    GRACEFUL_SHUTDOWN_TIMEOUT_MS
+                );
+                server_task.abort();
+            }
         }
     }
 
-    // Wait for any ongoing requests to complete (maximum wait time)
-    tokio::time::sleep(Duration::from_millis(GRACEFUL_SHUTDOWN_TIMEOUT_MS)).await;
+    // 4. Revoke the etcd lease so clients stop resolving this instance
+    if let Some(registration) = etcd_registration {
+        registration.deregister().await;
+    }
     info!("Server shutdown complete");
+    hello_grpc_rust::otel::shutdown_otel();
 
     Ok(())
 }
 
+/// Reports the landing service, and the server as a whole, NOT_SERVING
+/// while no pooled connection to the backend is connected, and SERVING once
+/// one is.
+async fn watch_backend(pool: Arc<ConnectionPool>, reporter: HealthReporter) {
+    let mut serving = None;
+    let mut interval = tokio::time::interval(Duration::from_millis(BACKEND_HEALTH_INTERVAL_MS));
+    loop {
+        interval.tick().await;
+        let up = pool.healthy() > 0;
+        if serving == Some(up) {
+            continue;
+        }
+        if up {
+            info!("Backend is connected, reporting SERVING");
+            reporter
+                .set_serving::<LandingServiceServer<ProtoServer>>()
+                .await;
+            reporter
+                .set_service_status("", ServingStatus::Serving)
+                .await;
+        } else {
+            warn!("Backend is not connected, reporting NOT_SERVING");
+            reporter
+                .set_not_serving::<LandingServiceServer<ProtoServer>>()
+                .await;
+            reporter
+                .set_service_status("", ServingStatus::NotServing)
+                .await;
+        }
+        serving = Some(up);
+    }
+}
+
 /// Waits for termination signals to initiate graceful shutdown.
-/// Handles CTRL+C on all platforms and SIGTERM on Unix platforms.
+/// Handles CTRL+C on all platforms and SIGTERM on Unix platforms;
+/// whichever arrives first starts the drain.
 async fn shutdown_signal() {
-    // Wait for CTRL+C
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler");
-
-    // Wait for SIGTERM
     #[cfg(unix)]
     {
         let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
@@ -324,7 +359,7 @@ async fn shutdown_signal() {
                 info!("Received SIGTERM signal");
             }
             _ = tokio::signal::ctrl_c() => {
-                info!("Received second CTRL+C signal");
+                info!("Received CTRL+C signal");
             }
         }
     }
@@ -333,8 +368,8 @@ async fn shutdown_signal() {
     {
         tokio::signal::ctrl_c()
             .await
-            .expect("Failed to install second CTRL+C signal handler");
-        info!("Received second CTRL+C signal");
+            .expect("Failed to install CTRL+C signal handler");
+        info!("Received CTRL+C signal");
     }
 }
 
@@ -343,32 +378,59 @@ fn get_server_port() -> String {
     env::var("GRPC_SERVER_PORT").unwrap_or_else(|_| "9996".to_string())
 }
 
-// Helper function to propagate tracing headers
-fn propagate_headers(request: &mut Request<TalkRequest>) -> MetadataMap {
-    let mut headers_map = MetadataMap::new();
-
-    // First collect the values we need to copy
-    let mut values_to_copy = Vec::new();
-    for &key_name in TRACING_KEYS {
-        if let Some(value) = request.metadata().get(key_name) {
-            debug!("Propagating tracing header: {}={:?}", key_name, value);
-            values_to_copy.push((key_name, value.clone()));
+/// Turns an inbound request stream into the outbound stream for a proxied
+/// call. Messages are forwarded one at a time and never buffered.
+///
+/// If the caller's stream fails, its status is handed over through the
+/// returned receiver. The outbound stream then stops yielding but stays open,
+/// so the backend never sees a clean half-close for a broken upload. The
+/// receiver reports `Err(RecvError)` once the inbound stream ended normally.
+///
+/// hyper keeps the HTTP/2 stream alive for as long as it is still sending the
+/// request body, so dropping the backend response alone cancels nothing, and
+/// ending the outbound stream would half-close it as if the upload were
+/// complete. The backend request is therefore marked with an [`AbortStream`]:
+/// dropping its handle fails the request body, and hyper resets the
+/// backend stream.
+fn forward_inbound(
+    mut inbound: Streaming<TalkRequest>,
+) -> (
+    impl Stream<Item = TalkRequest> + Send + 'static,
+    oneshot::Receiver<Status>,
+) {
+    let (error_tx, error_rx) = oneshot::channel();
+    let mut error_tx = Some(error_tx);
+    let outbound = async_stream::stream! {
+        while let Some(result) = inbound.next().await {
+            match result {
+                Ok(request) => {
+                    debug!("Forwarding stream item - data: {}, meta: {}", request.data, request.meta);
+                    yield request;
+                }
+                Err(status) => {
+                    error!("Error receiving client stream: {}", status);
+                    if let Some(error_tx) = error_tx.take() {
+                        let _ = error_tx.send(status);
+                    }
+                    futures::future::pending::<()>().await;
+                }
+            }
         }
-    }
-
-    // Now insert the values into both maps
-    for (key, value) in values_to_copy {
-        request.metadata_mut().insert(key, value.clone());
-        headers_map.insert(key, value);
-    }
-
-    headers_map
+    };
+    (outbound, error_rx)
 }
 
 // Helper function to log metadata
-fn log_metadata(method: &str, metadata: &MetadataMap) {
-    debug!("Method: {} Metadata:", method);
-    for key_and_value in metadata.iter() {
+fn log_metadata<T>(method: &str, request: &Request<T>) {
+    match request
+        .extensions()
+        .get::<Claims>()
+        .and_then(Claims::subject)
+    {
+        Some(principal) => debug!("Method: {} Principal: {} Metadata:", method, principal),
+        None => debug!("Method: {} Metadata:", method),
+    }
+    for key_and_value in request.metadata().iter() {
         match key_and_value {
             KeyAndValueRef::Ascii(key, value) => {
                 debug!("  {}: {:?}", key.as_str(), value);
@@ -416,91 +478,139 @@ fn create_response(data: String) -> Result<TalkResult, Status> {
     })
 }
 
-type ClientPool = Arc<Mutex<Vec<Option<LandingServiceClient<Channel>>>>>;
-
 /// Implementation of the gRPC LandingService.
 /// Can operate either as a standalone server or as a proxy to a backend service.
 pub struct ProtoServer {
     /// The address of the backend service, empty if operating in standalone mode
     backend: String,
     /// Pool of clients for communicating with the backend service
-    client_pool: Option<ClientPool>,
-    /// Server metrics collector
-    metrics: Arc<ServerMetrics>,
+    client_pool: Option<Arc<ConnectionPool>>,
+    /// Part of the caller's deadline kept back for the proxy hop
+    deadline_margin: Duration,
+    /// Which request metadata, response headers and trailers cross the hop
+    propagation: Arc<PropagationPolicy>,
+    /// Whether the caller's `authorization` is passed on to the backend
+    forward_token: bool,
 }
 
 impl ProtoServer {
-    // Helper method to get a client from the connection pool
-    async fn get_client(&self) -> Option<LandingServiceClient<Channel>> {
-        if let Some(pool) = &self.client_pool {
-            let mut pool_guard = pool.lock().await;
-
-            // Find an available client in the pool
-            if let Some(client) = pool_guard.iter_mut().flatten().next() {
-                return Some(client.clone());
-            }
+    // Helper method to lease a client from the connection pool
+    fn get_client(&self) -> Option<PooledClient> {
+        self.client_pool
+            .as_deref()
+            .and_then(ConnectionPool::checkout)
+    }
 
-            // If no clients available, try to create a new one
-            // build_client() returns the client directly, not a Result
-            let client = build_client().await;
+    // Helper method to derive the backend call's timeout from the caller's
+    // deadline; fails with DEADLINE_EXCEEDED once nothing is left of it
+    fn backend_timeout(&self, metadata: &MetadataMap) -> Result<Option<Duration>, Status> {
+        backend_budget(metadata, self.deadline_margin)
+    }
 
-            // Try to replace a None slot if available
-            for client_opt in pool_guard.iter_mut() {
-                if client_opt.is_none() {
-                    *client_opt = Some(client.clone());
-                    return Some(client);
-                }
-            }
-            Some(client)
-        } else {
-            None
+    // Helper method to build the backend request: the message, the caller
+    // metadata the request policy lets through, the caller's token and the
+    // backend timeout
+    fn backend_request<T>(
+        &self,
+        metadata: &MetadataMap,
+        message: T,
+        timeout: Option<Duration>,
+    ) -> Request<T> {
+        let mut request = Request::new(message);
+        self.propagation
+            .request
+            .copy(metadata, request.metadata_mut());
+        if self.forward_token
+            && let Some(token) = metadata.get("authorization")
+        {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        if let Some(timeout) = timeout {
+            request.set_timeout(timeout);
         }
+        request
+    }
+
+    // Helper method to relay a single backend response with the headers and
+    // trailers the policies let through
+    fn forward_response(
+        &self,
+        response: Response<TalkResponse>,
+        trailers: Option<&ForwardedTrailers>,
+    ) -> Response<TalkResponse> {
+        let (metadata, message, _) = response.into_parts();
+        let mut forwarded = Response::new(message);
+        let mut extra = MetadataMap::new();
+        self.propagation
+            .split_response(&metadata, forwarded.metadata_mut(), &mut extra);
+        if let Some(trailers) = trailers {
+            trailers.set(extra);
+        }
+        forwarded
+    }
+}
+
+/// Relays the trailers of a finished backend stream through `slot`.
+async fn forward_stream_trailers(
+    policy: &PropagationPolicy,
+    stream: &mut Streaming<TalkResponse>,
+    slot: Option<&ForwardedTrailers>,
+) {
+    if let (Ok(Some(backend_trailers)), Some(slot)) = (stream.trailers().await, slot) {
+        let mut trailers = MetadataMap::new();
+        policy.trailers.copy(&backend_trailers, &mut trailers);
+        slot.set(trailers);
     }
 }
 
 #[tonic::async_trait]
 impl LandingService for ProtoServer {
     /// Implements the unary RPC method 'Talk'.
-    async fn talk(
-        &self,
-        mut request: Request<TalkRequest>,
-    ) -> Result<Response<TalkResponse>, Status> {
-        self.metrics.record_request().await;
-
+    async fn talk(&self, request: Request<TalkRequest>) -> Result<Response<TalkResponse>, Status> {
         let talk_request = request.get_ref();
         let data = &talk_request.data;
         let meta = &talk_request.meta;
         info!("Unary call received - data: {}, meta: {}", data, meta);
-        log_metadata("Talk", request.metadata());
+        log_metadata("Talk", &request);
 
         // If backend is configured, proxy the request
         if !self.backend.is_empty() {
-            // Propagate tracing headers
-            propagate_headers(&mut request);
+            // Without a caller deadline the server-wide request timeout applies
+            let timeout = self
+                .backend_timeout(request.metadata())?
+                .unwrap_or_else(|| {
+                    Duration::from_millis(REQUEST_TIMEOUT_MS).saturating_sub(self.deadline_margin)
+                });
 
-            match self.get_client().await {
-                Some(mut client) => {
-                    // Set timeout for the backend request
-                    let mut req = request.into_request();
-                    req.set_timeout(Duration::from_millis(REQUEST_TIMEOUT_MS - 500)); // Slightly shorter than server timeout
+            let trailers = request.extensions().get::<ForwardedTrailers>().cloned();
+            let req =
+                self.backend_request(request.metadata(), request.get_ref().clone(), Some(timeout));
 
-                    match client.talk(req).await {
+            match self.get_client() {
+                Some(mut client) => {
+                    // Enforced locally too, in case the backend never answers
+                    let result = tokio::time::timeout(timeout, client.talk(req))
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(Status::deadline_exceeded("backend call timed out"))
+                        });
+                    client.report(&result);
+                    match result {
                         Ok(response) => {
-                            let talk_response = response.get_ref();
                             info!("Proxy response received from backend");
-                            Ok(Response::new(talk_response.clone()))
+                            Ok(self.forward_response(response, trailers.as_ref()))
                         }
                         Err(status) => {
                             error!("Backend call failed: {}", status);
-                            self.metrics.record_error();
-                            Err(status)
+                            Err(self.propagation.trailers.filter_status(status))
                         }
                     }
                 }
                 None => {
                     error!("Backend configured but client not available");
-                    self.metrics.record_error();
-                    Err(Status::internal("Backend connection not available"))
+                    Err(Status::unavailable("Backend connection not available"))
                 }
             }
         } else {
@@ -528,23 +638,50 @@ impl LandingService for ProtoServer {
             "Server streaming call received - data: {}, meta: {}",
             talk_request.data, talk_request.meta
         );
-        log_metadata("TalkOneAnswerMore", request.metadata());
+        log_metadata("TalkOneAnswerMore", &request);
 
         let (tx, rx) = mpsc::channel(4);
+        let mut headers = MetadataMap::new();
 
         // If backend is configured, proxy the request
         if !self.backend.is_empty() {
-            if let Some(client) = self.get_client().await {
-                let mut client_clone = client.clone();
-
-                match client_clone.talk_one_answer_more(request).await {
+            let timeout = self.backend_timeout(request.metadata())?;
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let trailers = request.extensions().get::<ForwardedTrailers>().cloned();
+            let backend_request =
+                self.backend_request(request.metadata(), talk_request.clone(), timeout);
+
+            if let Some(mut client) = self.get_client() {
+                let result = client.talk_one_answer_more(backend_request).await;
+                client.report(&result);
+                match result {
                     Ok(response) => {
+                        self.propagation
+                            .headers
+                            .copy(response.metadata(), &mut headers);
                         let mut stream = response.into_inner();
+                        let propagation = self.propagation.clone();
 
-                        // Spawn a task to forward responses from backend to client
+                        // Spawn a task to forward responses from backend to
+                        // client; the lease is held until the stream ends
                         tokio::spawn(async move {
-                            while let Some(result) = stream.message().await.unwrap_or(None) {
-                                if tx.send(Ok(result)).await.is_err() {
+                            let _client = client;
+                            loop {
+                                let item = tokio::select! {
+                                    message = stream.message() => match message {
+                                        Ok(Some(response)) => Ok(response),
+                                        Ok(None) => {
+                                            forward_stream_trailers(&propagation, &mut stream, trailers.as_ref()).await;
+                                            break;
+                                        }
+                                        Err(status) => Err(propagation.trailers.filter_status(status)),
+                                    },
+                                    _ = expired(deadline) => {
+                                        Err(Status::deadline_exceeded("deadline exceeded while streaming from backend"))
+                                    }
+                                };
+                                let last = item.is_err();
+                                if tx.send(item).await.is_err() || last {
                                     break;
                                 }
                             }
@@ -552,12 +689,12 @@ impl LandingService for ProtoServer {
                     }
                     Err(status) => {
                         error!("Backend streaming call failed: {}", status);
-                        return Err(status);
+                        return Err(self.propagation.trailers.filter_status(status));
                     }
                 }
             } else {
                 error!("Backend configured but client not available");
-                return Err(Status::internal("Backend connection not available"));
+                return Err(Status::unavailable("Backend connection not available"));
             }
         } else {
             // Process locally
@@ -586,9 +723,10 @@ impl LandingService for ProtoServer {
         }
 
         // Return the receiver stream
-        Ok(Response::new(Box::pin(
-            tokio_stream::wrappers::ReceiverStream::new(rx),
-        )))
+        let mut response = Response::new(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx))
+            as Self::TalkOneAnswerMoreStream);
+        *response.metadata_mut() = headers;
+        Ok(response)
     }
 
     /// Implements the client streaming RPC method 'TalkMoreAnswerOne'.
@@ -597,43 +735,49 @@ impl LandingService for ProtoServer {
         request: Request<Streaming<TalkRequest>>,
     ) -> Result<Response<TalkResponse>, Status> {
         info!("Client streaming call received");
-        log_metadata("TalkMoreAnswerOne", request.metadata());
-
-        let mut inbound_stream = request.into_inner();
+        log_metadata("TalkMoreAnswerOne", &request);
 
         // If backend is configured, proxy the request
         if !self.backend.is_empty() {
-            if let Some(client) = self.get_client().await {
-                let mut client_clone = client.clone();
-                let mut requests = Vec::new();
-
-                // Collect all incoming requests
-                while let Some(result) = inbound_stream.next().await {
-                    match result {
-                        Ok(request) => requests.push(request),
-                        Err(status) => {
-                            error!("Error receiving client stream: {}", status);
-                            return Err(status);
+            let timeout = self.backend_timeout(request.metadata())?;
+            let trailers = request.extensions().get::<ForwardedTrailers>().cloned();
+            let (metadata, _, inbound_stream) = request.into_parts();
+
+            if let Some(mut client) = self.get_client() {
+                // Requests are forwarded as they arrive instead of being
+                // collected first
+                let (outbound, inbound_error) = forward_inbound(inbound_stream);
+                let (abort, _guard) = AbortStream::new();
+                let mut outbound = self.backend_request(&metadata, outbound, timeout);
+                outbound.extensions_mut().insert(abort);
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+                // A failed upload or the deadline wins the race: the backend
+                // call future is dropped and `_guard` goes with it, which
+                // resets the stream
+                tokio::select! {
+                    response = client.talk_more_answer_one(outbound) => {
+                        client.report(&response);
+                        match response {
+                            Ok(response) => Ok(self.forward_response(response, trailers.as_ref())),
+                            Err(status) => {
+                                error!("Backend client streaming call failed: {}", status);
+                                Err(self.propagation.trailers.filter_status(status))
+                            }
                         }
                     }
-                }
-
-                // Forward collected requests to backend
-                let outbound = Request::new(stream::iter(requests));
-
-                match client_clone.talk_more_answer_one(outbound).await {
-                    Ok(response) => Ok(Response::new(response.into_inner())),
-                    Err(status) => {
-                        error!("Backend client streaming call failed: {}", status);
-                        Err(status)
+                    Ok(status) = inbound_error => Err(status),
+                    _ = expired(deadline) => {
+                        Err(Status::deadline_exceeded("deadline exceeded while streaming to backend"))
                     }
                 }
             } else {
                 error!("Backend configured but client not available");
-                Err(Status::internal("Backend connection not available"))
+                Err(Status::unavailable("Backend connection not available"))
             }
         } else {
             // Process locally
+            let mut inbound_stream = request.into_inner();
             let mut results = Vec::new();
 
             // Process each incoming request
@@ -672,60 +816,89 @@ impl LandingService for ProtoServer {
         request: Request<Streaming<TalkRequest>>,
     ) -> Result<Response<Self::TalkBidirectionalStream>, Status> {
         info!("Bidirectional streaming call received");
-        log_metadata("TalkBidirectional", request.metadata());
-
-        let mut request_stream = request.into_inner();
+        log_metadata("TalkBidirectional", &request);
 
         // If backend is configured, proxy the request
         if !self.backend.is_empty() {
-            if let Some(client) = self.get_client().await {
-                let mut client_clone = client.clone();
-                let (tx, rx) = mpsc::channel(4);
-
-                // Collect all incoming requests
-                let mut requests = Vec::new();
-                while let Some(result) = request_stream.next().await {
-                    match result {
-                        Ok(request) => requests.push(request),
-                        Err(status) => {
-                            error!("Error receiving bidirectional stream: {}", status);
-                            return Err(status);
-                        }
-                    }
-                }
-
-                // Forward collected requests to backend
-                let outbound = Request::new(stream::iter(requests));
-
-                match client_clone.talk_bidirectional(outbound).await {
+            let timeout = self.backend_timeout(request.metadata())?;
+            let trailers = request.extensions().get::<ForwardedTrailers>().cloned();
+            let (metadata, _, request_stream) = request.into_parts();
+
+            if let Some(mut client) = self.get_client() {
+                // Requests are forwarded as they arrive; hyper only pulls the
+                // next one when the backend stream has flow-control credit
+                let (outbound, mut inbound_error) = forward_inbound(request_stream);
+                let (abort, guard) = AbortStream::new();
+                let mut outbound = self.backend_request(&metadata, outbound, timeout);
+                outbound.extensions_mut().insert(abort);
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+                let result = client.talk_bidirectional(outbound).await;
+                client.report(&result);
+                match result {
                     Ok(response) => {
+                        let mut headers = MetadataMap::new();
+                        self.propagation
+                            .headers
+                            .copy(response.metadata(), &mut headers);
                         let mut response_stream = response.into_inner();
-
-                        // Spawn a task to forward responses
-                        tokio::spawn(async move {
-                            while let Some(result) = response_stream.message().await.unwrap_or(None)
-                            {
-                                if tx.send(Ok(result)).await.is_err() {
-                                    break;
+                        let propagation = self.propagation.clone();
+
+                        // Responses are pulled from the backend only as fast as
+                        // the caller reads them. Dropping this stream (caller
+                        // gone, or an inbound error below) resets the backend
+                        // call.
+                        let output = async_stream::stream! {
+                            let _guard = guard;
+                            let _client = client;
+                            let mut inbound_open = true;
+                            loop {
+                                tokio::select! {
+                                    failed = &mut inbound_error, if inbound_open => match failed {
+                                        Ok(status) => {
+                                            yield Err(status);
+                                            break;
+                                        }
+                                        // Inbound half-closed cleanly
+                                        Err(_) => inbound_open = false,
+                                    },
+                                    message = response_stream.message() => match message {
+                                        Ok(Some(response)) => yield Ok(response),
+                                        Ok(None) => {
+                                            forward_stream_trailers(&propagation, &mut response_stream, trailers.as_ref()).await;
+                                            break;
+                                        }
+                                        Err(status) => {
+                                            error!("Backend bidirectional stream failed: {}", status);
+                                            yield Err(propagation.trailers.filter_status(status));
+                                            break;
+                                        }
+                                    },
+                                    _ = expired(deadline) => {
+                                        yield Err(Status::deadline_exceeded("deadline exceeded while streaming with backend"));
+                                        break;
+                                    }
                                 }
                             }
-                        });
+                        };
 
-                        Ok(Response::new(Box::pin(
-                            tokio_stream::wrappers::ReceiverStream::new(rx),
-                        )))
+                        let mut response =
+                            Response::new(Box::pin(output) as Self::TalkBidirectionalStream);
+                        *response.metadata_mut() = headers;
+                        Ok(response)
                     }
                     Err(status) => {
                         error!("Backend bidirectional streaming call failed: {}", status);
-                        Err(status)
+                        Err(self.propagation.trailers.filter_status(status))
                     }
                 }
             } else {
                 error!("Backend configured but client not available");
-                Err(Status::internal("Backend connection not available"))
+                Err(Status::unavailable("Backend connection not available"))
             }
         } else {
             // Process locally
+            let mut request_stream = request.into_inner();
             let output = async_stream::try_stream! {
                 while let Some(result) = request_stream.next().await {
                     match result {
@@ -751,6 +924,204 @@ impl LandingService for ProtoServer {
 mod tests {
     use super::*;
 
+    use std::net::SocketAddr;
+
+    use hello_grpc_rust::common::conn::ClientBuilder;
+    use hello_grpc_rust::common::landing::landing_service_client::LandingServiceClient;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn request(data: &str) -> TalkRequest {
+        TalkRequest {
+            data: data.to_string(),
+            meta: "RUST".to_string(),
+        }
+    }
+
+    /// Reports how a backend call ended when dropped: `completed` after a
+    /// clean half-close from the proxy, `cancelled` otherwise.
+    struct Outcome {
+        events: UnboundedSender<&'static str>,
+        completed: bool,
+    }
+
+    impl Drop for Outcome {
+        fn drop(&mut self) {
+            let event = if self.completed {
+                "completed"
+            } else {
+                "cancelled"
+            };
+            let _ = self.events.send(event);
+        }
+    }
+
+    /// A backend that answers every streamed request at once and reports
+    /// `received` for each, then how the call ended.
+    struct StubBackend {
+        events: UnboundedSender<&'static str>,
+    }
+
+    #[tonic::async_trait]
+    impl LandingService for StubBackend {
+        async fn talk(&self, _: Request<TalkRequest>) -> Result<Response<TalkResponse>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+
+        type TalkOneAnswerMoreStream =
+            Pin<Box<dyn Stream<Item = Result<TalkResponse, Status>> + Send + 'static>>;
+
+        async fn talk_one_answer_more(
+            &self,
+            _: Request<TalkRequest>,
+        ) -> Result<Response<Self::TalkOneAnswerMoreStream>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+
+        async fn talk_more_answer_one(
+            &self,
+            request: Request<Streaming<TalkRequest>>,
+        ) -> Result<Response<TalkResponse>, Status> {
+            let mut outcome = Outcome {
+                events: self.events.clone(),
+                completed: false,
+            };
+            let mut inbound = request.into_inner();
+            while let Some(result) = inbound.next().await {
+                result?;
+                let _ = self.events.send("received");
+            }
+            outcome.completed = true;
+            Ok(Response::new(TalkResponse::default()))
+        }
+
+        type TalkBidirectionalStream =
+            Pin<Box<dyn Stream<Item = Result<TalkResponse, Status>> + Send + 'static>>;
+
+        async fn talk_bidirectional(
+            &self,
+            request: Request<Streaming<TalkRequest>>,
+        ) -> Result<Response<Self::TalkBidirectionalStream>, Status> {
+            let events = self.events.clone();
+            let mut inbound = request.into_inner();
+            let output = async_stream::stream! {
+                let mut outcome = Outcome { events: events.clone(), completed: false };
+                while let Some(Ok(request)) = inbound.next().await {
+                    let _ = events.send("received");
+                    yield create_response(request.data).map(|result| TalkResponse { status: 200, results: vec![result] });
+                }
+                outcome.completed = true;
+            };
+            Ok(Response::new(Box::pin(output)))
+        }
+    }
+
+    async fn next_event(events: &mut UnboundedReceiver<&'static str>) -> Option<&'static str> {
+        tokio::time::timeout(WAIT, events.recv())
+            .await
+            .expect("no backend event")
+    }
+
+    async fn serve<S: LandingService>(service: LandingServiceServer<S>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(service.accept_compressed(CompressionEncoding::Gzip))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        address
+    }
+
+    /// A proxy to `backend` whose pool is connected.
+    async fn proxy_to(backend: SocketAddr) -> ProtoServer {
+        let builder = ClientBuilder::new().backend("127.0.0.1", backend.port().to_string());
+        let pool = ConnectionPool::connect_with(1, PoolPolicy::RoundRobin, builder).await;
+        let started = Instant::now();
+        while pool.healthy() == 0 {
+            assert!(started.elapsed() < WAIT, "backend never connected");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        ProtoServer {
+            backend: backend.to_string(),
+            client_pool: Some(Arc::new(pool)),
+            deadline_margin: Duration::from_millis(100),
+            propagation: Arc::new(PropagationPolicy::from_env()),
+            forward_token: false,
+        }
+    }
+
+    #[test]
+    fn talk_bidirectional_relays_before_half_close_and_cancels_with_the_caller() {
+        let servers = tokio::runtime::Runtime::new().unwrap();
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let proxy = servers.block_on(async {
+            let backend = serve(LandingServiceServer::new(StubBackend { events: events_tx })).await;
+            serve(LandingServiceServer::new(proxy_to(backend).await)).await
+        });
+
+        // The caller runs on its own runtime so that shutting it down drops
+        // its connection the way a crashed client would
+        let caller = tokio::runtime::Runtime::new().unwrap();
+        let (requests, rx) = mpsc::channel(4);
+        let responses = caller.block_on(async {
+            let mut client = LandingServiceClient::connect(format!("http://{}", proxy))
+                .await
+                .unwrap();
+            requests.send(request("0")).await.unwrap();
+            let mut responses = client
+                .talk_bidirectional(ReceiverStream::new(rx))
+                .await
+                .unwrap()
+                .into_inner();
+            // The request stream is still open
+            let first = tokio::time::timeout(WAIT, responses.message())
+                .await
+                .expect("no response before half-close")
+                .unwrap()
+                .unwrap();
+            assert_eq!(first.results[0].kv["idx"], "0");
+            responses
+        });
+        servers.block_on(async {
+            assert_eq!(next_event(&mut events).await, Some("received"));
+        });
+
+        drop(responses);
+        caller.shutdown_background();
+        servers.block_on(async {
+            assert_eq!(next_event(&mut events).await, Some("cancelled"));
+        });
+        drop(requests);
+    }
+
+    #[tokio::test]
+    async fn talk_more_answer_one_cancels_the_backend_on_an_inbound_error() {
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let backend = serve(LandingServiceServer::new(StubBackend { events: events_tx })).await;
+        // Oversized messages fail the proxy's inbound stream
+        let proxy =
+            serve(LandingServiceServer::new(proxy_to(backend).await).max_decoding_message_size(64))
+                .await;
+
+        let mut client = LandingServiceClient::connect(format!("http://{}", proxy))
+            .await
+            .unwrap();
+        let (requests, rx) = mpsc::channel(4);
+        requests.send(request("0")).await.unwrap();
+        let call =
+            tokio::spawn(async move { client.talk_more_answer_one(ReceiverStream::new(rx)).await });
+        assert_eq!(next_event(&mut events).await, Some("received"));
+
+        requests.send(request(&"9".repeat(1024))).await.unwrap();
+        let result = tokio::time::timeout(WAIT, call).await.unwrap().unwrap();
+        assert!(result.is_err(), "the broken upload succeeded");
+        assert_eq!(next_event(&mut events).await, Some("cancelled"));
+    }
+
     #[test]
     fn create_response_rejects_invalid_data() {
         for invalid in ["", "not-a-number", "-1", "99"] {
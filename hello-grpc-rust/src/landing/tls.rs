@@ -0,0 +1,152 @@
+//! TLS termination for the server with certificate reload.
+//!
+//! tonic's own TLS config is fixed when the server starts, so TLS is
+//! terminated here instead: [`incoming`] accepts TCP connections and runs
+//! the handshake with the current [`ServerConfig`], which is rebuilt when
+//! `full_chain.pem`, `private.key` or the client CA bundle change (see
+//! `common/tls.rs`). The streams keep tonic's `TlsConnectInfo`, so the peer
+//! address and client certificate still reach the services.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use rustls::ServerConfig;
+use rustls::server::WebPkiClientVerifier;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::common::cert_expiry::MONITOR;
+use crate::common::tls::{
+    Reloadable, crypto_provider, load_certs, load_key, load_roots, not_after, spki_pin,
+};
+use crate::common::trans::{server_cert_chain, server_cert_key};
+use crate::landing::mtls::{ClientAuth, client_ca_file};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshaken connections waiting for the server to take them.
+const BACKLOG: usize = 128;
+
+/// The files the server config is built from.
+pub fn server_tls_files(client_auth: ClientAuth) -> Vec<PathBuf> {
+    let mut files = vec![server_cert_chain(), server_cert_key()];
+    if client_auth != ClientAuth::Off {
+        files.push(client_ca_file());
+    }
+    files
+}
+
+/// Builds the server config from the certificate files: the server identity
+/// and, with mutual TLS, the client CA bundle. Returns it with the server
+/// certificate's expiry.
+pub fn server_config(client_auth: ClientAuth) -> Result<(ServerConfig, DateTime<Utc>), String> {
+    let chain_path = server_cert_chain();
+    let chain = load_certs(&chain_path).map_err(|e| e.to_string())?;
+    let expiry = not_after(&chain[0]).map_err(|e| format!("{:?}: {}", chain_path, e))?;
+    let key = load_key(&server_cert_key()).map_err(|e| e.to_string())?;
+
+    let builder = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = if client_auth == ClientAuth::Off {
+        builder.with_no_client_auth()
+    } else {
+        let ca = client_ca_file();
+        let roots = load_roots(&ca).map_err(|e| e.to_string())?;
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), crypto_provider());
+        let verifier = if client_auth == ClientAuth::Optional {
+            verifier.allow_unauthenticated()
+        } else {
+            verifier
+        };
+        builder.with_client_cert_verifier(verifier.build().map_err(|e| format!("{:?}: {}", ca, e))?)
+    };
+    let mut config = builder
+        .with_single_cert(chain, key)
+        .map_err(|e| format!("{:?}: {}", chain_path, e))?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok((config, expiry))
+}
+
+/// Loads the server config and reloads it whenever its files change.
+pub fn reloading_server_config(
+    client_auth: ClientAuth,
+) -> Result<Arc<Reloadable<ServerConfig>>, String> {
+    let (config, expiry) = server_config(client_auth)?;
+    info!(
+        "Loaded the server certificate {:?}, valid until {}",
+        server_cert_chain(),
+        expiry
+    );
+    // The value clients pin with GRPC_HELLO_TLS_PIN_SHA256
+    if let Ok(chain) = load_certs(&server_cert_chain())
+        && let Ok(pin) = spki_pin(&chain[0])
+    {
+        info!("Server certificate key pin: {}", pin);
+    }
+    MONITOR.record("server", expiry);
+    let config = Reloadable::new(config);
+    config.watch("server", server_tls_files(client_auth), move || {
+        server_config(client_auth).map(|(config, expiry)| (config, Some(expiry)))
+    });
+    Ok(config)
+}
+
+/// Accepts connections on `listener` and yields them once their TLS
+/// handshake is done. Handshakes run concurrently, each with the config
+/// current when its connection was accepted; failed ones are dropped.
+pub fn incoming(
+    listener: TcpListener,
+    config: Arc<Reloadable<ServerConfig>>,
+) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = mpsc::channel(BACKLOG);
+    tokio::spawn(async move {
+        loop {
+            let (tcp, peer) = tokio::select! {
+                _ = tx.closed() => return,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept a connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+            };
+            let acceptor = tokio_rustls::TlsAcceptor::from(config.get());
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                if let Some(stream) = handshake(acceptor, tcp, peer).await {
+                    let _ = tx.send(Ok(stream)).await;
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+async fn handshake(
+    acceptor: tokio_rustls::TlsAcceptor,
+    tcp: TcpStream,
+    peer: SocketAddr,
+) -> Option<TlsStream<TcpStream>> {
+    let _ = tcp.set_nodelay(true);
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            debug!("TLS handshake with {} failed: {}", peer, e);
+            None
+        }
+        Err(_) => {
+            debug!("TLS handshake with {} timed out", peer);
+            None
+        }
+    }
+}
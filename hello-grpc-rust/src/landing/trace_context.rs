@@ -0,0 +1,78 @@
+//! Server-side trace context.
+//!
+//! [`TraceContextLayer`] opens the server span for every call, parented on
+//! the `traceparent` / B3 context the caller sent, and keeps it open until
+//! the response body finishes so streaming calls are timed end to end.
+//! Handlers run inside the span, which is how a proxied backend call made by
+//! [`ClientTraceLayer`](crate::common::propagation::ClientTraceLayer) ends up
+//! as its child.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tonic::Code;
+use tower::{Layer, Service};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::common::framing::{Finish, ObservedBody, split_grpc_path};
+use crate::common::propagation::extract_context;
+use crate::otel;
+
+#[derive(Clone, Default)]
+pub struct TraceContextLayer;
+
+impl<S> Layer<S> for TraceContextLayer {
+    type Service = TraceContextService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceContextService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct TraceContextService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for TraceContextService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = http::Response<ObservedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let (service, method) = split_grpc_path(request.uri().path());
+        let span = otel::span_with_rpc_attrs(&method, &service);
+        span.set_parent(extract_context(request.headers()));
+
+        // The span outlives the handler future: it closes with the body
+        let finished = span.clone();
+        let finish = Finish::new(Box::new(move |code: Code, _responses: u64| {
+            otel::record_span_status(&finished, code);
+        }));
+
+        let future = span.in_scope(|| inner.call(request));
+        Box::pin(
+            async move {
+                let response = future.await?;
+                let (parts, body) = response.into_parts();
+                let body = ObservedBody::new(body, &parts.headers, finish);
+                Ok(http::Response::from_parts(parts, body))
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,122 @@
+//! Extra trailers for successful responses.
+//!
+//! tonic lets a handler attach metadata to an error `Status`, but a
+//! successful response only gets the `grpc-status` trailer tonic writes
+//! itself. [`ForwardedTrailersLayer`] puts a [`ForwardedTrailers`] slot into
+//! every request's extensions. Whatever the handler stores there is appended
+//! to the trailers when the response body finishes. The proxy uses it to
+//! hand the backend's trailers on to the caller.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tonic::metadata::MetadataMap;
+use tower::{Layer, Service};
+
+/// Per-call slot for trailers to send after the last message.
+#[derive(Clone, Default)]
+pub struct ForwardedTrailers(Arc<Mutex<Option<MetadataMap>>>);
+
+impl ForwardedTrailers {
+    /// Replaces the trailers to append. Empty maps are ignored.
+    pub fn set(&self, trailers: MetadataMap) {
+        if !trailers.is_empty() {
+            *self.0.lock().unwrap() = Some(trailers);
+        }
+    }
+
+    fn take(&self) -> Option<http::HeaderMap> {
+        self.0.lock().unwrap().take().map(MetadataMap::into_headers)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ForwardedTrailersLayer;
+
+impl<S> Layer<S> for ForwardedTrailersLayer {
+    type Service = ForwardedTrailersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ForwardedTrailersService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ForwardedTrailersService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for ForwardedTrailersService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<TrailersBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let slot = ForwardedTrailers::default();
+        request.extensions_mut().insert(slot.clone());
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            Ok(response.map(|inner| TrailersBody { inner, slot }))
+        })
+    }
+}
+
+pin_project! {
+    /// Response body that appends the handler's [`ForwardedTrailers`] to
+    /// the trailers frame.
+    pub struct TrailersBody<B> {
+        #[pin]
+        inner: B,
+        slot: ForwardedTrailers,
+    }
+}
+
+impl<B: Body<Data = Bytes>> Body for TrailersBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        match this.inner.poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) if frame.is_trailers() => {
+                let mut trailers = frame.into_trailers().unwrap_or_default();
+                if let Some(extra) = this.slot.take() {
+                    // Never let forwarded metadata shadow the call's own status
+                    let own: Vec<http::HeaderName> = trailers.keys().cloned().collect();
+                    for (key, value) in extra.iter() {
+                        if !own.contains(key) {
+                            trailers.append(key.clone(), value.clone());
+                        }
+                    }
+                }
+                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+            }
+            polled => polled,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -4,17 +4,200 @@
 //! opt-in via `GRPC_HELLO_OTEL=Y`. When unset, `init_otel` is a no-op
 //! and tonic's existing `tracing` instrumentation is untouched.
 //!
-//! The exporter is stdout (`opentelemetry-stdout`) so spans show up
-//! without a sidecar Jaeger / OTLP collector. Operators wanting an
-//! OTLP backend swap in `opentelemetry-otlp` here without changing
-//! the call sites in server.rs / client.rs.
+//! Exporters are picked per signal from the environment:
+//!
+//!   GRPC_HELLO_OTEL_EXPORTER          stdout | otlp-grpc | otlp-http | none (default stdout)
+//!   GRPC_HELLO_OTEL_TRACES_EXPORTER   overrides the above for spans
+//!   GRPC_HELLO_OTEL_METRICS_EXPORTER  overrides the above for metrics
+//!
+//! The default stdout exporter shows spans and metrics without a sidecar
+//! Jaeger / OTLP collector. The OTLP exporters honour the standard
+//! `OTEL_EXPORTER_OTLP_ENDPOINT`, `OTEL_EXPORTER_OTLP_HEADERS`,
+//! `OTEL_EXPORTER_OTLP_TIMEOUT` variables and their `_TRACES_` / `_METRICS_`
+//! variants. Spans go through a batch processor so exporting never blocks
+//! the handler path.
+//!
+//! Metrics (parity matrix B2): `rpc_calls_total`, `rpc.server.duration`,
+//! `rpc.client.duration` and the `rpc.{server,client}.{requests,responses}_per_rpc`
+//! message counts. They are recorded by the server metrics layer and the
+//! client metrics layer through [`record_server_call`] / [`record_client_call`],
+//! and exported every `OTEL_METRIC_EXPORT_INTERVAL` ms (SDK default 60s)
+//! plus once more from [`shutdown_otel`].
+//!
+//! Trace context crosses process boundaries through the global propagator:
+//! incoming W3C `traceparent` / `tracestate` or B3 (`b3`, `x-b3-*`) headers
+//! become the parent of the server span, and every outgoing call carries a
+//! fresh `traceparent` for its client span.
 
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Histogram, MeterProvider as _};
 use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig as _};
 use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::runtime;
 use opentelemetry_sdk::trace::{Sampler, TracerProvider};
 use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
+use tonic::Code;
 use tracing_subscriber::{EnvFilter, prelude::*};
 
+/// Where a telemetry signal is exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExporterKind {
+    Stdout,
+    OtlpGrpc,
+    OtlpHttp,
+    None,
+}
+
+impl ExporterKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stdout" | "console" => Some(ExporterKind::Stdout),
+            "otlp-grpc" | "otlp" => Some(ExporterKind::OtlpGrpc),
+            "otlp-http" => Some(ExporterKind::OtlpHttp),
+            "none" => Some(ExporterKind::None),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves the exporter for one signal: the signal-specific variable wins
+/// over `GRPC_HELLO_OTEL_EXPORTER`, and anything unset or unknown falls back
+/// to stdout.
+pub fn exporter_kind(signal_var: &str) -> ExporterKind {
+    let configured = std::env::var(signal_var)
+        .or_else(|_| std::env::var("GRPC_HELLO_OTEL_EXPORTER"))
+        .ok();
+    match configured {
+        Some(value) => ExporterKind::parse(&value).unwrap_or_else(|| {
+            // log4rs is not initialised yet when this runs
+            eprintln!("Unknown OTel exporter {:?}, using stdout", value);
+            ExporterKind::Stdout
+        }),
+        None => ExporterKind::Stdout,
+    }
+}
+
+fn build_tracer_provider(kind: ExporterKind, resource: Resource) -> TracerProvider {
+    let builder = TracerProvider::builder()
+        .with_resource(resource)
+        .with_sampler(Sampler::AlwaysOn);
+    let exporter = match kind {
+        ExporterKind::Stdout => {
+            return builder
+                .with_batch_exporter(
+                    opentelemetry_stdout::SpanExporter::default(),
+                    runtime::Tokio,
+                )
+                .build();
+        }
+        ExporterKind::OtlpGrpc => SpanExporter::builder().with_tonic().build(),
+        ExporterKind::OtlpHttp => SpanExporter::builder()
+            .with_http()
+            .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
+            .build(),
+        // Spans are still created so trace context keeps propagating
+        ExporterKind::None => return builder.build(),
+    };
+    match exporter {
+        Ok(exporter) => builder
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .build(),
+        Err(e) => {
+            eprintln!(
+                "Failed to build OTLP span exporter, spans are dropped: {}",
+                e
+            );
+            builder.build()
+        }
+    }
+}
+
+fn build_meter_provider(kind: ExporterKind, resource: Resource) -> SdkMeterProvider {
+    let builder = SdkMeterProvider::builder().with_resource(resource);
+    let exporter = match kind {
+        ExporterKind::Stdout => {
+            let reader = PeriodicReader::builder(
+                opentelemetry_stdout::MetricExporter::default(),
+                runtime::Tokio,
+            )
+            .build();
+            return builder.with_reader(reader).build();
+        }
+        ExporterKind::OtlpGrpc => MetricExporter::builder().with_tonic().build(),
+        ExporterKind::OtlpHttp => MetricExporter::builder()
+            .with_http()
+            .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
+            .build(),
+        ExporterKind::None => return builder.build(),
+    };
+    match exporter {
+        Ok(exporter) => builder
+            .with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build())
+            .build(),
+        Err(e) => {
+            eprintln!(
+                "Failed to build OTLP metric exporter, metrics are dropped: {}",
+                e
+            );
+            builder.build()
+        }
+    }
+}
+
+static TRACER_PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+static METER_PROVIDER: OnceLock<SdkMeterProvider> = OnceLock::new();
+static INSTRUMENTS: OnceLock<RpcInstruments> = OnceLock::new();
+
+/// Instruments for one side (server or client) of a call.
+struct SideInstruments {
+    duration: Histogram<f64>,
+    requests_per_rpc: Histogram<u64>,
+    responses_per_rpc: Histogram<u64>,
+}
+
+struct RpcInstruments {
+    calls_total: Counter<u64>,
+    server: SideInstruments,
+    client: SideInstruments,
+}
+
+impl RpcInstruments {
+    fn new(provider: &SdkMeterProvider) -> Self {
+        let meter = provider.meter("hello-grpc-rust");
+        let side = |side: &'static str| SideInstruments {
+            duration: meter
+                .f64_histogram(format!("rpc.{}.duration", side))
+                .with_description("Duration of inbound/outbound RPCs")
+                .with_unit("ms")
+                .build(),
+            requests_per_rpc: meter
+                .u64_histogram(format!("rpc.{}.requests_per_rpc", side))
+                .with_description("Number of request messages per RPC")
+                .with_unit("{count}")
+                .build(),
+            responses_per_rpc: meter
+                .u64_histogram(format!("rpc.{}.responses_per_rpc", side))
+                .with_description("Number of response messages per RPC")
+                .with_unit("{count}")
+                .build(),
+        };
+        RpcInstruments {
+            calls_total: meter
+                .u64_counter("rpc_calls_total")
+                .with_description("Total number of gRPC calls handled")
+                .with_unit("{call}")
+                .build(),
+            server: side("server"),
+            client: side("client"),
+        }
+    }
+}
+
 /// Returns true iff `GRPC_HELLO_OTEL=Y`.
 pub fn otel_enabled() -> bool {
     std::env::var("GRPC_HELLO_OTEL")
@@ -36,12 +219,114 @@ pub fn otel_enabled() -> bool {
 pub fn span_with_rpc_attrs(method: &str, service: &str) -> tracing::Span {
     tracing::info_span!(
         "grpc_handler",
+        otel.name = %format!("{}/{}", service, method),
+        otel.kind = "server",
+        otel.status_code = tracing::field::Empty,
         rpc.system = "grpc",
         rpc.method = %method,
         rpc.service = %service,
+        rpc.grpc.status_code = tracing::field::Empty,
     )
 }
 
+/// Client-side counterpart of [`span_with_rpc_attrs`], opened around every
+/// outgoing call. It is a child of whatever span is current, so a proxied
+/// call nests under the server span of the request that triggered it.
+pub fn client_span_with_rpc_attrs(method: &str, service: &str) -> tracing::Span {
+    tracing::info_span!(
+        "grpc_call",
+        otel.name = %format!("{}/{}", service, method),
+        otel.kind = "client",
+        otel.status_code = tracing::field::Empty,
+        rpc.system = "grpc",
+        rpc.method = %method,
+        rpc.service = %service,
+        rpc.grpc.status_code = tracing::field::Empty,
+    )
+}
+
+/// Records the final status on a span from [`span_with_rpc_attrs`] or
+/// [`client_span_with_rpc_attrs`].
+pub fn record_span_status(span: &tracing::Span, code: Code) {
+    span.record("rpc.grpc.status_code", code as i64);
+    if code != Code::Ok {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+/// Records a finished server-side call. No-op unless `init_otel` ran with
+/// `GRPC_HELLO_OTEL=Y`.
+pub fn record_server_call(
+    service: &str,
+    method: &str,
+    code: Code,
+    elapsed: Duration,
+    requests: u64,
+    responses: u64,
+) {
+    if let Some(instruments) = INSTRUMENTS.get() {
+        let attrs = rpc_attributes(service, method, code, "server");
+        record_side(&instruments.server, &attrs, elapsed, requests, responses);
+        instruments.calls_total.add(1, &attrs);
+    }
+}
+
+/// Records a finished client-side call. No-op unless `init_otel` ran with
+/// `GRPC_HELLO_OTEL=Y`.
+pub fn record_client_call(
+    service: &str,
+    method: &str,
+    code: Code,
+    elapsed: Duration,
+    requests: u64,
+    responses: u64,
+) {
+    if let Some(instruments) = INSTRUMENTS.get() {
+        let attrs = rpc_attributes(service, method, code, "client");
+        record_side(&instruments.client, &attrs, elapsed, requests, responses);
+        instruments.calls_total.add(1, &attrs);
+    }
+}
+
+fn rpc_attributes(service: &str, method: &str, code: Code, side: &'static str) -> [KeyValue; 5] {
+    [
+        KeyValue::new("rpc.system", "grpc"),
+        KeyValue::new("rpc.service", service.to_string()),
+        KeyValue::new("rpc.method", method.to_string()),
+        KeyValue::new("rpc.grpc.status_code", code as i64),
+        KeyValue::new("side", side),
+    ]
+}
+
+fn record_side(
+    instruments: &SideInstruments,
+    attrs: &[KeyValue],
+    elapsed: Duration,
+    requests: u64,
+    responses: u64,
+) {
+    instruments
+        .duration
+        .record(elapsed.as_secs_f64() * 1000.0, attrs);
+    instruments.requests_per_rpc.record(requests, attrs);
+    instruments.responses_per_rpc.record(responses, attrs);
+}
+
+/// Flushes and shuts down the tracer and meter providers so short-lived
+/// processes (e.g. `proto-client`) still export their final batch.
+pub fn shutdown_otel() {
+    if let Some(provider) = METER_PROVIDER.get()
+        && let Err(e) = provider.shutdown()
+    {
+        log::warn!("OTel meter provider shutdown failed: {}", e);
+    }
+    if let Some(provider) = TRACER_PROVIDER.get()
+        && let Err(e) = provider.shutdown()
+    {
+        log::warn!("OTel tracer provider shutdown failed: {}", e);
+    }
+}
+
 /// One-time OTel SDK + tracing-subscriber wiring. Idempotent: a second
 /// call (e.g. when invoked from both server and client mains) returns
 /// without making further changes.
@@ -51,22 +336,32 @@ pub fn init_otel(service_name: &'static str) {
     }
     static INIT: std::sync::Once = std::sync::Once::new();
     INIT.call_once(|| {
-        let exporter = opentelemetry_stdout::SpanExporter::default();
-        let provider = TracerProvider::builder()
-            .with_resource(Resource::new([opentelemetry::KeyValue::new(
-                SERVICE_NAME,
-                service_name,
-            )]))
-            .with_sampler(Sampler::AlwaysOn)
-            .with_simple_exporter(exporter)
-            .build();
+        let resource = Resource::new([KeyValue::new(SERVICE_NAME, service_name)]);
+        let provider = build_tracer_provider(
+            exporter_kind("GRPC_HELLO_OTEL_TRACES_EXPORTER"),
+            resource.clone(),
+        );
         let tracer = provider.tracer("hello-grpc-rust");
+        let _ = TRACER_PROVIDER.set(provider);
+
+        let meter_provider =
+            build_meter_provider(exporter_kind("GRPC_HELLO_OTEL_METRICS_EXPORTER"), resource);
+        opentelemetry::global::set_meter_provider(meter_provider.clone());
+        let _ = INSTRUMENTS.set(RpcInstruments::new(&meter_provider));
+        let _ = METER_PROVIDER.set(meter_provider);
+
+        // W3C `traceparent` wins over B3 when a caller sends both
+        opentelemetry::global::set_text_map_propagator(crate::common::propagation::propagator());
+
         // Bridge tonic's tracing -> OTel spans. Without this layer,
         // tonic emits tracing events but they go nowhere.
+        // Installed with `set_global_default` rather than `.init()`: the
+        // latter also claims the `log` facade, which makes the subsequent
+        // `log4rs::init_file` in server.rs / client.rs fail.
         let layer = tracing_opentelemetry::layer().with_tracer(tracer);
-        tracing_subscriber::registry()
+        let subscriber = tracing_subscriber::registry()
             .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
-            .with(layer)
-            .init();
+            .with(layer);
+        let _ = tracing::subscriber::set_global_default(subscriber);
     });
 }
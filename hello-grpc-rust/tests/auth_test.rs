@@ -0,0 +1,145 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hello_grpc_rust::common::credentials::TokenSource;
+use hello_grpc_rust::landing::auth::{Claims, JwtVerifier, authenticate};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde_json::json;
+use tonic::{Code, Request};
+
+const SECRET: &[u8] = b"hello-grpc-secret";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn token(header: Header, claims: serde_json::Value) -> String {
+    encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+}
+
+fn verifier() -> JwtVerifier {
+    JwtVerifier::from_key(Algorithm::HS256, b"hello-grpc-secret\n")
+        .unwrap()
+        .with_issuer(vec!["https://issuer.hello.grpc.io".to_string()])
+        .with_audience(vec!["landing".to_string()])
+}
+
+#[test]
+fn test_jwt_verifies_issuer_audience_and_expiry() {
+    let verifier = verifier();
+    let claims = |iss: &str, aud: &str, exp: u64| json!({"sub": "alice", "iss": iss, "aud": aud, "exp": exp, "role": "admin"});
+    let iss = "https://issuer.hello.grpc.io";
+
+    let ok = verifier
+        .verify(&token(
+            Header::default(),
+            claims(iss, "landing", now() + 60),
+        ))
+        .unwrap();
+    assert_eq!(ok.subject(), Some("alice"));
+    assert_eq!(ok.get("role"), Some(&json!("admin")));
+
+    let bad = [
+        token(
+            Header::default(),
+            claims("https://evil", "landing", now() + 60),
+        ),
+        token(Header::default(), claims(iss, "other", now() + 60)),
+        token(Header::default(), claims(iss, "landing", now() - 3600)),
+        token(
+            Header::default(),
+            json!({"sub": "alice", "iss": iss, "aud": "landing"}),
+        ),
+        token(
+            Header::new(Algorithm::HS384),
+            claims(iss, "landing", now() + 60),
+        ),
+        "not-a-jwt".to_string(),
+    ];
+    for token in bad {
+        assert!(verifier.verify(&token).is_err(), "accepted {}", token);
+    }
+}
+
+#[test]
+fn test_jwks_selects_key_by_kid() {
+    let jwks = json!({"keys": [
+        {"kty": "oct", "kid": "old", "k": "b3RoZXItc2VjcmV0"},
+        {"kty": "oct", "kid": "current", "alg": "HS256", "k": "aGVsbG8tZ3JwYy1zZWNyZXQ"}
+    ]});
+    let verifier = JwtVerifier::from_jwks(&jwks.to_string()).unwrap();
+    let claims = json!({"sub": "bob", "exp": now() + 60});
+
+    let header = Header {
+        kid: Some("current".to_string()),
+        ..Header::default()
+    };
+    assert!(verifier.verify(&token(header, claims.clone())).is_ok());
+
+    let header = Header {
+        kid: Some("old".to_string()),
+        ..Header::default()
+    };
+    assert!(verifier.verify(&token(header, claims.clone())).is_err());
+    // Two keys and no kid: nothing to pick from
+    assert!(verifier.verify(&token(Header::default(), claims)).is_err());
+}
+
+#[test]
+fn test_authenticate_attaches_claims() {
+    let verifier = verifier();
+    let valid = token(
+        Header::default(),
+        json!({"sub": "alice", "iss": "https://issuer.hello.grpc.io", "aud": "landing", "exp": now() + 60}),
+    );
+
+    let mut request = Request::new(());
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", valid).parse().unwrap(),
+    );
+    let request = authenticate(Some(&verifier), request).unwrap();
+    let claims = request.extensions().get::<Claims>().unwrap();
+    assert_eq!(claims.subject(), Some("alice"));
+
+    let missing = authenticate(Some(&verifier), Request::new(())).unwrap_err();
+    assert_eq!(missing.code(), Code::Unauthenticated);
+
+    let mut request = Request::new(());
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer nope".parse().unwrap());
+    let invalid = authenticate(Some(&verifier), request).unwrap_err();
+    assert_eq!(invalid.code(), Code::Unauthenticated);
+
+    // Without a verifier every call passes
+    assert!(authenticate(None, Request::new(())).is_ok());
+}
+
+#[tokio::test]
+async fn test_token_file_is_cached_until_reloaded() {
+    let path = std::env::temp_dir().join(format!("token-{}", std::process::id()));
+    std::fs::write(&path, "first\n").unwrap();
+    let source = TokenSource::file(path.clone());
+    assert_eq!(source.header().unwrap(), "Bearer first");
+
+    std::fs::write(&path, "second").unwrap();
+    assert_eq!(source.header().unwrap(), "Bearer first");
+    source.reload();
+    assert_eq!(source.header().unwrap(), "Bearer second");
+
+    // A file caught mid-rewrite, or gone, keeps the last good token
+    std::fs::write(&path, "").unwrap();
+    source.reload();
+    assert_eq!(source.header().unwrap(), "Bearer second");
+    std::fs::remove_file(&path).unwrap();
+    source.reload();
+    assert_eq!(source.header().unwrap(), "Bearer second");
+
+    std::fs::write(&path, "third").unwrap();
+    source.reload();
+    assert_eq!(source.header().unwrap(), "Bearer third");
+    std::fs::remove_file(&path).unwrap();
+}
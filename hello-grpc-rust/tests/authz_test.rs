@@ -0,0 +1,85 @@
+use hello_grpc_rust::landing::authz::{AuthzPolicy, Decision};
+
+const TALK: &str = "/hello.LandingService/Talk";
+const BIDI: &str = "/hello.LandingService/TalkBidirectional";
+
+const POLICY: &str = r#"
+name: landing
+deny_rules:
+  - name: no-bidi-for-guests
+    source: {principals: ["guest-*"]}
+    request: {paths: ["/hello.LandingService/TalkBidirectional"]}
+allow_rules:
+  - name: admins
+    source: {principals: ["admin"]}
+  - name: rust-clients-talk
+    request:
+      paths: ["/hello.LandingService/Talk*"]
+      headers: [{key: Client, values: ["rust-*"]}]
+"#;
+
+fn headers(client: Option<&str>) -> http::HeaderMap {
+    let mut headers = http::HeaderMap::new();
+    if let Some(client) = client {
+        headers.insert("client", client.parse().unwrap());
+    }
+    headers
+}
+
+fn decide(
+    policy: &AuthzPolicy,
+    path: &str,
+    principal: Option<&str>,
+    client: Option<&str>,
+) -> Decision {
+    let principals: Vec<String> = principal.map(String::from).into_iter().collect();
+    policy.evaluate(path, &principals, &headers(client))
+}
+
+#[test]
+fn test_authz_policy_deny_then_allow() {
+    let policy = AuthzPolicy::parse(POLICY).unwrap();
+    assert_eq!(policy.name(), "landing");
+
+    let admin = decide(&policy, BIDI, Some("admin"), None);
+    assert_eq!(
+        (admin.allowed, admin.rule.as_deref()),
+        (true, Some("admins"))
+    );
+
+    let guest = decide(&policy, BIDI, Some("guest-1"), Some("rust-client"));
+    assert_eq!(
+        (guest.allowed, guest.rule.as_deref()),
+        (false, Some("no-bidi-for-guests"))
+    );
+
+    // Header rules match unauthenticated callers too
+    let rust = decide(&policy, TALK, None, Some("rust-client"));
+    assert_eq!(rust.rule.as_deref(), Some("rust-clients-talk"));
+    assert!(rust.allowed);
+
+    let other = decide(&policy, TALK, Some("bob"), Some("go-client"));
+    assert_eq!((other.allowed, other.rule), (false, None));
+}
+
+#[test]
+fn test_authz_policy_json_and_validation() {
+    let policy = AuthzPolicy::parse(
+        r#"{"name": "any", "allow_rules": [{"name": "authenticated", "source": {"principals": ["*"]}}]}"#,
+    )
+    .unwrap();
+    assert!(decide(&policy, TALK, Some("alice"), None).allowed);
+    assert!(!decide(&policy, TALK, None, None).allowed);
+
+    let empty = AuthzPolicy::parse("name: closed").unwrap();
+    assert!(!decide(&empty, TALK, Some("admin"), None).allowed);
+
+    for invalid in [
+        r#"{"allow_rules": [{"name": "x", "request": {"headers": [{"key": "grpc-timeout", "values": ["1S"]}]}}]}"#,
+        r#"{"allow_rules": [{"name": "x", "request": {"headers": [{"key": ":path", "values": ["/"]}]}}]}"#,
+        "allow_rules: [{source: {}}]",
+        "{not json",
+    ] {
+        assert!(AuthzPolicy::parse(invalid).is_err(), "accepted {}", invalid);
+    }
+}
@@ -0,0 +1,246 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use hello_grpc_rust::common::balancer::{BalancedChannel, LbPolicy, parse_targets};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Endpoint, Server};
+use tonic_health::pb::HealthCheckRequest;
+use tonic_health::pb::health_client::HealthClient;
+
+#[test]
+fn test_parse_targets() {
+    let targets = parse_targets("backend-a:9001, backend-b ,[::1]:9003,,", "9996");
+    assert_eq!(
+        targets,
+        vec![
+            ("backend-a".to_string(), "9001".to_string()),
+            ("backend-b".to_string(), "9996".to_string()),
+            ("[::1]".to_string(), "9003".to_string()),
+        ]
+    );
+    assert!(parse_targets("", "9996").is_empty());
+}
+
+#[test]
+fn test_lb_policy_parse() {
+    assert_eq!(LbPolicy::parse("pick_first"), Some(LbPolicy::PickFirst));
+    assert_eq!(LbPolicy::parse("ROUND_ROBIN"), Some(LbPolicy::RoundRobin));
+    assert_eq!(LbPolicy::parse("p2c"), Some(LbPolicy::PowerOfTwoChoices));
+    assert_eq!(LbPolicy::parse("ring_hash"), None);
+}
+
+/// A health-only backend counting the calls it serves.
+struct Backend {
+    address: SocketAddr,
+    calls: Arc<AtomicUsize>,
+    stop: oneshot::Sender<()>,
+    serving: JoinHandle<Result<(), tonic::transport::Error>>,
+}
+
+impl Backend {
+    async fn start() -> Self {
+        Self::serve(TcpListener::bind("127.0.0.1:0").await.unwrap())
+    }
+
+    fn serve(listener: TcpListener) -> Self {
+        let address = listener.local_addr().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let (_, health) = tonic_health::server::health_reporter();
+        let health = InterceptedService::new(health, move |request| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            Ok(request)
+        });
+        let (stop, stopped) = oneshot::channel();
+        let serving = tokio::spawn(
+            Server::builder()
+                .add_service(health)
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                    let _ = stopped.await;
+                }),
+        );
+        Backend {
+            address,
+            calls,
+            stop,
+            serving,
+        }
+    }
+
+    /// Shuts the backend down and waits for its connections to close.
+    async fn stop(self) {
+        let _ = self.stop.send(());
+        self.serving.await.unwrap().unwrap();
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    fn endpoint(&self) -> Endpoint {
+        endpoint(self.address)
+    }
+}
+
+fn endpoint(address: SocketAddr) -> Endpoint {
+    Endpoint::from_shared(format!("http://{}", address)).unwrap()
+}
+
+/// An address nothing listens on.
+async fn unused_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn connect(backends: &[&Backend], policy: LbPolicy) -> HealthClient<BalancedChannel> {
+    let endpoints = backends.iter().map(|backend| backend.endpoint()).collect();
+    let channel = BalancedChannel::connect(endpoints, policy, None)
+        .await
+        .unwrap();
+    HealthClient::new(channel)
+}
+
+async fn check(client: &mut HealthClient<BalancedChannel>) {
+    client.check(HealthCheckRequest::default()).await.unwrap();
+}
+
+/// The backend each of `n` calls lands on.
+async fn spread(
+    client: &mut HealthClient<BalancedChannel>,
+    backends: &[&Backend],
+    n: usize,
+) -> Vec<usize> {
+    let mut picked = Vec::with_capacity(n);
+    for _ in 0..n {
+        let before: Vec<_> = backends.iter().map(|backend| backend.calls()).collect();
+        check(client).await;
+        let served = backends
+            .iter()
+            .zip(before)
+            .position(|(backend, before)| backend.calls() > before)
+            .unwrap();
+        picked.push(served);
+    }
+    picked
+}
+
+#[tokio::test]
+async fn test_pick_first_and_round_robin_selection() {
+    let (a, b, c) = (
+        Backend::start().await,
+        Backend::start().await,
+        Backend::start().await,
+    );
+    let backends = [&a, &b, &c];
+
+    let mut client = connect(&backends, LbPolicy::PickFirst).await;
+    assert_eq!(spread(&mut client, &backends, 4).await, [0, 0, 0, 0]);
+
+    let mut client = connect(&backends, LbPolicy::RoundRobin).await;
+    assert_eq!(spread(&mut client, &backends, 6).await, [0, 1, 2, 0, 1, 2]);
+}
+
+#[tokio::test]
+async fn test_p2c_avoids_the_loaded_target() {
+    let (a, b) = (Backend::start().await, Backend::start().await);
+    let backends = [&a, &b];
+    let mut client = connect(&backends, LbPolicy::PowerOfTwoChoices).await;
+
+    // A watch stays in flight for as long as it is open
+    let watch = client
+        .watch(HealthCheckRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let loaded = if a.calls() == 1 { 0 } else { 1 };
+    assert_eq!(spread(&mut client, &backends, 6).await, [1 - loaded; 6]);
+
+    // Once it ends both are picked again
+    drop(watch);
+    let mut seen = [false; 2];
+    for _ in 0..100 {
+        for picked in spread(&mut client, &backends, 1).await {
+            seen[picked] = true;
+        }
+        if seen == [true, true] {
+            return;
+        }
+    }
+    panic!("p2c kept to one target: {:?}", seen);
+}
+
+#[tokio::test]
+async fn test_unreachable_target_is_ejected() {
+    let live = Backend::start().await;
+    let down = unused_address().await;
+    let endpoints = vec![endpoint(down), live.endpoint()];
+
+    let channel = BalancedChannel::connect(endpoints, LbPolicy::RoundRobin, None)
+        .await
+        .unwrap();
+    assert!(channel.is_ready());
+    let mut client = HealthClient::new(channel);
+    for _ in 0..4 {
+        check(&mut client).await;
+    }
+    assert_eq!(live.calls(), 4);
+
+    // A connected target that goes away is ejected by the call that fails
+    // on it, and the rest go to the live one
+    let gone = Backend::start().await;
+    let mut client = connect(&[&gone, &live], LbPolicy::RoundRobin).await;
+    check(&mut client).await;
+    check(&mut client).await;
+    gone.stop().await;
+    let status = client
+        .check(HealthCheckRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    for _ in 0..3 {
+        check(&mut client).await;
+    }
+    assert_eq!(live.calls(), 4 + 1 + 3);
+
+    // Without a reachable target the build fails
+    let result = BalancedChannel::connect(vec![endpoint(down)], LbPolicy::RoundRobin, None).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_ejected_target_is_reprobed_with_backoff() {
+    let address = unused_address().await;
+    let started = Instant::now();
+    // Probes at once, then after 1s, then 2s later
+    let channel = BalancedChannel::lazy(vec![endpoint(address)], LbPolicy::PickFirst, None);
+
+    let mut client = HealthClient::new(channel.clone());
+    let status = client
+        .check(HealthCheckRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+
+    // Comes up between the second and third probes
+    tokio::time::sleep_until((started + Duration::from_millis(1500)).into()).await;
+    let backend = Backend::serve(TcpListener::bind(address).await.unwrap());
+    tokio::time::sleep_until((started + Duration::from_millis(2500)).into()).await;
+    assert!(!channel.is_ready(), "probed again before the backoff");
+
+    while !channel.is_ready() {
+        assert!(started.elapsed() < Duration::from_secs(6), "never reprobed");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(started.elapsed() >= Duration::from_secs(3));
+    check(&mut client).await;
+    assert_eq!(backend.calls(), 1);
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use tonic::Code;
+
+use hello_grpc_rust::common::breaker::{BreakerConfig, BreakerState, CircuitBreaker, is_rejection};
+
+fn config() -> BreakerConfig {
+    BreakerConfig {
+        consecutive_failures: 3,
+        error_rate: 0.0,
+        min_calls: 20,
+        window: Duration::from_secs(10),
+        open_for: Duration::from_millis(50),
+        half_open_calls: 1,
+    }
+}
+
+fn call(breaker: &CircuitBreaker, code: Code) {
+    let permit = breaker.admit().expect("call should be admitted");
+    breaker.record(permit, code);
+}
+
+#[test]
+fn test_breaker_opens_on_consecutive_failures() {
+    let breaker = CircuitBreaker::new("consecutive", config());
+    call(&breaker, Code::Unavailable);
+    call(&breaker, Code::Unavailable);
+    // A success, or an application error, resets the run
+    call(&breaker, Code::NotFound);
+    call(&breaker, Code::Unavailable);
+    call(&breaker, Code::DeadlineExceeded);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+    call(&breaker, Code::Internal);
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    let status = breaker.admit().unwrap_err();
+    assert_eq!(status.code(), Code::Unavailable);
+    assert!(is_rejection(&status));
+    assert!(!is_rejection(&tonic::Status::unavailable(
+        "connection refused"
+    )));
+}
+
+#[test]
+fn test_breaker_opens_on_error_rate() {
+    let breaker = CircuitBreaker::new(
+        "rate",
+        BreakerConfig {
+            consecutive_failures: 0,
+            error_rate: 0.5,
+            min_calls: 4,
+            ..config()
+        },
+    );
+    call(&breaker, Code::Unavailable);
+    call(&breaker, Code::Ok);
+    call(&breaker, Code::Unavailable);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+    call(&breaker, Code::Unavailable);
+    assert_eq!(breaker.state(), BreakerState::Open);
+}
+
+#[test]
+fn test_breaker_half_open_trial() {
+    let breaker = CircuitBreaker::new("trial", config());
+    for _ in 0..3 {
+        call(&breaker, Code::Unavailable);
+    }
+    assert!(breaker.admit().is_err());
+
+    std::thread::sleep(Duration::from_millis(60));
+    let trial = breaker.admit().unwrap();
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    // Only one trial call at a time
+    assert!(breaker.admit().is_err());
+    breaker.record(trial, Code::Unavailable);
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    std::thread::sleep(Duration::from_millis(60));
+    let cancelled = breaker.admit().unwrap();
+    breaker.record(cancelled, Code::Cancelled);
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    call(&breaker, Code::Ok);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+}
+
+#[test]
+fn test_breaker_ignores_calls_from_earlier_state() {
+    let breaker = CircuitBreaker::new("stale", config());
+    let early = breaker.admit().unwrap();
+    for _ in 0..3 {
+        call(&breaker, Code::Unavailable);
+    }
+    std::thread::sleep(Duration::from_millis(60));
+    let trial = breaker.admit().unwrap();
+    // Admitted while closed, so it is not the trial call
+    breaker.record(early, Code::Ok);
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    breaker.record(trial, Code::Ok);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+}
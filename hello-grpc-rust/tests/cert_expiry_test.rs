@@ -0,0 +1,60 @@
+use chrono::{Duration, Utc};
+use hello_grpc_rust::common::cert_expiry::{ExpiryMonitor, parse_warn_days};
+use hello_grpc_rust::common::metrics::REGISTRY;
+
+#[test]
+fn test_parse_warn_days() {
+    assert_eq!(parse_warn_days("").unwrap(), [30, 7, 1]);
+    assert_eq!(parse_warn_days("14, 2").unwrap(), [14, 2]);
+    assert!(parse_warn_days("14,soon").is_err());
+}
+
+#[test]
+fn test_thresholds_gauge_and_detail() {
+    let monitor = ExpiryMonitor::new(vec![7, 30, 1]);
+    let now = Utc::now();
+    let not_after = now + Duration::days(10);
+    monitor.record("expiry-test", not_after);
+
+    // Each threshold warns once, the lowest first when several are crossed
+    assert!(monitor.check(now).is_empty());
+    assert_eq!(
+        monitor.check(not_after - Duration::days(6)),
+        [("expiry-test", 7)]
+    );
+    assert!(monitor.check(not_after - Duration::days(5)).is_empty());
+    assert_eq!(
+        monitor.check(not_after - Duration::hours(1)),
+        [("expiry-test", 1)]
+    );
+    assert_eq!(
+        monitor.check(not_after + Duration::seconds(1)),
+        [("expiry-test", 0)]
+    );
+    assert!(monitor.check(not_after + Duration::days(1)).is_empty());
+
+    let text = REGISTRY.render();
+    assert!(text.contains("# TYPE tls_certificate_expiry_seconds gauge"));
+    assert!(text.contains("tls_certificate_expiry_seconds{certificate=\"expiry-test\"} -86400"));
+
+    let detail = monitor.detail(not_after - Duration::days(40));
+    assert_eq!(detail["expiry-test"]["status"], "ok");
+    assert_eq!(detail["expiry-test"]["expires_in_seconds"], 40 * 86_400);
+    assert_eq!(
+        monitor.detail(not_after - Duration::days(20))["expiry-test"]["status"],
+        "expiring"
+    );
+    assert_eq!(
+        monitor.detail(not_after)["expiry-test"]["status"],
+        "expired"
+    );
+
+    // A new certificate starts over: loading it warned about 7 days already
+    let renewed = now + Duration::days(3);
+    monitor.record("expiry-test", renewed);
+    assert!(monitor.check(now).is_empty());
+    assert_eq!(
+        monitor.check(renewed - Duration::hours(12)),
+        [("expiry-test", 1)]
+    );
+}
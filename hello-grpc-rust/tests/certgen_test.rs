@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hello_grpc_rust::common::certgen::{CertOptions, SERVER_NAME, generate};
+use hello_grpc_rust::common::tls::{
+    client_config, crypto_provider, load_certs, load_key, load_roots, not_after,
+};
+use hello_grpc_rust::landing::mtls::PeerIdentity;
+use rustls::ServerConfig;
+use rustls::pki_types::ServerName;
+use rustls::server::WebPkiClientVerifier;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+fn out_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("certgen-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_parse_options() {
+    let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    let options = CertOptions::parse(args(&[
+        "--out",
+        "/tmp/certs",
+        "--san",
+        "a.example,10.0.0.1",
+        "--san=b.example",
+        "--days=7",
+    ]))
+    .unwrap()
+    .unwrap();
+    assert_eq!(options.out, PathBuf::from("/tmp/certs"));
+    assert_eq!(options.extra_sans, ["a.example", "10.0.0.1", "b.example"]);
+    assert_eq!(options.days, 7);
+
+    assert!(CertOptions::parse(args(&["--help"])).unwrap().is_none());
+    assert!(CertOptions::parse(args(&["--days", "0"])).is_err());
+    assert!(CertOptions::parse(args(&["--out"])).is_err());
+    assert!(CertOptions::parse(args(&["--force"])).is_err());
+}
+
+#[tokio::test]
+async fn test_generated_certificates_pass_mutual_tls() {
+    let out = out_dir("mtls");
+    let options = CertOptions {
+        out: out.clone(),
+        extra_sans: vec!["api.example.test".to_string()],
+        days: 30,
+    };
+    generate(&options).unwrap();
+
+    let server_dir = out.join("server_certs");
+    let chain = load_certs(&server_dir.join("full_chain.pem")).unwrap();
+    assert_eq!(chain.len(), 2);
+    let days_left = (not_after(&chain[0]).unwrap() - chrono::Utc::now()).num_days();
+    assert!((29..=30).contains(&days_left), "{} days left", days_left);
+    let server_identity = PeerIdentity::from_der(&chain[0]).unwrap();
+    assert_eq!(
+        server_identity.sans,
+        [
+            SERVER_NAME,
+            "localhost",
+            "127.0.0.1",
+            "::1",
+            "api.example.test"
+        ]
+    );
+
+    let verifier = WebPkiClientVerifier::builder_with_provider(
+        Arc::new(load_roots(&server_dir.join("myssl_root.cer")).unwrap()),
+        crypto_provider(),
+    )
+    .build()
+    .unwrap();
+    let server = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain, load_key(&server_dir.join("private.key")).unwrap())
+        .unwrap();
+
+    let client_dir = out.join("client_certs");
+    let (client, _) = client_config(
+        &client_dir.join("myssl_root.cer"),
+        &client_dir.join("full_chain.pem"),
+        &client_dir.join("private.key"),
+    )
+    .unwrap();
+
+    let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+    let acceptor = TlsAcceptor::from(Arc::new(server));
+    let connector = TlsConnector::from(Arc::new(client));
+    let name = ServerName::try_from("api.example.test").unwrap();
+    let (accepted, connected) = tokio::join!(
+        acceptor.accept(server_io),
+        connector.connect(name, client_io)
+    );
+    connected.unwrap();
+    let accepted = accepted.unwrap();
+
+    let peer = &accepted.get_ref().1.peer_certificates().unwrap()[0];
+    let client_identity = PeerIdentity::from_der(peer).unwrap();
+    assert_eq!(
+        client_identity.subject,
+        "O=HelloGRPC, OU=Client, CN=hello.grpc.client"
+    );
+
+    let _ = std::fs::remove_dir_all(&out);
+}
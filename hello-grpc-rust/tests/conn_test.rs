@@ -0,0 +1,121 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use hello_grpc_rust::common::certgen::{CertOptions, SERVER_NAME, generate};
+use hello_grpc_rust::common::conn::{ClientBuilder, ConnectError};
+use hello_grpc_rust::common::tls::{
+    ClientTlsSettings, Reloadable, TrustRoots, crypto_provider, load_certs, load_key, spki_pin,
+};
+use hello_grpc_rust::landing::tls::incoming;
+use rustls::ServerConfig;
+use rustls::pki_types::ServerName;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+fn certs(name: &str) -> PathBuf {
+    let out = std::env::temp_dir().join(format!("conn-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&out);
+    generate(&CertOptions {
+        out: out.clone(),
+        extra_sans: Vec::new(),
+        days: 30,
+    })
+    .unwrap();
+    out
+}
+
+fn pin(chain: &Path) -> String {
+    spki_pin(&load_certs(chain).unwrap()[0]).unwrap()
+}
+
+fn settings(out: &Path, pins: Vec<String>) -> ClientTlsSettings {
+    ClientTlsSettings {
+        server_name: ServerName::try_from(SERVER_NAME).unwrap(),
+        roots: TrustRoots::Ca(out.join("client_certs/myssl_root.cer")),
+        identity: None,
+        insecure_skip_verify: false,
+        pins,
+    }
+}
+
+/// Serves the health service over TLS with the generated server certificate.
+async fn serve_tls(out: &Path) -> SocketAddr {
+    let dir = out.join("server_certs");
+    let config = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            load_certs(&dir.join("full_chain.pem")).unwrap(),
+            load_key(&dir.join("private.key")).unwrap(),
+        )
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (_, health) = tonic_health::server::health_reporter();
+    tokio::spawn(
+        Server::builder()
+            .add_service(health)
+            .serve_with_incoming(incoming(listener, Reloadable::new(config))),
+    );
+    address
+}
+
+/// Serves the health service in plaintext.
+async fn serve_plaintext() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (_, health) = tonic_health::server::health_reporter();
+    tokio::spawn(
+        Server::builder()
+            .add_service(health)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    address
+}
+
+fn builder(address: SocketAddr, settings: ClientTlsSettings) -> ClientBuilder {
+    ClientBuilder::new()
+        .backend("127.0.0.1", address.port().to_string())
+        .tls(settings)
+}
+
+#[tokio::test]
+async fn test_pin_mismatch_fails_the_build() {
+    let out = certs("pin");
+    let address = serve_tls(&out).await;
+
+    let server_pin = pin(&out.join("server_certs/cert.pem"));
+    let backend = builder(address, settings(&out, vec![server_pin]))
+        .build()
+        .await
+        .unwrap();
+    assert!(backend.is_ready());
+
+    // Pinned to another key: the server certificate is refused
+    let other_pin = pin(&out.join("client_certs/cert.pem"));
+    let result = builder(address, settings(&out, vec![other_pin]))
+        .build()
+        .await;
+    assert!(
+        matches!(result, Err(ConnectError::Connect(_))),
+        "expected a connect error"
+    );
+
+    let _ = std::fs::remove_dir_all(&out);
+}
+
+#[tokio::test]
+async fn test_tls_is_never_downgraded_to_plaintext() {
+    let out = certs("downgrade");
+    let address = serve_plaintext().await;
+
+    let result = builder(address, settings(&out, Vec::new())).build().await;
+    assert!(
+        matches!(result, Err(ConnectError::Connect(_))),
+        "expected a connect error"
+    );
+
+    let _ = std::fs::remove_dir_all(&out);
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use hello_grpc_rust::common::deadline::{backend_budget, grpc_timeout_value, parse_grpc_timeout};
+use tonic::metadata::MetadataMap;
+
+#[test]
+fn test_parse_grpc_timeout() {
+    assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_grpc_timeout("5S"), Some(Duration::from_secs(5)));
+    assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_grpc_timeout("100u"), Some(Duration::from_micros(100)));
+    assert_eq!(parse_grpc_timeout("123456789n"), None);
+    assert_eq!(parse_grpc_timeout("10x"), None);
+    assert_eq!(parse_grpc_timeout("m"), None);
+}
+
+#[test]
+fn test_backend_budget_subtracts_margin() {
+    let margin = Duration::from_millis(100);
+    assert_eq!(backend_budget(&MetadataMap::new(), margin).unwrap(), None);
+
+    let mut metadata = MetadataMap::new();
+    metadata.insert("grpc-timeout", "1S".parse().unwrap());
+    assert_eq!(
+        backend_budget(&metadata, margin).unwrap(),
+        Some(Duration::from_millis(900))
+    );
+
+    // Shorter than the margin: a small budget, or what is left of it
+    metadata.insert("grpc-timeout", "80m".parse().unwrap());
+    assert_eq!(
+        backend_budget(&metadata, margin).unwrap(),
+        Some(Duration::from_millis(10))
+    );
+    metadata.insert("grpc-timeout", "5m".parse().unwrap());
+    assert_eq!(
+        backend_budget(&metadata, margin).unwrap(),
+        Some(Duration::from_millis(5))
+    );
+
+    metadata.insert("grpc-timeout", "0m".parse().unwrap());
+    let status = backend_budget(&metadata, margin).unwrap_err();
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+}
+
+#[test]
+fn test_grpc_timeout_value_round_trips() {
+    for timeout in [
+        Duration::from_millis(250),
+        Duration::from_secs(5),
+        Duration::from_secs(3 * 3600),
+    ] {
+        let value = grpc_timeout_value(timeout);
+        assert!(value.len() <= 9, "{}", value);
+        assert_eq!(parse_grpc_timeout(&value), Some(timeout));
+    }
+}
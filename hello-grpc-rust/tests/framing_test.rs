@@ -0,0 +1,34 @@
+use hello_grpc_rust::common::framing::MessageCounter;
+
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8];
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+    out
+}
+
+#[test]
+fn test_counts_messages_in_one_chunk() {
+    let mut data = frame(b"hello");
+    data.extend(frame(b""));
+    data.extend(frame(b"world!"));
+
+    let mut counter = MessageCounter::default();
+    assert_eq!(counter.feed(&data), 3);
+    assert_eq!(counter.messages(), 3);
+}
+
+#[test]
+fn test_counts_messages_split_across_chunks() {
+    let mut data = frame(&[7u8; 300]);
+    data.extend(frame(b"tail"));
+
+    let mut counter = MessageCounter::default();
+    // Split inside the first length prefix and inside the second payload
+    let (a, rest) = data.split_at(3);
+    let (b, c) = rest.split_at(306);
+    assert_eq!(counter.feed(a), 0);
+    assert_eq!(counter.feed(b), 1);
+    assert_eq!(counter.feed(c), 1);
+    assert_eq!(counter.messages(), 2);
+}
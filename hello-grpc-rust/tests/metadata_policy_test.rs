@@ -0,0 +1,74 @@
+use hello_grpc_rust::common::metadata_policy::{MetadataPolicy, PropagationPolicy};
+use tonic::Status;
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+#[test]
+fn test_exact_and_prefix_patterns() {
+    let policy = MetadataPolicy::new("x-tenant, x-b3-*", "x-b3-flags");
+    assert!(policy.allows("x-tenant"));
+    assert!(policy.allows("X-Tenant"));
+    assert!(policy.allows("x-b3-traceid"));
+    assert!(!policy.allows("x-b3-flags"));
+    assert!(!policy.allows("x-tenant-id"));
+    assert!(!policy.allows("authorization"));
+}
+
+#[test]
+fn test_reserved_keys_are_never_allowed() {
+    let policy = MetadataPolicy::new("*", "");
+    assert!(policy.allows("x-anything"));
+    assert!(!policy.allows("grpc-timeout"));
+    assert!(!policy.allows("content-type"));
+    assert!(!policy.allows("te"));
+}
+
+#[test]
+fn test_copy_keeps_binary_and_repeated_values() {
+    let mut from = MetadataMap::new();
+    from.append("x-tag", "a".parse().unwrap());
+    from.append("x-tag", "b".parse().unwrap());
+    from.insert_bin("x-blob-bin", MetadataValue::from_bytes(b"\x00\x01"));
+    from.insert("secret", "s".parse().unwrap());
+
+    let mut to = MetadataMap::new();
+    MetadataPolicy::new("x-*", "").copy(&from, &mut to);
+    assert_eq!(to.get_all("x-tag").iter().count(), 2);
+    assert_eq!(
+        to.get_bin("x-blob-bin").unwrap().to_bytes().unwrap().as_ref(),
+        b"\x00\x01"
+    );
+    assert!(to.get("secret").is_none());
+}
+
+#[test]
+fn test_filter_status_drops_denied_metadata() {
+    let mut metadata = MetadataMap::new();
+    metadata.insert("x-reason", "quota".parse().unwrap());
+    metadata.insert("x-internal", "host-7".parse().unwrap());
+    let status = Status::with_metadata(tonic::Code::Unavailable, "down", metadata);
+
+    let filtered = MetadataPolicy::new("x-*", "x-internal").filter_status(status);
+    assert_eq!(filtered.code(), tonic::Code::Unavailable);
+    assert_eq!(filtered.message(), "down");
+    assert_eq!(filtered.metadata().get("x-reason").unwrap(), "quota");
+    assert!(filtered.metadata().get("x-internal").is_none());
+}
+
+#[test]
+fn test_split_response_prefers_headers() {
+    let policy = PropagationPolicy {
+        request: MetadataPolicy::default(),
+        headers: MetadataPolicy::new("x-server", ""),
+        trailers: MetadataPolicy::new("x-*", ""),
+    };
+    let mut merged = MetadataMap::new();
+    merged.insert("x-server", "b1".parse().unwrap());
+    merged.insert("x-checksum", "42".parse().unwrap());
+
+    let (mut headers, mut trailers) = (MetadataMap::new(), MetadataMap::new());
+    policy.split_response(&merged, &mut headers, &mut trailers);
+    assert_eq!(headers.get("x-server").unwrap(), "b1");
+    assert!(headers.get("x-checksum").is_none());
+    assert_eq!(trailers.get("x-checksum").unwrap(), "42");
+    assert!(trailers.get("x-server").is_none());
+}
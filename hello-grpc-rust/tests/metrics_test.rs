@@ -0,0 +1,69 @@
+use hello_grpc_rust::common::framing::split_grpc_path;
+use hello_grpc_rust::common::metrics::{Registry, code_name};
+use tonic::Code;
+
+#[test]
+fn test_render_counter_and_gauge() {
+    let registry = Registry::default();
+    let labels = [("grpc_method", "Talk"), ("mode", "standalone")];
+    registry.inc_counter("calls_total", "Calls.", &labels);
+    registry.inc_counter("calls_total", "Calls.", &labels);
+    registry.add_gauge("in_flight", "In flight.", &labels, 1.0);
+    registry.add_gauge("in_flight", "In flight.", &labels, -1.0);
+
+    let text = registry.render();
+    assert!(text.contains("# TYPE calls_total counter"));
+    assert!(text.contains("calls_total{grpc_method=\"Talk\",mode=\"standalone\"} 2"));
+    assert!(text.contains("# TYPE in_flight gauge"));
+    assert!(text.contains("in_flight{grpc_method=\"Talk\",mode=\"standalone\"} 0"));
+}
+
+#[test]
+fn test_render_histogram() {
+    let registry = Registry::default();
+    registry.observe(
+        "latency_seconds",
+        "Latency.",
+        &[("grpc_method", "Talk")],
+        0.02,
+    );
+
+    let text = registry.render();
+    assert!(text.contains("latency_seconds_bucket{grpc_method=\"Talk\",le=\"0.01\"} 0"));
+    assert!(text.contains("latency_seconds_bucket{grpc_method=\"Talk\",le=\"0.025\"} 1"));
+    assert!(text.contains("latency_seconds_bucket{grpc_method=\"Talk\",le=\"+Inf\"} 1"));
+    assert!(text.contains("latency_seconds_count{grpc_method=\"Talk\"} 1"));
+}
+
+#[test]
+fn test_label_values_are_escaped() {
+    let registry = Registry::default();
+    registry.inc_counter("escaped_total", "Escaped.", &[("v", "a\"b\\c")]);
+    assert!(
+        registry
+            .render()
+            .contains("escaped_total{v=\"a\\\"b\\\\c\"} 1")
+    );
+}
+
+#[test]
+fn test_canonical_code_names() {
+    assert_eq!(code_name(Code::Ok), "OK");
+    assert_eq!(code_name(Code::Unavailable), "UNAVAILABLE");
+    assert_eq!(code_name(Code::DeadlineExceeded), "DEADLINE_EXCEEDED");
+}
+
+#[test]
+fn test_split_grpc_path() {
+    assert_eq!(
+        split_grpc_path("/hello.LandingService/TalkBidirectional"),
+        (
+            "hello.LandingService".to_string(),
+            "TalkBidirectional".to_string()
+        )
+    );
+    assert_eq!(
+        split_grpc_path("/favicon.ico"),
+        ("unknown".to_string(), "/favicon.ico".to_string())
+    );
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::PathBuf;
+
+use hello_grpc_rust::landing::mtls::{ClientAuth, PeerIdentity};
+use x509_parser::pem::parse_x509_pem;
+
+fn cert_der(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../docker/tls")
+        .join(name)
+        .join("full_chain.pem");
+    let pem = fs::read(path).unwrap();
+    parse_x509_pem(&pem).unwrap().1.contents
+}
+
+#[test]
+fn test_client_auth_modes() {
+    assert_eq!(ClientAuth::parse("").unwrap(), ClientAuth::Off);
+    assert_eq!(ClientAuth::parse("Optional").unwrap(), ClientAuth::Optional);
+    assert_eq!(ClientAuth::parse("required").unwrap(), ClientAuth::Required);
+    assert!(ClientAuth::parse("sometimes").is_err());
+}
+
+#[test]
+fn test_peer_identity_from_certificate() {
+    let server = PeerIdentity::from_der(&cert_der("server_certs")).unwrap();
+    assert!(server.subject.ends_with("CN=hello.grpc.io"));
+    assert_eq!(
+        server.sans,
+        ["hello.grpc.io", "localhost", "127.0.0.1", "::1"]
+    );
+    assert_eq!(server.principals().last(), Some(server.subject.as_str()));
+
+    let client = PeerIdentity::from_der(&cert_der("client_certs")).unwrap();
+    assert!(client.sans.is_empty());
+    assert_eq!(
+        client.to_string(),
+        "subject=\"C=CN, ST=Beijing, L=Beijing, O=HelloGRPC, OU=Client, CN=hello.grpc.client\""
+    );
+
+    assert!(PeerIdentity::from_der(b"not a certificate").is_err());
+}
@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tonic::Code;
+
+use hello_grpc_rust::otel::{
+    ExporterKind, exporter_kind, init_otel, record_server_call, shutdown_otel, span_with_rpc_attrs,
+};
+
+/// Minimal OTLP/HTTP collector stand-in: records the path of every POST.
+async fn start_collector() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let seen = paths.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                    seen.lock().unwrap().push(req.uri().path().to_string());
+                    async { Ok::<_, hyper::Error>(Response::new(String::new())) }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    (endpoint, paths)
+}
+
+#[test]
+fn test_exporter_kind_parse() {
+    assert_eq!(ExporterKind::parse("stdout"), Some(ExporterKind::Stdout));
+    assert_eq!(
+        ExporterKind::parse("OTLP-GRPC"),
+        Some(ExporterKind::OtlpGrpc)
+    );
+    assert_eq!(
+        ExporterKind::parse("otlp-http"),
+        Some(ExporterKind::OtlpHttp)
+    );
+    assert_eq!(ExporterKind::parse("none"), Some(ExporterKind::None));
+    assert_eq!(ExporterKind::parse("jaeger"), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_otlp_http_exports_spans_and_metrics() {
+    let (endpoint, paths) = start_collector().await;
+    unsafe {
+        std::env::set_var("GRPC_HELLO_OTEL", "Y");
+        std::env::set_var("GRPC_HELLO_OTEL_EXPORTER", "otlp-http");
+        std::env::set_var("OTEL_EXPORTER_OTLP_ENDPOINT", &endpoint);
+    }
+    assert_eq!(
+        exporter_kind("GRPC_HELLO_OTEL_TRACES_EXPORTER"),
+        ExporterKind::OtlpHttp
+    );
+
+    init_otel("otel-export-test");
+    span_with_rpc_attrs("Talk", "LandingService").in_scope(|| {
+        record_server_call(
+            "hello.LandingService",
+            "Talk",
+            Code::Ok,
+            Duration::from_millis(3),
+            1,
+            1,
+        );
+    });
+    // Provider shutdown blocks on the batch/periodic export tasks
+    tokio::task::spawn_blocking(shutdown_otel).await.unwrap();
+
+    let paths = paths.lock().unwrap().clone();
+    assert!(paths.iter().any(|p| p == "/v1/traces"), "{:?}", paths);
+    assert!(paths.iter().any(|p| p == "/v1/metrics"), "{:?}", paths);
+}
@@ -0,0 +1,213 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use hello_grpc_rust::common::conn::ClientBuilder;
+use hello_grpc_rust::common::metrics::REGISTRY;
+use hello_grpc_rust::landing::pool::{ConnectionPool, FAILURE_THRESHOLD, PoolPolicy};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::Status;
+use tonic::transport::Server;
+
+#[test]
+fn test_pool_policy_parse() {
+    assert_eq!(
+        PoolPolicy::parse("round_robin"),
+        Some(PoolPolicy::RoundRobin)
+    );
+    assert_eq!(
+        PoolPolicy::parse(" Least_Request "),
+        Some(PoolPolicy::LeastRequest)
+    );
+    assert_eq!(PoolPolicy::parse("random"), None);
+}
+
+/// Pools share the metrics registry, and the connection labels with it, so
+/// the tests below run one at a time.
+static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+const WAIT: Duration = Duration::from_secs(5);
+
+async fn serve() -> SocketAddr {
+    serve_on(TcpListener::bind("127.0.0.1:0").await.unwrap())
+}
+
+/// Serves the health service, enough for channels to connect.
+fn serve_on(listener: TcpListener) -> SocketAddr {
+    let address = listener.local_addr().unwrap();
+    let (_, health) = tonic_health::server::health_reporter();
+    tokio::spawn(
+        Server::builder()
+            .add_service(health)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    address
+}
+
+/// A pool of `size` connections to `backend`, once all are connected.
+async fn pool(size: usize, policy: PoolPolicy, backend: SocketAddr) -> ConnectionPool {
+    let pool = lazy_pool(size, policy, backend).await;
+    wait_until(|| pool.healthy() == size).await;
+    pool
+}
+
+/// A pool of `size` connections to `backend`, connecting in the background.
+async fn lazy_pool(size: usize, policy: PoolPolicy, backend: SocketAddr) -> ConnectionPool {
+    let builder = ClientBuilder::new().backend("127.0.0.1", backend.port().to_string());
+    ConnectionPool::connect_with(size, policy, builder).await
+}
+
+async fn wait_until(done: impl Fn() -> bool) {
+    let started = Instant::now();
+    while !done() {
+        assert!(started.elapsed() < WAIT, "timed out");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// The value of one series in the shared registry, 0 if absent.
+fn metric(series: &str) -> f64 {
+    REGISTRY
+        .render()
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// The healthy gauges of a pool of `size`, summed.
+fn healthy_gauges(size: usize) -> f64 {
+    (0..size)
+        .map(|i| {
+            metric(&format!(
+                r#"grpc_proxy_pool_connection_healthy{{connection="{}"}}"#,
+                i
+            ))
+        })
+        .sum()
+}
+
+/// The connections `n` checkouts land on, each released before the next.
+fn spread(pool: &ConnectionPool, n: usize) -> Vec<String> {
+    (0..n)
+        .map(|_| pool.checkout().unwrap().connection().to_string())
+        .collect()
+}
+
+fn unavailable() -> Result<(), Status> {
+    Err(Status::unavailable("connection reset"))
+}
+
+#[tokio::test]
+async fn test_round_robin_and_least_request_selection() {
+    let _serial = SERIAL.lock().await;
+    let backend = serve().await;
+
+    // Round robin takes turns whatever is in flight
+    let round_robin = pool(3, PoolPolicy::RoundRobin, backend).await;
+    let held = round_robin.checkout().unwrap();
+    assert_eq!(held.connection(), "0");
+    assert_eq!(spread(&round_robin, 4), ["1", "2", "0", "1"]);
+    drop(held);
+
+    // Least request avoids the connection with a call in flight; ties go
+    // to the round-robin order
+    let least_request = pool(3, PoolPolicy::LeastRequest, backend).await;
+    let held = least_request.checkout().unwrap();
+    assert_eq!(held.connection(), "0");
+    assert_eq!(spread(&least_request, 4), ["1", "2", "1", "1"]);
+    drop(held);
+    assert_eq!(spread(&least_request, 3), ["2", "0", "1"]);
+}
+
+#[tokio::test]
+async fn test_failing_connection_is_evicted_and_rebuilt() {
+    let _serial = SERIAL.lock().await;
+    let backend = serve().await;
+    let pool = pool(2, PoolPolicy::RoundRobin, backend).await;
+    let rebuilds = metric(r#"grpc_proxy_pool_rebuilds_total{connection="0"}"#);
+
+    let lease = pool.checkout().unwrap();
+    assert_eq!(lease.connection(), "0");
+    // Application errors and a success in between do not add up
+    for _ in 1..FAILURE_THRESHOLD {
+        lease.report(&unavailable());
+    }
+    lease.report(&Err::<(), _>(Status::invalid_argument("bad request")));
+    lease.report(&Ok(()));
+    for _ in 1..FAILURE_THRESHOLD {
+        lease.report(&unavailable());
+    }
+    assert_eq!(pool.healthy(), 2);
+
+    // FAILURE_THRESHOLD in a row take it out of rotation
+    lease.report(&unavailable());
+    assert_eq!(pool.healthy(), 1);
+    assert_eq!(spread(&pool, 3), ["1", "1", "1"]);
+    drop(lease);
+
+    // and it is rebuilt in the background
+    wait_until(|| pool.healthy() == 2).await;
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_rebuilds_total{connection="0"}"#),
+        rebuilds + 1.0
+    );
+}
+
+#[tokio::test]
+async fn test_pool_metrics() {
+    let _serial = SERIAL.lock().await;
+
+    // Not in rotation, nor healthy, until the channels connect
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+    let pool = lazy_pool(2, PoolPolicy::RoundRobin, address).await;
+    assert_eq!(pool.healthy(), 0);
+    assert_eq!(healthy_gauges(2), 0.0);
+    serve_on(TcpListener::bind(address).await.unwrap());
+    wait_until(|| pool.healthy() == 2).await;
+    assert_eq!(healthy_gauges(2), 2.0);
+
+    let checkouts = metric(r#"grpc_proxy_pool_checkouts_total{connection="1"}"#);
+    let failures = metric(r#"grpc_proxy_pool_failures_total{connection="1"}"#);
+
+    let first = pool.checkout().unwrap();
+    let second = pool.checkout().unwrap();
+    assert_eq!(second.connection(), "1");
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_outstanding_requests{connection="1"}"#),
+        1.0
+    );
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_checkouts_total{connection="1"}"#),
+        checkouts + 1.0
+    );
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_connection_healthy{connection="1"}"#),
+        1.0
+    );
+
+    for _ in 0..FAILURE_THRESHOLD {
+        second.report(&unavailable());
+    }
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_failures_total{connection="1"}"#),
+        failures + f64::from(FAILURE_THRESHOLD)
+    );
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_connection_healthy{connection="1"}"#),
+        0.0
+    );
+
+    drop(first);
+    drop(second);
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_outstanding_requests{connection="1"}"#),
+        0.0
+    );
+    wait_until(|| pool.healthy() == 2).await;
+    assert_eq!(
+        metric(r#"grpc_proxy_pool_connection_healthy{connection="1"}"#),
+        1.0
+    );
+}
@@ -0,0 +1,69 @@
+use hello_grpc_rust::common::propagation::{
+    B3Propagator, HeaderExtractor, HeaderInjector, propagator,
+};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TraceContextExt;
+
+fn headers(pairs: &[(&'static str, &'static str)]) -> http::HeaderMap {
+    let mut map = http::HeaderMap::new();
+    for (k, v) in pairs {
+        map.insert(*k, v.parse().unwrap());
+    }
+    map
+}
+
+#[test]
+fn test_b3_single_and_multi_header() {
+    let single = headers(&[("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1")]);
+    let cx = B3Propagator.extract(&HeaderExtractor(&single));
+    let span = cx.span().span_context().clone();
+    assert!(span.is_remote() && span.is_sampled());
+    assert_eq!(
+        span.trace_id().to_string(),
+        "80f198ee56343ba864fe8b2a57d3eff7"
+    );
+    assert_eq!(span.span_id().to_string(), "e457b5a2e4d86bd1");
+
+    // 64-bit trace ids are left-padded
+    let multi = headers(&[
+        ("x-b3-traceid", "64fe8b2a57d3eff7"),
+        ("x-b3-spanid", "e457b5a2e4d86bd1"),
+        ("x-b3-sampled", "0"),
+    ]);
+    let cx = B3Propagator.extract(&HeaderExtractor(&multi));
+    let span = cx.span().span_context().clone();
+    assert!(span.is_valid() && !span.is_sampled());
+    assert_eq!(
+        span.trace_id().to_string(),
+        "000000000000000064fe8b2a57d3eff7"
+    );
+
+    let garbage = headers(&[("b3", "not-a-trace")]);
+    let cx = B3Propagator.extract(&HeaderExtractor(&garbage));
+    assert!(!cx.span().span_context().is_valid());
+}
+
+#[test]
+fn test_traceparent_wins_and_round_trips() {
+    let both = headers(&[
+        ("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1"),
+        (
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ),
+    ]);
+    let cx = propagator().extract(&HeaderExtractor(&both));
+    let span = cx.span().span_context().clone();
+    assert_eq!(
+        span.trace_id().to_string(),
+        "4bf92f3577b34da6a3ce929d0e0e4736"
+    );
+
+    let mut out = http::HeaderMap::new();
+    propagator().inject_context(&cx, &mut HeaderInjector(&mut out));
+    assert_eq!(
+        out.get("traceparent").unwrap(),
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    );
+    assert!(out.get("b3").is_none());
+}
@@ -0,0 +1,119 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use hello_grpc_rust::landing::rate_limit::{KeySource, MAX_BUCKETS, RateLimitConfig, RateLimiter};
+
+const TALK: &str = "/hello.LandingService/Talk";
+const BIDI: &str = "/hello.LandingService/TalkBidirectional";
+
+fn peer(last: u8) -> Option<IpAddr> {
+    Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)))
+}
+
+#[test]
+fn test_rate_limit_config_lookup() {
+    let config = RateLimitConfig::parse(
+        r#"{
+            "default": {"rate": 50},
+            "methods": {
+                "Talk": {"rate": 5, "burst": 10, "key": "header:Client"},
+                "/hello.LandingService/TalkBidirectional": {"rate": 1, "key": "global"}
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let talk = config.rule(TALK).unwrap();
+    assert_eq!(talk.burst, 10.0);
+    assert_eq!(talk.key, KeySource::Header("client".to_string()));
+    assert_eq!(config.rule(BIDI).unwrap().key, KeySource::Global);
+    let other = config
+        .rule("/hello.LandingService/TalkOneAnswerMore")
+        .unwrap();
+    assert_eq!((other.rate, other.burst), (50.0, 50.0));
+    assert_eq!(other.key, KeySource::Peer);
+
+    let unlimited = RateLimitConfig::parse(r#"{"methods": {"Talk": {"rate": 1}}}"#).unwrap();
+    assert!(unlimited.rule(BIDI).is_none());
+}
+
+#[test]
+fn test_rate_limit_config_rejects_invalid() {
+    for json in [
+        r#"{"key": "cookie"}"#,
+        r#"{"key": "header:"}"#,
+        r#"{"default": {"rate": 0}}"#,
+        r#"{"methods": {"Talk": {"rate": 5, "burst": 0.5}}}"#,
+        r#"{"methods": {"Talk": {"burst": 5}}}"#,
+    ] {
+        assert!(RateLimitConfig::parse(json).is_err(), "{}", json);
+    }
+}
+
+#[test]
+fn test_rate_limiter_buckets_per_key() {
+    let limiter = RateLimiter::new(
+        RateLimitConfig::parse(r#"{"methods": {"Talk": {"rate": 0.001, "burst": 2}}}"#).unwrap(),
+    );
+    let headers = http::HeaderMap::new();
+    assert!(limiter.check(TALK, peer(1), &headers).is_ok());
+    assert!(limiter.check(TALK, peer(1), &headers).is_ok());
+    let retry_after = limiter.check(TALK, peer(1), &headers).unwrap_err();
+    assert!(retry_after.as_secs() > 900);
+
+    // Other peers and unlimited methods are unaffected
+    assert!(limiter.check(TALK, peer(2), &headers).is_ok());
+    assert!(limiter.check(BIDI, peer(1), &headers).is_ok());
+
+    // Reloading starts the buckets over
+    limiter.reload(
+        RateLimitConfig::parse(r#"{"key": "global", "default": {"rate": 0.001}}"#).unwrap(),
+    );
+    assert!(limiter.check(TALK, peer(1), &headers).is_ok());
+    assert!(limiter.check(TALK, peer(2), &headers).is_err());
+}
+
+#[test]
+fn test_rate_limiter_header_key() {
+    let limiter = RateLimiter::new(
+        RateLimitConfig::parse(r#"{"key": "header:client", "default": {"rate": 0.001}}"#).unwrap(),
+    );
+    let mut rust = http::HeaderMap::new();
+    rust.insert("client", "rust-client".parse().unwrap());
+    let mut go = http::HeaderMap::new();
+    go.insert("client", "go-client".parse().unwrap());
+
+    assert!(limiter.check(TALK, peer(1), &rust).is_ok());
+    assert!(limiter.check(TALK, peer(2), &rust).is_err());
+    assert!(limiter.check(TALK, peer(1), &go).is_ok());
+}
+
+#[test]
+fn test_rate_limiter_caps_the_buckets() {
+    let limiter = RateLimiter::new(
+        RateLimitConfig::parse(
+            r#"{"key": "header:client", "default": {"rate": 0.001, "burst": 2}}"#,
+        )
+        .unwrap(),
+    );
+    let client = |value: usize| {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("client", value.to_string().parse().unwrap());
+        headers
+    };
+    for value in 0..MAX_BUCKETS {
+        assert!(limiter.check(TALK, None, &client(value)).is_ok());
+    }
+    assert_eq!(limiter.bucket_count(), MAX_BUCKETS);
+
+    // New keys past the cap share one bucket and do not grow the map
+    assert!(limiter.check(TALK, None, &client(MAX_BUCKETS)).is_ok());
+    assert!(limiter.check(TALK, None, &client(MAX_BUCKETS + 1)).is_ok());
+    for value in MAX_BUCKETS + 2..MAX_BUCKETS + 500 {
+        assert!(limiter.check(TALK, None, &client(value)).is_err());
+    }
+    assert_eq!(limiter.bucket_count(), MAX_BUCKETS);
+
+    // Keys that have a bucket keep it
+    assert!(limiter.check(TALK, None, &client(0)).is_ok());
+    assert!(limiter.check(TALK, None, &client(0)).is_err());
+}
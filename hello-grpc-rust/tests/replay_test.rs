@@ -0,0 +1,43 @@
+use bytes::Bytes;
+use futures::stream;
+use http_body::Frame;
+use http_body_util::{BodyExt, StreamBody};
+use tonic::Status;
+use tonic::body::Body;
+
+use hello_grpc_rust::common::replay::Replay;
+
+fn source(chunks: &[&'static str]) -> Body {
+    let frames = chunks
+        .iter()
+        .map(|chunk| Ok::<_, Status>(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+        .collect::<Vec<_>>();
+    Body::new(StreamBody::new(stream::iter(frames)))
+}
+
+#[tokio::test]
+async fn test_replay_resends_recorded_frames() {
+    let replay = Replay::new(source(&["one", "two", "three"]), 64);
+
+    let mut first = replay.body();
+    let frame = first.frame().await.unwrap().unwrap();
+    assert_eq!(frame.into_data().unwrap(), "one");
+
+    // A new attempt starts from the beginning; the old one is cut off
+    let second = replay.body();
+    assert!(first.frame().await.is_none());
+    let sent = second.collect().await.unwrap().to_bytes();
+    assert_eq!(sent, "onetwothree");
+    assert!(replay.is_replayable());
+
+    let third = replay.body();
+    assert_eq!(third.collect().await.unwrap().to_bytes(), "onetwothree");
+}
+
+#[tokio::test]
+async fn test_replay_stops_recording_over_limit() {
+    let replay = Replay::new(source(&["one", "two", "three"]), 8);
+    let sent = replay.body().collect().await.unwrap().to_bytes();
+    assert_eq!(sent, "onetwothree");
+    assert!(!replay.is_replayable());
+}
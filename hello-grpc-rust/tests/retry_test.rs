@@ -0,0 +1,410 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use hello_grpc_rust::common::balancer::WaitForReady;
+use hello_grpc_rust::common::retry::{RetryLayer, RetryThrottle};
+use hello_grpc_rust::common::service_config::{RetryThrottling, ServiceConfig};
+use http_body_util::Full;
+use tonic::body::Body;
+use tonic::codegen::StdError;
+use tonic::{Code, Status};
+use tower::{Layer, Service, ServiceExt};
+
+#[test]
+fn test_retry_throttle_token_bucket() {
+    let throttle = RetryThrottle::new(RetryThrottling {
+        max_tokens: 4,
+        token_ratio: 0.5,
+    });
+    assert!(throttle.allows_retry());
+
+    throttle.record_failure();
+    assert!(throttle.allows_retry());
+    // Two tokens left out of four: retries stop at half
+    throttle.record_failure();
+    assert!(!throttle.allows_retry());
+
+    throttle.record_success();
+    assert!(throttle.allows_retry());
+
+    // Never above max_tokens, never below zero
+    for _ in 0..10 {
+        throttle.record_success();
+    }
+    throttle.record_failure();
+    throttle.record_failure();
+    assert!(!throttle.allows_retry());
+    for _ in 0..10 {
+        throttle.record_failure();
+    }
+    for _ in 0..5 {
+        throttle.record_success();
+    }
+    assert!(throttle.allows_retry());
+}
+
+/// What the stub channel answers an attempt with.
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    /// A trailers-only response: the call failed before committing.
+    Fail(Code),
+    /// A trailers-only failure asking to wait `ms` before retrying.
+    Pushback(Code, u64),
+    /// The connection failed.
+    Transport,
+    /// Response headers: the call committed, its status comes later.
+    Headers,
+    Ok,
+}
+
+/// One attempt as the stub channel saw it.
+struct Attempt {
+    at: Instant,
+    previous: Option<String>,
+    wait_for_ready: bool,
+}
+
+/// A channel answering attempts with scripted replies, each after its
+/// delay; the last reply repeats once the script runs out. Responses carry
+/// the attempt's number in `x-attempt`.
+#[derive(Clone)]
+struct Stub {
+    replies: Arc<Mutex<VecDeque<(Duration, Reply)>>>,
+    attempts: Arc<Mutex<Vec<Attempt>>>,
+    /// Attempts dropped before they answered.
+    cancelled: Arc<AtomicUsize>,
+}
+
+/// Counts an attempt as cancelled unless it answered.
+struct Answering {
+    cancelled: Arc<AtomicUsize>,
+    answered: bool,
+}
+
+impl Answering {
+    fn answer(&mut self) {
+        self.answered = true;
+    }
+}
+
+impl Drop for Answering {
+    fn drop(&mut self) {
+        if !self.answered {
+            self.cancelled.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Stub {
+    fn new(replies: &[Reply]) -> Self {
+        let replies: Vec<_> = replies.iter().map(|reply| (0, *reply)).collect();
+        Stub::delayed(&replies)
+    }
+
+    /// Replies given with their delay in milliseconds.
+    fn delayed(replies: &[(u64, Reply)]) -> Self {
+        Stub {
+            replies: Arc::new(Mutex::new(
+                replies
+                    .iter()
+                    .map(|(ms, reply)| (Duration::from_millis(*ms), *reply))
+                    .collect(),
+            )),
+            attempts: Arc::new(Mutex::new(Vec::new())),
+            cancelled: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn cancelled(&self) -> usize {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn attempts(&self) -> usize {
+        self.attempts.lock().unwrap().len()
+    }
+
+    /// Whether each attempt was marked [`WaitForReady`].
+    fn wait_for_ready(&self) -> Vec<bool> {
+        let attempts = self.attempts.lock().unwrap();
+        attempts
+            .iter()
+            .map(|attempt| attempt.wait_for_ready)
+            .collect()
+    }
+
+    /// The time between consecutive attempts.
+    fn gaps(&self) -> Vec<Duration> {
+        let attempts = self.attempts.lock().unwrap();
+        attempts.windows(2).map(|w| w[1].at - w[0].at).collect()
+    }
+}
+
+impl Service<http::Request<Body>> for Stub {
+    type Response = http::Response<Body>;
+    type Error = StdError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.push(Attempt {
+            at: Instant::now(),
+            previous: request
+                .headers()
+                .get("grpc-previous-rpc-attempts")
+                .map(|value| value.to_str().unwrap().to_string()),
+            wait_for_ready: request.extensions().get::<WaitForReady>().is_some(),
+        });
+        let attempt = attempts.len();
+        drop(attempts);
+        let (delay, reply) = {
+            let mut replies = self.replies.lock().unwrap();
+            match replies.len() {
+                1 => replies[0],
+                _ => replies.pop_front().unwrap(),
+            }
+        };
+        let response = http::Response::builder()
+            .header("content-type", "application/grpc")
+            .header("x-attempt", attempt);
+        let result = match reply {
+            Reply::Fail(code) => Ok(response.header("grpc-status", code as i32)),
+            Reply::Pushback(code, ms) => Ok(response
+                .header("grpc-status", code as i32)
+                .header("grpc-retry-pushback-ms", ms)),
+            Reply::Transport => Err(Status::unavailable("connection refused").into()),
+            Reply::Headers => Ok(response),
+            Reply::Ok => Ok(response.header("grpc-status", 0)),
+        };
+        let mut answering = Answering {
+            cancelled: self.cancelled.clone(),
+            answered: false,
+        };
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            answering.answer();
+            result.map(|response| response.body(Body::default()).unwrap())
+        })
+    }
+}
+
+fn retry_config(max_attempts: u32, initial_backoff: &str, max_backoff: &str) -> String {
+    format!(
+        r#"{{"methodConfig": [{{
+            "name": [{{"service": "hello.LandingService"}}],
+            "retryPolicy": {{
+                "maxAttempts": {}, "initialBackoff": "{}", "maxBackoff": "{}",
+                "backoffMultiplier": 2, "retryableStatusCodes": ["UNAVAILABLE"]
+            }}
+        }}]}}"#,
+        max_attempts, initial_backoff, max_backoff
+    )
+}
+
+/// Makes a unary `Talk` call through a [`RetryLayer`] over `stub`.
+async fn call(config: &str, stub: &Stub) -> Result<http::Response<Body>, StdError> {
+    let config = Arc::new(ServiceConfig::parse(config).unwrap());
+    let request = http::Request::builder()
+        .uri("/hello.LandingService/Talk")
+        .body(Body::new(Full::new(Bytes::from_static(b"\0\0\0\0\0"))))
+        .unwrap();
+    RetryLayer::new(config)
+        .layer(stub.clone())
+        .oneshot(request)
+        .await
+}
+
+fn grpc_status(response: &http::Response<Body>) -> Option<&str> {
+    response
+        .headers()
+        .get("grpc-status")
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn test_retries_retryable_codes_until_success() {
+    let config = retry_config(4, "0.01s", "0.01s");
+    let stub = Stub::new(&[Reply::Transport, Reply::Fail(Code::Unavailable), Reply::Ok]);
+    let response = call(&config, &stub).await.unwrap();
+    assert_eq!(grpc_status(&response), Some("0"));
+    assert_eq!(stub.attempts(), 3);
+    let previous: Vec<_> = stub
+        .attempts
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|attempt| attempt.previous.clone())
+        .collect();
+    assert_eq!(previous, [None, Some("1".into()), Some("2".into())]);
+
+    // Codes outside retryableStatusCodes fail at once
+    let stub = Stub::new(&[Reply::Fail(Code::InvalidArgument), Reply::Ok]);
+    let response = call(&config, &stub).await.unwrap();
+    assert_eq!(grpc_status(&response), Some("3"));
+    assert_eq!(stub.attempts(), 1);
+}
+
+#[tokio::test]
+async fn test_stops_at_max_attempts() {
+    let stub = Stub::new(&[Reply::Fail(Code::Unavailable)]);
+    let response = call(&retry_config(3, "0.01s", "0.01s"), &stub)
+        .await
+        .unwrap();
+    assert_eq!(grpc_status(&response), Some("14"));
+    assert_eq!(stub.attempts(), 3);
+
+    // gRPC caps maxAttempts at 5
+    let stub = Stub::new(&[Reply::Fail(Code::Unavailable)]);
+    call(&retry_config(9, "0.001s", "0.001s"), &stub)
+        .await
+        .unwrap();
+    assert_eq!(stub.attempts(), 5);
+
+    // A transport failure on the last attempt comes back as the error
+    let stub = Stub::new(&[Reply::Transport]);
+    let error = call(&retry_config(2, "0.01s", "0.01s"), &stub)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Status>().unwrap().code(),
+        Code::Unavailable
+    );
+    assert_eq!(stub.attempts(), 2);
+}
+
+#[tokio::test]
+async fn test_backoff_is_capped_and_pushback_replaces_it() {
+    // Each delay is a random part of min(initialBackoff * 2^(n-1), maxBackoff)
+    let stub = Stub::new(&[Reply::Fail(Code::Unavailable)]);
+    call(&retry_config(5, "0.02s", "0.05s"), &stub)
+        .await
+        .unwrap();
+    let gaps = stub.gaps();
+    assert_eq!(gaps.len(), 4);
+    for (gap, ceiling) in gaps.iter().zip([20, 40, 50, 50]) {
+        assert!(
+            *gap <= Duration::from_millis(ceiling + 40),
+            "{:?} over the {}ms backoff ceiling",
+            gap,
+            ceiling
+        );
+    }
+
+    // The server's pushback is waited for exactly, whatever the backoff
+    let stub = Stub::new(&[Reply::Pushback(Code::Unavailable, 150), Reply::Ok]);
+    call(&retry_config(3, "0.001s", "0.001s"), &stub)
+        .await
+        .unwrap();
+    assert_eq!(stub.attempts(), 2);
+    assert!(stub.gaps()[0] >= Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn test_committed_calls_are_not_retried() {
+    // Response headers commit the call, whatever its trailers say
+    let stub = Stub::new(&[Reply::Headers, Reply::Ok]);
+    let response = call(&retry_config(4, "0.01s", "0.01s"), &stub)
+        .await
+        .unwrap();
+    assert_eq!(grpc_status(&response), None);
+    assert_eq!(stub.attempts(), 1);
+}
+
+#[tokio::test]
+async fn test_wait_for_ready_marks_every_attempt() {
+    let config = r#"{"methodConfig": [{
+        "name": [{"service": "hello.LandingService"}],
+        "waitForReady": true,
+        "retryPolicy": {
+            "maxAttempts": 2, "initialBackoff": "0.01s", "maxBackoff": "0.01s",
+            "backoffMultiplier": 2, "retryableStatusCodes": ["UNAVAILABLE"]
+        }
+    }]}"#;
+    let stub = Stub::new(&[Reply::Transport, Reply::Ok]);
+    call(config, &stub).await.unwrap();
+    assert_eq!(stub.wait_for_ready(), [true, true]);
+
+    let stub = Stub::new(&[Reply::Ok]);
+    call(&retry_config(2, "0.01s", "0.01s"), &stub)
+        .await
+        .unwrap();
+    assert_eq!(stub.wait_for_ready(), [false]);
+}
+
+fn hedging_config(max_attempts: u32, hedging_delay: &str) -> String {
+    format!(
+        r#"{{"methodConfig": [{{
+            "name": [{{"service": "hello.LandingService"}}],
+            "hedgingPolicy": {{
+                "maxAttempts": {}, "hedgingDelay": "{}",
+                "nonFatalStatusCodes": ["UNAVAILABLE"]
+            }}
+        }}]}}"#,
+        max_attempts, hedging_delay
+    )
+}
+
+fn attempt(response: &http::Response<Body>) -> &str {
+    response.headers()["x-attempt"].to_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_hedges_run_in_parallel_and_the_first_answer_wins() {
+    // Copies go out every 50ms; the third answers first
+    let stub = Stub::delayed(&[(1000, Reply::Ok), (1000, Reply::Ok), (10, Reply::Ok)]);
+    let started = Instant::now();
+    let response = call(&hedging_config(3, "0.05s"), &stub).await.unwrap();
+    assert_eq!(attempt(&response), "3");
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert_eq!(stub.attempts(), 3);
+    for gap in stub.gaps() {
+        assert!(
+            gap >= Duration::from_millis(50),
+            "hedge sent after {:?}",
+            gap
+        );
+    }
+    // The copies still in flight are cancelled
+    assert_eq!(stub.cancelled(), 2);
+
+    // No more copies than maxAttempts, and none once one has answered
+    let stub = Stub::delayed(&[(200, Reply::Ok)]);
+    let response = call(&hedging_config(2, "0.02s"), &stub).await.unwrap();
+    assert_eq!(attempt(&response), "1");
+    assert_eq!(stub.attempts(), 2);
+    assert_eq!(stub.cancelled(), 1);
+}
+
+#[tokio::test]
+async fn test_hedging_honours_non_fatal_codes() {
+    // A non-fatal failure sends the next copy at once instead of after the
+    // hedging delay, and does not end the call
+    let stub = Stub::new(&[Reply::Fail(Code::Unavailable), Reply::Ok]);
+    let started = Instant::now();
+    let response = call(&hedging_config(3, "1s"), &stub).await.unwrap();
+    assert_eq!(grpc_status(&response), Some("0"));
+    assert_eq!(attempt(&response), "2");
+    assert!(started.elapsed() < Duration::from_millis(500));
+
+    // A fatal failure wins like a success and cancels the other copies
+    let stub = Stub::delayed(&[(1000, Reply::Ok), (10, Reply::Fail(Code::InvalidArgument))]);
+    let response = call(&hedging_config(3, "0.02s"), &stub).await.unwrap();
+    assert_eq!(grpc_status(&response), Some("3"));
+    assert_eq!(attempt(&response), "2");
+    assert_eq!(stub.cancelled(), 1);
+
+    // When every copy fails non-fatally the last failure is returned
+    let stub = Stub::new(&[Reply::Fail(Code::Unavailable)]);
+    let response = call(&hedging_config(3, "1s"), &stub).await.unwrap();
+    assert_eq!(grpc_status(&response), Some("14"));
+    assert_eq!(stub.attempts(), 3);
+}
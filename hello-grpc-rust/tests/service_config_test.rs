@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use hello_grpc_rust::common::service_config::{
+    DEFAULT_SERVICE_CONFIG, ServiceConfig, parse_duration,
+};
+use tonic::Code;
+
+#[test]
+fn test_default_config_matches_other_clients() {
+    let config = ServiceConfig::parse(DEFAULT_SERVICE_CONFIG).unwrap();
+    let talk = config.method_config("/hello.LandingService/Talk").unwrap();
+    assert_eq!(talk.wait_for_ready, Some(true));
+    let retry = talk.retry_policy.as_ref().unwrap();
+    assert_eq!(retry.max_attempts, 4);
+    assert_eq!(retry.initial_backoff, Duration::from_millis(100));
+    assert_eq!(retry.max_backoff, Duration::from_secs(1));
+    assert_eq!(retry.retryable_status_codes, vec![Code::Unavailable]);
+    assert!(config.method_config("/other.Service/Call").is_none());
+
+    assert!(config.retry_throttling().is_none());
+
+    let throttled = ServiceConfig::parse(
+        r#"{"retryThrottling": {"maxTokens": 10, "tokenRatio": 0.1}, "methodConfig": []}"#,
+    )
+    .unwrap();
+    let throttling = throttled.retry_throttling().unwrap();
+    assert_eq!(throttling.max_tokens, 10);
+    assert_eq!(throttling.token_ratio, 0.1);
+}
+
+#[test]
+fn test_method_config_lookup_order() {
+    let config = ServiceConfig::parse(
+        r#"{"methodConfig": [
+            {"name": [{}], "timeout": "30s"},
+            {"name": [{"service": "hello.LandingService"}], "timeout": "5s"},
+            {"name": [{"service": "hello.LandingService", "method": "Talk"}],
+             "timeout": "0.5s", "maxRequestMessageBytes": 1024}
+        ]}"#,
+    )
+    .unwrap();
+    let timeout = |path: &str| config.method_config(path).unwrap().timeout;
+    assert_eq!(
+        timeout("/hello.LandingService/Talk"),
+        Some(Duration::from_millis(500))
+    );
+    assert_eq!(
+        timeout("/hello.LandingService/TalkBidirectional"),
+        Some(Duration::from_secs(5))
+    );
+    assert_eq!(
+        timeout("/other.Service/Call"),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(
+        config
+            .method_config("/hello.LandingService/Talk")
+            .unwrap()
+            .max_request_message_bytes,
+        Some(1024)
+    );
+}
+
+#[test]
+fn test_retry_policy_validation_and_backoff() {
+    let config = ServiceConfig::parse(
+        r#"{"methodConfig": [{"name": [{"service": "s"}], "retryPolicy": {
+            "maxAttempts": 9, "initialBackoff": ".1s", "maxBackoff": "0.3s",
+            "backoffMultiplier": 2, "retryableStatusCodes": ["UNAVAILABLE", 4]}}]}"#,
+    )
+    .unwrap();
+    let retry = config
+        .method_config("/s/m")
+        .unwrap()
+        .retry_policy
+        .clone()
+        .unwrap();
+    assert_eq!(retry.max_attempts, 5);
+    assert!(retry.is_retryable(Code::DeadlineExceeded));
+    assert_eq!(retry.backoff_ceiling(1), Duration::from_millis(100));
+    assert_eq!(retry.backoff_ceiling(2), Duration::from_millis(200));
+    assert_eq!(retry.backoff_ceiling(3), Duration::from_millis(300));
+
+    for invalid in [
+        r#"{"methodConfig": [{"name": [{}], "retryPolicy": {"maxAttempts": 1, "initialBackoff": "1s", "maxBackoff": "1s", "backoffMultiplier": 2, "retryableStatusCodes": ["UNAVAILABLE"]}}]}"#,
+        r#"{"methodConfig": [{"name": [{}], "retryPolicy": {"maxAttempts": 3, "initialBackoff": "1s", "maxBackoff": "1s", "backoffMultiplier": 2, "retryableStatusCodes": []}}]}"#,
+        r#"{"methodConfig": [{"name": [{}], "retryPolicy": {"maxAttempts": 3, "initialBackoff": "1s", "maxBackoff": "1s", "backoffMultiplier": 2, "retryableStatusCodes": ["NOPE"]}}]}"#,
+        r#"{"methodConfig": [{"name": [{"method": "Talk"}]}]}"#,
+        r#"{"methodConfig": [{"name": [{}]}, {"name": [{}]}]}"#,
+        r#"{"retryThrottling": {"maxTokens": 0, "tokenRatio": 0.1}}"#,
+        r#"{"retryThrottling": {"maxTokens": 10, "tokenRatio": 0}}"#,
+    ] {
+        assert!(ServiceConfig::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_hedging_policy() {
+    let config = ServiceConfig::parse(
+        r#"{"methodConfig": [{"name": [{"service": "hello.LandingService", "method": "Talk"}],
+            "hedgingPolicy": {"maxAttempts": 3, "hedgingDelay": "0.05s",
+                              "nonFatalStatusCodes": ["UNAVAILABLE"]}}]}"#,
+    )
+    .unwrap();
+    let talk = config.method_config("/hello.LandingService/Talk").unwrap();
+    let hedging = talk.hedging_policy.as_ref().unwrap();
+    assert_eq!(hedging.max_attempts, 3);
+    assert_eq!(hedging.hedging_delay, Duration::from_millis(50));
+    assert!(hedging.is_non_fatal(Code::Unavailable));
+    assert!(!hedging.is_non_fatal(Code::InvalidArgument));
+    assert!(talk.is_retryable(Code::Unavailable));
+
+    let both = r#"{"methodConfig": [{"name": [{}],
+        "hedgingPolicy": {"maxAttempts": 2},
+        "retryPolicy": {"maxAttempts": 2, "initialBackoff": "1s", "maxBackoff": "1s",
+                        "backoffMultiplier": 2, "retryableStatusCodes": ["UNAVAILABLE"]}}]}"#;
+    assert!(ServiceConfig::parse(both).is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("1s"), Ok(Duration::from_secs(1)));
+    assert_eq!(parse_duration(".25s"), Ok(Duration::from_millis(250)));
+    assert!(parse_duration("1").is_err());
+    assert!(parse_duration("-1s").is_err());
+}
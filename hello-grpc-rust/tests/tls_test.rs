@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use hello_grpc_rust::common::certgen::{CertOptions, generate};
+use hello_grpc_rust::common::tls::{
+    ClientTlsSettings, DEFAULT_SERVER_NAME, Reloadable, TrustRoots, client_config, crypto_provider,
+    load_certs, load_key, not_after, parse_pins, spki_pin,
+};
+use rustls::ServerConfig;
+use rustls::pki_types::ServerName;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+fn cert_file(dir: &str, name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../docker/tls")
+        .join(dir)
+        .join(name)
+}
+
+#[test]
+fn test_load_certificates_and_expiry() {
+    let chain = load_certs(&cert_file("server_certs", "full_chain.pem")).unwrap();
+    assert!(!chain.is_empty());
+    let expiry = not_after(&chain[0]).unwrap();
+    assert_eq!(expiry.to_rfc3339(), "2035-11-18T08:13:19+00:00");
+    assert!(load_key(&cert_file("server_certs", "private.key")).is_ok());
+
+    // A key file is not a certificate and vice versa
+    assert!(load_certs(&cert_file("server_certs", "private.key")).is_err());
+    assert!(load_key(&cert_file("server_certs", "full_chain.pem")).is_err());
+    assert!(load_certs(&cert_file("server_certs", "missing.pem")).is_err());
+}
+
+#[test]
+fn test_client_config_rejects_mismatched_key() {
+    let ca = cert_file("client_certs", "myssl_root.cer");
+    let (config, _) = client_config(
+        &ca,
+        &cert_file("client_certs", "full_chain.pem"),
+        &cert_file("client_certs", "private.key"),
+    )
+    .unwrap();
+    assert_eq!(config.alpn_protocols, vec![b"h2".to_vec()]);
+
+    // Half-rotated files: the new chain with the old key
+    assert!(
+        client_config(
+            &ca,
+            &cert_file("client_certs", "full_chain.pem"),
+            &cert_file("server_certs", "private.key"),
+        )
+        .is_err()
+    );
+
+    let reloadable = Reloadable::new(1);
+    let before = reloadable.get();
+    reloadable.set(2);
+    assert_eq!((*before, *reloadable.get()), (1, 2));
+}
+
+async fn handshake(settings: &ClientTlsSettings, base: &Path) -> Result<(), std::io::Error> {
+    let server_dir = base.join("server_certs");
+    let server = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            load_certs(&server_dir.join("full_chain.pem")).unwrap(),
+            load_key(&server_dir.join("private.key")).unwrap(),
+        )
+        .unwrap();
+    let (client, _) = settings.config().unwrap();
+
+    let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+    let acceptor = TlsAcceptor::from(Arc::new(server));
+    let connector = TlsConnector::from(Arc::new(client));
+    let (_, connected) = tokio::join!(
+        acceptor.accept(server_io),
+        connector.connect(settings.server_name.clone(), client_io)
+    );
+    connected.map(|_| ())
+}
+
+#[tokio::test]
+async fn test_client_tls_settings() {
+    let base = std::env::temp_dir().join(format!("tls-settings-{}", std::process::id()));
+    generate(&CertOptions {
+        out: base.clone(),
+        extra_sans: vec!["api.example.test".to_string()],
+        days: 1,
+    })
+    .unwrap();
+    let name = |name: &str| ServerName::try_from(name.to_string()).unwrap();
+    let ca = TrustRoots::Ca(base.join("client_certs/myssl_root.cer"));
+
+    // The default name, an overridden one the certificate also carries, and
+    // no client identity
+    let mut settings = ClientTlsSettings {
+        server_name: name(DEFAULT_SERVER_NAME),
+        roots: ca.clone(),
+        identity: Some((
+            base.join("client_certs/full_chain.pem"),
+            base.join("client_certs/private.key"),
+        )),
+        insecure_skip_verify: false,
+        pins: Vec::new(),
+    };
+    assert!(settings.config().unwrap().1.is_some());
+    handshake(&settings, &base).await.unwrap();
+    settings.server_name = name("api.example.test");
+    settings.identity = None;
+    assert!(settings.config().unwrap().1.is_none());
+    assert_eq!(settings.files(), [base.join("client_certs/myssl_root.cer")]);
+    handshake(&settings, &base).await.unwrap();
+
+    // A name the certificate does not carry, and public roots for a private CA
+    settings.server_name = name("other.example.test");
+    assert!(handshake(&settings, &base).await.is_err());
+    settings.server_name = name(DEFAULT_SERVER_NAME);
+    settings.roots = TrustRoots::Webpki;
+    assert!(settings.files().is_empty());
+    assert!(handshake(&settings, &base).await.is_err());
+
+    // Skipping verification accepts both
+    settings.insecure_skip_verify = true;
+    handshake(&settings, &base).await.unwrap();
+    settings.server_name = name("other.example.test");
+    handshake(&settings, &base).await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&base);
+}
+
+#[tokio::test]
+async fn test_pinned_server_key() {
+    let base = std::env::temp_dir().join(format!("tls-pins-{}", std::process::id()));
+    generate(&CertOptions {
+        out: base.clone(),
+        extra_sans: Vec::new(),
+        days: 1,
+    })
+    .unwrap();
+    let chain = load_certs(&base.join("server_certs/full_chain.pem")).unwrap();
+    let pin = spki_pin(&chain[0]).unwrap();
+    assert!(pin.starts_with("sha256/"));
+    let other = spki_pin(&chain[1]).unwrap();
+
+    let hash = pin.trim_start_matches("sha256/");
+    assert_eq!(
+        parse_pins(&format!("{}, {}", other, hash)).unwrap(),
+        [other.clone(), pin.clone()]
+    );
+    assert!(parse_pins("sha256/bm90IGEgaGFzaA==").is_err());
+    assert!(parse_pins("").unwrap().is_empty());
+
+    // The pin applies on top of the CA check
+    let mut settings = ClientTlsSettings {
+        server_name: ServerName::try_from(DEFAULT_SERVER_NAME).unwrap(),
+        roots: TrustRoots::Ca(base.join("client_certs/myssl_root.cer")),
+        identity: None,
+        insecure_skip_verify: false,
+        pins: vec![other.clone(), pin.clone()],
+    };
+    handshake(&settings, &base).await.unwrap();
+    settings.pins = vec![other];
+    assert!(handshake(&settings, &base).await.is_err());
+    settings.pins = vec![pin];
+    settings.roots = TrustRoots::Webpki;
+    assert!(handshake(&settings, &base).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&base);
+}